@@ -0,0 +1,309 @@
+// Command line parsing
+//
+//   samb_gb [run] <rom> [options]
+//   samb_gb disasm <rom> [bank:addr [count]] [--sym <path>]
+//   samb_gb info <rom>
+//   samb_gb test <rom>
+
+use crate::gb::model::Model;
+use crate::gb::hardware::io::gpu::parse_dmg_palette;
+
+use std::path::PathBuf;
+
+pub const USAGE: &str = "\
+usage: samb_gb [run] <rom> [options]
+       samb_gb disasm <rom> [bank:addr [count]] [--sym <path>]
+       samb_gb info <rom>
+       samb_gb test <rom>
+
+commands:
+  run                     play the rom in a window (the default)
+  disasm                  disassemble the rom, or count instructions from bank:addr
+  info                    print the cartridge header
+  test                    run a test rom headless, exiting 0 if it passed
+
+options:
+  --boot-rom <path>       run a dmg (256 byte) or cgb (2304 byte) boot rom first
+  --model <name>          dmg, mgb, sgb, cgb or agb (picked from the rom otherwise)
+  --scale <n>             open the window n times the size of the lcd
+  --palette <palette>     dmg colors, grey, green, pocket or four hex colors
+                          lightest first, like e0f8d0,88c070,346856,081820
+  --save-dir <dir>        keep battery saves and states in dir instead of next to the rom
+  --frames <n>            run n frames without a window, then exit
+  --screenshot-at-frame <n> <path>
+                          save a png of frame n, running headless until then
+  --screenshot-scale <n>  save screenshots n times the size of the lcd
+  --load-state <path>     start from a save state
+  --record <path>         record the buttons pressed each frame to an input movie
+  --play <path>           play an input movie back, then carry on from the keyboard
+  --audio <on|off>        turn sound on or off, a placeholder until sound is emulated
+  --config <path>         read settings from path instead of the config directory
+  --sym <path>            rgbds symbol file (<rom>.sym is used if there is one)
+  --trace <path>          write the cpu state before every instruction
+  --doctor                make ly always read $90, as gameboy doctor traces expect
+  --log <filter>          turn on diagnostics, like cpu=debug,bus=warn
+  --debug                 start in the command line debugger
+  --gdb <port>            wait for a gdb remote protocol client
+  --memory                open the memory viewer
+  --vram                  open the vram inspector
+  --link-listen <port>    wait for another emulator's link cable
+  --link-connect <port>   connect a link cable to another emulator
+  --printer <dir>         plug in a gameboy printer that saves to dir
+  -h, --help              show this message
+  -V, --version           show the version
+";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Command {
+    Run,
+    Disasm,
+    Info,
+    Test,
+    Help,
+    Version,
+}
+
+// What's plugged into the link port
+#[derive(Clone, Debug)]
+pub enum LinkOption {
+    Listen(u16),
+    Connect(u16),
+    Printer(PathBuf),
+}
+
+pub struct Options {
+    pub command: Command,
+    pub rom: PathBuf,
+
+    // disasm start (bank:addr) and instruction count
+    pub start: Option<String>,
+    pub count: Option<usize>,
+
+    pub boot_rom: Option<PathBuf>,
+    pub model: Option<Model>,
+    pub scale: Option<usize>,
+    pub palette: Option<[u32; 4]>,
+    pub save_dir: Option<PathBuf>,
+    pub frames: Option<usize>,
+    pub screenshot: Option<(usize, PathBuf)>,
+    pub screenshot_scale: Option<usize>,
+    pub load_state: Option<PathBuf>,
+    pub record: Option<PathBuf>,
+    pub play: Option<PathBuf>,
+    pub audio: Option<bool>,
+    pub config: Option<PathBuf>,
+
+    pub symbols: Option<PathBuf>,
+    pub trace: Option<PathBuf>,
+    pub doctor: bool,
+    pub log: Option<String>,
+
+    pub debug: bool,
+    pub gdb: Option<u16>,
+    pub memory: bool,
+    pub vram: bool,
+
+    pub link: Option<LinkOption>,
+}
+
+impl Options {
+
+    fn init() -> Self {
+        Self {
+            command: Command::Run,
+            rom: PathBuf::new(),
+
+            start: None,
+            count: None,
+
+            boot_rom: None,
+            model: None,
+            scale: None,
+            palette: None,
+            save_dir: None,
+            frames: None,
+            screenshot: None,
+            screenshot_scale: None,
+            load_state: None,
+            record: None,
+            play: None,
+            audio: None,
+            config: None,
+
+            symbols: None,
+            trace: None,
+            doctor: false,
+            log: None,
+
+            debug: false,
+            gdb: None,
+            memory: false,
+            vram: false,
+
+            link: None,
+        }
+    }
+
+}
+
+// Parse the arguments after the program name
+pub fn parse(args: &[String]) -> Result<Options, String> {
+    let mut options = Options::init();
+    let mut positional = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with('-') || arg == "-" {
+            positional.push(arg.clone());
+            continue;
+        }
+
+        // --name value or --name=value
+        let (name, inline) = match arg.find('=') {
+            Some(i) if arg.starts_with("--") => (&arg[..i], Some(arg[(i + 1)..].to_string())),
+            _ => (arg.as_str(), None),
+        };
+
+        let mut value = || -> Result<String, String> {
+            match inline.clone() {
+                Some(value) => Ok(value),
+                None => args.next().cloned().ok_or_else(|| format!("{} expects a value", name)),
+            }
+        };
+
+        match name {
+            "-h" | "--help" => options.command = Command::Help,
+            "-V" | "--version" => options.command = Command::Version,
+
+            "--boot-rom" => options.boot_rom = Some(PathBuf::from(value()?)),
+            "--model" => {
+                let name = value()?;
+                options.model = Some(Model::from_name(&name).ok_or_else(|| {
+                    format!("unknown model '{}' (expected dmg, mgb, sgb, cgb or agb)", name)
+                })?);
+            },
+            "--scale" => options.scale = Some(parse_number(name, &value()?, 1)?),
+            "--palette" => options.palette = Some(parse_dmg_palette(&value()?)?),
+            "--save-dir" => options.save_dir = Some(PathBuf::from(value()?)),
+            "--frames" => options.frames = Some(parse_number(name, &value()?, 0)?),
+            "--screenshot-at-frame" => {
+                let frame = parse_number(name, &value()?, 1)?;
+                let path = args.next().ok_or_else(|| format!("{} expects a frame and a path", name))?;
+                options.screenshot = Some((frame, PathBuf::from(path)));
+            },
+            "--screenshot-scale" => options.screenshot_scale = Some(parse_number(name, &value()?, 1)?),
+            "--load-state" => options.load_state = Some(PathBuf::from(value()?)),
+            "--record" => options.record = Some(PathBuf::from(value()?)),
+            "--play" => options.play = Some(PathBuf::from(value()?)),
+            "--audio" => options.audio = Some(parse_switch(name, &value()?)?),
+            "--config" => options.config = Some(PathBuf::from(value()?)),
+
+            "--sym" => options.symbols = Some(PathBuf::from(value()?)),
+            "--trace" => options.trace = Some(PathBuf::from(value()?)),
+            "--doctor" => options.doctor = true,
+            "--log" => options.log = Some(value()?),
+
+            "--debug" => options.debug = true,
+            "--gdb" => options.gdb = Some(parse_port(name, &value()?)?),
+            "--memory" => options.memory = true,
+            "--vram" => options.vram = true,
+
+            "--link-listen" => options.link = Some(LinkOption::Listen(parse_port(name, &value()?)?)),
+            "--link-connect" => options.link = Some(LinkOption::Connect(parse_port(name, &value()?)?)),
+            "--printer" => options.link = Some(LinkOption::Printer(PathBuf::from(value()?))),
+
+            _ => return Err(format!("unknown option {} (see --help)", name)),
+        }
+    }
+
+    if options.command == Command::Help || options.command == Command::Version {
+        return Ok(options);
+    }
+
+    let mut positional = positional.into_iter().peekable();
+
+    let command = match positional.peek().map(|arg| arg.as_str()) {
+        Some("run") => Some(Command::Run),
+        Some("disasm") => Some(Command::Disasm),
+        Some("info") => Some(Command::Info),
+        Some("test") => Some(Command::Test),
+        _ => None,
+    };
+
+    if let Some(command) = command {
+        options.command = command;
+        positional.next();
+    }
+
+    options.rom = match positional.next() {
+        Some(rom) => PathBuf::from(rom),
+        None => return Err(String::from("no rom given (see --help)")),
+    };
+
+    if options.command == Command::Disasm {
+        options.start = positional.next();
+        options.count = match positional.next() {
+            Some(count) => Some(parse_number("instruction count", &count, 1)?),
+            None => None,
+        };
+    }
+
+    if let Some(extra) = positional.next() {
+        return Err(format!("unexpected argument '{}' (see --help)", extra));
+    }
+
+    if let (Some((frame, _)), Some(frames)) = (&options.screenshot, options.frames) {
+        if *frame > frames {
+            return Err(format!("--screenshot-at-frame {} is after the last of --frames {}", frame, frames));
+        }
+    }
+
+    check_movie(&options)?;
+
+    Ok(options)
+}
+
+// Movies only play back the same if nothing but the movie drives the game
+fn check_movie(options: &Options) -> Result<(), String> {
+    if options.record.is_none() && options.play.is_none() {
+        return Ok(());
+    }
+
+    if options.record.is_some() && options.play.is_some() {
+        return Err(String::from("--record and --play can't be used together"));
+    }
+
+    if options.play.is_some() && options.load_state.is_some() {
+        return Err(String::from("--play starts where the movie does, it can't be used with --load-state"));
+    }
+
+    if options.debug || options.gdb.is_some() {
+        return Err(String::from("movies can't be recorded or played in the debugger"));
+    }
+
+    match options.link {
+        Some(LinkOption::Listen(_)) | Some(LinkOption::Connect(_)) => {
+            Err(String::from("movies can't be recorded or played with a link cable to another emulator"))
+        },
+        _ => Ok(()),
+    }
+}
+
+fn parse_number(name: &str, value: &str, min: usize) -> Result<usize, String> {
+    value.parse().ok()
+        .filter(|&n| n >= min)
+        .ok_or_else(|| format!("{} expects a whole number of at least {}, not '{}'", name, min, value))
+}
+
+fn parse_port(name: &str, value: &str) -> Result<u16, String> {
+    value.parse()
+        .map_err(|_| format!("{} expects a port number, not '{}'", name, value))
+}
+
+fn parse_switch(name: &str, value: &str) -> Result<bool, String> {
+    match value {
+        "on" | "true" | "yes" => Ok(true),
+        "off" | "false" | "no" => Ok(false),
+        _ => Err(format!("{} expects on or off, not '{}'", name, value)),
+    }
+}
@@ -1,10 +1,22 @@
+use crate::gb::hardware::cartridge::Cartridge;
 use crate::gb::hardware::memory_bus::MemoryBus;
 use crate::gb::hardware::registers::Registers;
 use crate::gb::opcodes::ops;
 use crate::gb::opcodes::table;
 use crate::gb::opcodes::opcode::OPCode;
+use crate::gb::model::Model;
+use crate::gb::debugger::hooks::{Break, Hooks};
+use crate::gb::log::Target;
+use crate::gb::trace::TraceWriter;
+use crate::gb::savestate::State;
+use crate::gb::hardware::io::gpu::FRAME_CYCLES;
+use crate::gb::png;
 
-use std::fs::File;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use std::io;
+use std::path::Path;
 
 #[derive(Clone)]
 pub struct CPU {
@@ -16,58 +28,86 @@ pub struct CPU {
     pub halted: bool,
 
     pub cycles: usize, 
+
+    // breakpoints and call tracking for the debugger
+    pub hooks: Hooks,
+
+    // writes the state before every instruction when set
+    pub trace: Option<Rc<RefCell<TraceWriter>>>,
 }
 
 impl CPU {
-    pub fn init(cartridge: &mut File) -> Self {
+    pub fn init(rom: Cartridge) -> Self {
+        Self::init_with(rom, None, None)
+    }
+
+    // Emulate a specific model, or pick one from the cartridge if None.
+    // Starts from the boot rom if one is given,
+    // otherwise starts where the boot rom would leave off
+    pub fn init_with(rom: Cartridge, model: Option<Model>, boot_rom: Option<Vec<u8>>) -> Self {
+        let bus = MemoryBus::init(rom, model, boot_rom);
+
+        let reg = if bus.is_boot_rom_mapped() {
+            Registers::init()
+        } else {
+            Registers::init_post_boot(bus.model, bus.rom.is_cgb())
+        };
+
         Self {
-            bus: MemoryBus::init(cartridge),
-            reg: Registers::init(),
+            bus,
+            reg,
 
             interrupts: true,
             stopped: false,
             halted: false,
 
             cycles: 0,
+
+            hooks: Hooks::init(),
+
+            trace: None,
         }
     }
 
-    pub fn step(&mut self) {
+    // runs a single instruction, returning the cycles it took
+    pub fn step(&mut self) -> usize {
         let inter = self.bus.get_interrupts();
-        println!("\t\t{}", self.reg);
+        log_trace!(Target::Cpu, "{}", self.reg);
+
+        let interrupted_pc = self.reg.pc;
 
         if self.interrupts {
             // VBlank
             if(inter & (1 << 0)) != 0 {
-                println!("VBlank Interrupt!");
+                log_debug!(Target::Cpu, "VBlank Interrupt!");
                 self.cycles += ops::jumps::rst_nn(self, 0x40);
                 self.halted = false;
             }
 
             // LCD Stat 
             if(inter & (1 << 1)) != 0 {
-                println!("LCD Stat Interrupt!");
+                log_debug!(Target::Cpu, "LCD Stat Interrupt!");
                 self.cycles += ops::jumps::rst_nn(self, 0x48);
                 self.halted = false;
             }
 
             // Timer
             if(inter & (1 << 2)) != 0 {
-                println!("Timer Interrupt!");
+                log_debug!(Target::Cpu, "Timer Interrupt!");
                 self.cycles += ops::jumps::rst_nn(self, 0x50);
                 self.halted = false;
             }
 
             // Serial
             if(inter & (1 << 3)) != 0 {
-                println!("Serial Interrupt!");
+                log_debug!(Target::Cpu, "Serial Interrupt!");
                 self.cycles += ops::jumps::rst_nn(self, 0x58);
                 self.halted = false;
             }
 
             // Keypad
             if(inter & (1 << 4)) != 0 {
-                println!("Keypad Interrupt!");
+                log_debug!(Target::Cpu, "Keypad Interrupt!");
                 self.cycles += ops::jumps::rst_nn(self, 0x60);
                 self.halted = false;
                 self.stopped = false;
@@ -75,19 +115,78 @@ impl CPU {
             
         }
 
+        if self.reg.pc != interrupted_pc {
+            self.hooks.enter_interrupt(interrupted_pc, &self.reg);
+        }
+
         if self.halted {
-            println!("HALTED");
+            log_trace!(Target::Cpu, "HALTED");
+            self.cycles += 4;
         } else if self.stopped {
-            println!("STOPPED");
+            log_trace!(Target::Cpu, "STOPPED");
+            self.cycles += 4;
+        } else if self.hooks.check_breakpoint(self.reg.pc) {
+            // stop before the instruction runs
         } else {
+            if let Some(trace) = &self.trace {
+                trace.borrow_mut().write_state(&self.reg, &self.bus);
+            }
+
+            let pc = self.reg.pc;
             let byte = self.read_prog_byte(0);
             let op = &table::OP_TABLE[byte as usize];
     
             self.exec(op);
+            self.hooks.after_exec(pc, op, &self.reg);
         }
 
-        self.bus.step(self.cycles);
+        if let Some(hit) = self.bus.watchpoints.take_hit() {
+            self.hooks.set_break(hit);
+        }
+
+        self.cycles += self.bus.take_dma_stall();
+
+        let cycles = self.cycles;
+        self.bus.step(cycles);
         self.cycles = 0;
+
+        cycles
+    }
+
+    // Run for a frame's worth of cycles, returning how many ran at the normal
+    // speed clock (the cpu gets through twice as many in double speed).
+    // Stops early with what was hit if there's a breakpoint or watchpoint
+    pub fn run_frame(&mut self) -> Result<usize, Break> {
+        let mut cycles = 0;
+
+        while cycles < FRAME_CYCLES {
+            cycles += self.run_step()?;
+        }
+
+        Ok(cycles)
+    }
+
+    // Run until the lcd finishes a frame and enters vblank, giving up after
+    // a frame's worth of cycles if the lcd is off. Returns normal speed cycles
+    pub fn run_until_vblank(&mut self) -> Result<usize, Break> {
+        let frame = self.bus.get_frame_count();
+        let mut cycles = 0;
+
+        while self.bus.get_frame_count() == frame && cycles < FRAME_CYCLES {
+            cycles += self.run_step()?;
+        }
+
+        Ok(cycles)
+    }
+
+    fn run_step(&mut self) -> Result<usize, Break> {
+        let step = self.step();
+
+        if let Some(hit) = self.hooks.take_break() {
+            return Err(hit);
+        }
+
+        Ok(if self.bus.double_speed { step / 2 } else { step })
     }
 
     pub fn exec(&mut self, op: &OPCode) {
@@ -100,21 +199,77 @@ impl CPU {
         let cycles = op.exec(self);
 
         if cycles == ops::errors::UNKNOWN_RETURN_CODE {
-            print!("EUI OP Code! {}", op);
+            log_error!(Target::Cpu, "EUI OP Code! {}", op);
             panic!("EUI OP Code! {}", op);
         } else {
             self.cycles += cycles;
-            print!("Ran OP Code! {}", op);
+            log_trace!(Target::Cpu, "Ran OP Code! {}\t\t{}", op, self.reg);
         }
-
-        println!("\t\t{}", self.reg);
     }
 }
 
 impl CPU {
+    pub fn get_model(&self) -> Model {
+        self.bus.model
+    }
+
     pub fn get_rom_name(&self) -> String {
         self.bus.rom.get_name()
     }
+
+    // Everything sent over the serial port since capturing was enabled
+    pub fn get_serial_output(&self) -> String {
+        self.bus.serial.get_capture_string()
+    }
+
+    // Write the screen as it is now to a png, `scale` times bigger. That's the
+    // 160x144 lcd, or 256x224 with the super gameboy border around it
+    pub fn save_screenshot(&self, path: &Path, scale: usize) -> io::Result<()> {
+        let (screen, width, height) = self.bus.get_screen();
+        let scale = scale.max(1);
+
+        png::write_png(path, width * scale, height * scale, &png::upscale(width, height, screen, scale))
+    }
+}
+
+impl CPU {
+
+    // Everything that changes as the game runs, see savestate.rs. Saving
+    // needs &mut only because the same code loads states
+    pub fn save_state(&mut self) -> Vec<u8> {
+        let mut state = State::save();
+        self.sync_state(&mut state);
+        state.finish().expect("saving a state can't fail")
+    }
+
+    // Go back to a saved state, leaving everything as it was if it doesn't load
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let mut cpu = self.clone();
+
+        let mut state = State::load(data);
+        cpu.sync_state(&mut state);
+        state.finish()?;
+
+        // clones start with the link cable unplugged, so it's moved across
+        self.bus.serial.move_device(&mut cpu.bus.serial);
+        *self = cpu;
+        Ok(())
+    }
+
+    fn sync_state(&mut self, state: &mut State) {
+        state.header(self.bus.rom.get_header().global_checksum, self.bus.model as u8);
+
+        self.reg.sync_state(state);
+
+        state.bool(&mut self.interrupts);
+        state.bool(&mut self.stopped);
+        state.bool(&mut self.halted);
+
+        state.usize(&mut self.cycles);
+
+        self.bus.sync_state(state);
+    }
+
 }
 
 impl CPU {
@@ -132,11 +287,12 @@ impl CPU {
 }
 
 impl CPU {
+    // code fetches skip read watchpoints, only data reads trigger them
     pub fn read_prog_byte(&self, delta: u16) -> u8 {
-        self.bus.read_byte(self.reg.pc.wrapping_sub(delta))
+        self.bus.fetch_byte(self.reg.pc.wrapping_sub(delta))
     }
 
     pub fn read_prog_word(&self, delta: u16) -> u16 {
-        self.bus.read_word(self.reg.pc.wrapping_sub(delta))
+        self.bus.fetch_word(self.reg.pc.wrapping_sub(delta))
     }
 }
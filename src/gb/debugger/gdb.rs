@@ -0,0 +1,616 @@
+// GDB remote serial protocol stub, started with --gdb <port>
+// https://sourceware.org/gdb/onlinedocs/gdb/Remote-Protocol.html
+//
+// Registers are af, bc, de, hl, sp and pc, each 16 bits little endian,
+// and are described to the client with a target.xml
+
+use crate::gb::cpu::CPU;
+use crate::gb::debugger::hooks::{Access, Break};
+
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+const TARGET_XML: &str = "<?xml version=\"1.0\"?>\
+<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+<target version=\"1.0\">\
+<feature name=\"org.gnu.gdb.sm83.core\">\
+<reg name=\"af\" bitsize=\"16\" type=\"uint16\" regnum=\"0\"/>\
+<reg name=\"bc\" bitsize=\"16\" type=\"uint16\"/>\
+<reg name=\"de\" bitsize=\"16\" type=\"uint16\"/>\
+<reg name=\"hl\" bitsize=\"16\" type=\"uint16\"/>\
+<reg name=\"sp\" bitsize=\"16\" type=\"data_ptr\"/>\
+<reg name=\"pc\" bitsize=\"16\" type=\"code_ptr\"/>\
+</feature>\
+</target>";
+
+const REGISTER_COUNT: usize = 6;
+
+// largest packet we accept, told to the client in qSupported
+const PACKET_SIZE: usize = 0x1000;
+
+// instructions run between checks for a ctrl-c from the client
+const POLL_INSTRUCTIONS: usize = 0x1000;
+
+// Unix signal numbers gdb expects in stop replies
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+// ctrl-c sent outside of a packet to stop the target
+const INTERRUPT: u8 = 0x03;
+
+pub struct GdbStub {
+    pub cpu: CPU,
+
+    stream: TcpStream,
+
+    // bytes read that haven't been made into a packet yet
+    incoming: Vec<u8>,
+
+    // acks are skipped once the client asks for QStartNoAckMode
+    no_ack: bool,
+}
+
+impl GdbStub {
+
+    // Wait for a client to connect on localhost
+    pub fn listen(cpu: CPU, port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        let (stream, _) = listener.accept()?;
+        stream.set_nodelay(true)?;
+
+        Ok(Self {
+            cpu,
+
+            stream,
+
+            incoming: Vec::new(),
+
+            no_ack: false,
+        })
+    }
+
+    // Serve packets until the client detaches, kills us or hangs up
+    pub fn run(&mut self) -> io::Result<()> {
+        loop {
+            let packet = match self.recv_packet()? {
+                Some(packet) => packet,
+                None => return Ok(()),
+            };
+
+            let reply = match self.handle(&packet) {
+                Some(reply) => reply,
+                None => return Ok(()),
+            };
+
+            self.send_packet(&reply)?;
+
+            if packet == "D" {
+                return Ok(());
+            }
+        }
+    }
+
+}
+
+////////// PACKETS //////////
+
+impl GdbStub {
+
+    // Reply to a packet, None if the session is over
+    fn handle(&mut self, packet: &str) -> Option<String> {
+        let (kind, args) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+
+        let reply = match kind {
+            "?" => format!("S{:02x}", SIGTRAP),
+
+            "g" => {
+                let regs = self.get_registers();
+                regs.iter().map(|&reg| hex_u16(reg)).collect()
+            },
+
+            "G" => {
+                let bytes = match decode_hex(args) {
+                    Some(bytes) if bytes.len() == REGISTER_COUNT * 2 => bytes,
+                    _ => return Some(error(1)),
+                };
+
+                for i in 0..REGISTER_COUNT {
+                    self.set_register(i, (bytes[i * 2] as u16) | ((bytes[i * 2 + 1] as u16) << 8));
+                }
+                "OK".to_string()
+            },
+
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(i) if i < REGISTER_COUNT => hex_u16(self.get_registers()[i]),
+                _ => error(1),
+            },
+
+            "P" => {
+                let parsed = split_pair(args, '=').and_then(|(reg, val)| {
+                    let bytes = decode_hex(val)?;
+                    let val = (*bytes.first()? as u16) | ((*bytes.get(1).unwrap_or(&0) as u16) << 8);
+                    Some((usize::from_str_radix(reg, 16).ok()?, val))
+                });
+
+                match parsed {
+                    Some((i, val)) if i < REGISTER_COUNT => {
+                        self.set_register(i, val);
+                        "OK".to_string()
+                    },
+                    _ => error(1),
+                }
+            },
+
+            "m" => match parse_range(args) {
+                Some((addr, len)) => {
+                    // peeking so the client looking at memory doesn't trip a watchpoint
+                    let bytes: Vec<u8> = (0..len)
+                        .map(|i| self.cpu.bus.peek_byte(addr.wrapping_add(i as u16)))
+                        .collect();
+
+                    encode_hex(&bytes)
+                },
+                None => error(1),
+            },
+
+            "M" => {
+                let parsed = split_pair(args, ':')
+                    .and_then(|(range, data)| Some((parse_range(range)?, decode_hex(data)?)));
+
+                match parsed {
+                    Some(((addr, len), bytes)) if bytes.len() == len => {
+                        for (i, &val) in bytes.iter().enumerate() {
+                            self.cpu.bus.write_byte(addr.wrapping_add(i as u16), val);
+                        }
+
+                        self.cpu.bus.watchpoints.take_hit();
+                        "OK".to_string()
+                    },
+                    _ => error(1),
+                }
+            },
+
+            "Z" | "z" => match self.set_point(kind == "Z", args) {
+                Some(true) => "OK".to_string(),
+                Some(false) => String::new(),
+                None => error(1),
+            },
+
+            "s" => {
+                self.jump_to(args);
+                self.cpu.hooks.resume(self.cpu.reg.pc);
+                self.cpu.step();
+
+                let hit = self.cpu.hooks.take_break();
+                stop_reply(hit, SIGTRAP)
+            },
+
+            "c" => {
+                self.jump_to(args);
+                self.resume()
+            },
+
+            "q" => self.query(args),
+
+            // this packet was acked already, and we never wait for the client's acks
+            "Q" if args == "StartNoAckMode" => {
+                self.no_ack = true;
+                "OK".to_string()
+            },
+
+            // there is only one thread
+            "H" | "T" => "OK".to_string(),
+
+            "D" => "OK".to_string(),
+
+            "k" => return None,
+
+            _ => String::new(),
+        };
+
+        Some(reply)
+    }
+
+    fn query(&self, args: &str) -> String {
+        if args.starts_with("Supported") {
+            return format!("PacketSize={:x};qXfer:features:read+;swbreak+;QStartNoAckMode+", PACKET_SIZE);
+        }
+
+        if let Some(range) = args.strip_prefix("Xfer:features:read:target.xml:") {
+            let (offset, len) = match split_pair(range, ',') {
+                Some((offset, len)) => (
+                    usize::from_str_radix(offset, 16).unwrap_or(0),
+                    usize::from_str_radix(len, 16).unwrap_or(0),
+                ),
+                None => return error(1),
+            };
+
+            let start = offset.min(TARGET_XML.len());
+            let end = start.saturating_add(len).min(TARGET_XML.len());
+            let more = if end < TARGET_XML.len() { 'm' } else { 'l' };
+
+            return format!("{}{}", more, &TARGET_XML[start..end]);
+        }
+
+        match args {
+            "Attached" => "1".to_string(),
+            "C" => "QC1".to_string(),
+            "fThreadInfo" => "m1".to_string(),
+            "sThreadInfo" => "l".to_string(),
+            _ => String::new(),
+        }
+    }
+
+    // Zt,addr,kind adds (or zt removes) a breakpoint or watchpoint, returns
+    // false for types we don't have and None if the packet is malformed
+    fn set_point(&mut self, add: bool, args: &str) -> Option<bool> {
+        let mut parts = args.split(',');
+        let kind = parts.next()?;
+        let addr = u16::from_str_radix(parts.next()?, 16).ok()?;
+        let len = usize::from_str_radix(parts.next()?, 16).ok()?;
+
+        let access = match kind {
+            // software and hardware breakpoints are the same to us
+            "0" | "1" => {
+                if add {
+                    self.cpu.hooks.add_breakpoint(addr);
+                } else {
+                    self.cpu.hooks.remove_breakpoint(addr);
+                }
+                return Some(true);
+            },
+
+            "2" => Access::Write,
+            "3" => Access::Read,
+            "4" => Access::ReadWrite,
+            _ => return Some(false),
+        };
+
+        for i in 0..len.max(1) {
+            let addr = addr.wrapping_add(i as u16);
+
+            if add {
+                self.cpu.bus.watchpoints.add(addr, access);
+            } else {
+                self.cpu.bus.watchpoints.remove(addr);
+            }
+        }
+
+        Some(true)
+    }
+
+}
+
+impl GdbStub {
+
+    // Run until something is hit or the client sends a ctrl-c
+    fn resume(&mut self) -> String {
+        self.cpu.hooks.resume(self.cpu.reg.pc);
+
+        loop {
+            for _ in 0..POLL_INSTRUCTIONS {
+                self.cpu.step();
+
+                if let Some(hit) = self.cpu.hooks.take_break() {
+                    return stop_reply(Some(hit), SIGTRAP);
+                }
+            }
+
+            if self.poll_interrupt() {
+                return stop_reply(None, SIGINT);
+            }
+        }
+    }
+
+    // s and c can give an address to carry on from
+    fn jump_to(&mut self, args: &str) {
+        if let Ok(addr) = u16::from_str_radix(args, 16) {
+            self.cpu.reg.pc = addr;
+        }
+    }
+
+    fn get_registers(&self) -> [u16; REGISTER_COUNT] {
+        let reg = &self.cpu.reg;
+        [reg.get_af(), reg.get_bc(), reg.get_de(), reg.get_hl(), reg.sp, reg.pc]
+    }
+
+    fn set_register(&mut self, i: usize, val: u16) {
+        let reg = &mut self.cpu.reg;
+
+        match i {
+            0 => reg.set_af(val & 0xfff0),
+            1 => reg.set_bc(val),
+            2 => reg.set_de(val),
+            3 => reg.set_hl(val),
+            4 => reg.sp = val,
+            5 => reg.pc = val,
+            _ => {},
+        }
+    }
+
+}
+
+////////// CONNECTION //////////
+
+impl GdbStub {
+
+    // Next packet from the client with its checksum checked and acked,
+    // None once the client hangs up
+    fn recv_packet(&mut self) -> io::Result<Option<String>> {
+        self.stream.set_nonblocking(false)?;
+
+        loop {
+            // anything before the start of a packet is acks or stray ctrl-c
+            match self.incoming.iter().position(|&b| b == b'$') {
+                Some(start) => { self.incoming.drain(..start); },
+                None => self.incoming.clear(),
+            }
+
+            let end = self.incoming.iter().position(|&b| b == b'#');
+            if let Some(end) = end.filter(|&end| self.incoming.len() >= end + 3) {
+                let body = self.incoming[1..end].to_vec();
+                let checksum = std::str::from_utf8(&self.incoming[(end + 1)..(end + 3)])
+                    .ok()
+                    .and_then(|sum| u8::from_str_radix(sum, 16).ok());
+                self.incoming.drain(..(end + 3));
+
+                let valid = checksum == Some(checksum_of(&body));
+                if !self.no_ack {
+                    self.stream.write_all(if valid { b"+" } else { b"-" })?;
+                }
+
+                if valid {
+                    return Ok(Some(String::from_utf8_lossy(&unescape(&body)).into_owned()));
+                }
+                continue;
+            }
+
+            let mut buffer = [0; 256];
+            match self.stream.read(&mut buffer) {
+                Ok(0) => return Ok(None),
+                Ok(n) => self.incoming.extend_from_slice(&buffer[..n]),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn send_packet(&mut self, body: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", body, checksum_of(body.as_bytes()));
+        self.stream.write_all(packet.as_bytes())?;
+        self.stream.flush()
+    }
+
+    // Check for a ctrl-c without waiting, a hang up also stops the target
+    fn poll_interrupt(&mut self) -> bool {
+        if self.stream.set_nonblocking(true).is_err() {
+            return true;
+        }
+
+        let mut buffer = [0; 256];
+        let interrupted = match self.stream.read(&mut buffer) {
+            Ok(0) => true,
+            Ok(n) => {
+                self.incoming.extend_from_slice(&buffer[..n]);
+                buffer[..n].contains(&INTERRUPT)
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => false,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => false,
+            Err(_) => true,
+        };
+
+        self.stream.set_nonblocking(false).is_err() || interrupted
+    }
+
+}
+
+// S or T reply for why the target stopped
+fn stop_reply(hit: Option<Break>, signal: u8) -> String {
+    match hit {
+        Some(Break::Breakpoint(_)) => format!("T{:02x}swbreak:;", SIGTRAP),
+        Some(Break::Read { addr, .. }) => format!("T{:02x}rwatch:{:x};", SIGTRAP, addr),
+        Some(Break::Write { addr, .. }) => format!("T{:02x}watch:{:x};", SIGTRAP, addr),
+        None => format!("S{:02x}", signal),
+    }
+}
+
+fn error(code: u8) -> String {
+    format!("E{:02x}", code)
+}
+
+fn checksum_of(body: &[u8]) -> u8 {
+    body.iter().fold(0u8, |sum, &b| sum.wrapping_add(b))
+}
+
+// } escapes the next byte, xored with 0x20
+fn unescape(body: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(body.len());
+    let mut escaped = false;
+
+    for &b in body {
+        if escaped {
+            out.push(b ^ 0x20);
+            escaped = false;
+        } else if b == b'}' {
+            escaped = true;
+        } else {
+            out.push(b);
+        }
+    }
+
+    out
+}
+
+fn split_pair(text: &str, separator: char) -> Option<(&str, &str)> {
+    let i = text.find(separator)?;
+    Some((&text[..i], &text[(i + 1)..]))
+}
+
+// Address and length of a memory packet, the length is capped so the hex
+// reply fits in a packet, gdb asks again for whatever is left over
+fn parse_range(text: &str) -> Option<(u16, usize)> {
+    let (addr, len) = split_pair(text, ',')?;
+    let len = usize::from_str_radix(len, 16).ok()?.min(PACKET_SIZE / 2);
+    Some((u16::from_str_radix(addr, 16).ok()?, len))
+}
+
+fn hex_u16(val: u16) -> String {
+    encode_hex(&val.to_le_bytes())
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..text.len()).step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..(i + 2))?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gb::testing::make_cpu;
+
+    // nop; jr -3
+    const LOOP: [u8; 3] = [0x00, 0x18, 0xfd];
+
+    // A stub talking to a client over a real connection on localhost
+    fn connected_stub(name: &str) -> (GdbStub, TcpStream) {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+
+        let stub = GdbStub {
+            cpu: make_cpu(name, &LOOP),
+
+            stream,
+
+            incoming: Vec::new(),
+
+            no_ack: false,
+        };
+
+        (stub, client)
+    }
+
+    fn reply(stub: &mut GdbStub, packet: &str) -> String {
+        stub.handle(packet).expect("session ended")
+    }
+
+    #[test]
+    fn checksums_and_escapes() {
+        assert_eq!(checksum_of(b"OK"), 0x9a);
+        assert_eq!(checksum_of(b""), 0x00);
+
+        // 0x03 ^ 0x20 is '#'
+        assert_eq!(unescape(b"a}\x03b"), b"a#b");
+        assert_eq!(unescape(b"}]"), b"}");
+    }
+
+    #[test]
+    fn parses_hex_and_ranges() {
+        assert_eq!(decode_hex("0aFf"), Some(vec![0x0a, 0xff]));
+        assert_eq!(decode_hex(""), Some(vec![]));
+        assert_eq!(decode_hex("abc"), None);
+        assert_eq!(decode_hex("zz"), None);
+
+        assert_eq!(parse_range("c000,4"), Some((0xc000, 4)));
+        assert_eq!(parse_range("c000,ffff"), Some((0xc000, PACKET_SIZE / 2)));
+        assert_eq!(parse_range("c000"), None);
+        assert_eq!(parse_range("10000,1"), None);
+    }
+
+    #[test]
+    fn stop_replies() {
+        assert_eq!(stop_reply(Some(Break::Breakpoint(0x150)), SIGTRAP), "T05swbreak:;");
+        assert_eq!(stop_reply(Some(Break::Read { addr: 0xc000, val: 1 }), SIGTRAP), "T05rwatch:c000;");
+        assert_eq!(stop_reply(Some(Break::Write { addr: 0xff80, val: 1 }), SIGTRAP), "T05watch:ff80;");
+        assert_eq!(stop_reply(None, SIGINT), "S02");
+    }
+
+    #[test]
+    fn sets_and_clears_points() {
+        let (mut stub, _client) = connected_stub("gdb-points");
+
+        assert_eq!(stub.set_point(true, "0,150,1"), Some(true));
+        assert_eq!(stub.cpu.hooks.get_breakpoints(), &[0x150]);
+        assert_eq!(stub.set_point(false, "1,150,1"), Some(true));
+        assert!(stub.cpu.hooks.get_breakpoints().is_empty());
+
+        // watchpoints cover every byte of the range
+        assert_eq!(stub.set_point(true, "4,c000,2"), Some(true));
+        assert_eq!(stub.cpu.bus.watchpoints.list(), &[(0xc000, Access::ReadWrite), (0xc001, Access::ReadWrite)]);
+        assert_eq!(stub.set_point(false, "4,c000,2"), Some(true));
+        assert!(stub.cpu.bus.watchpoints.list().is_empty());
+
+        assert_eq!(stub.set_point(true, "5,150,1"), Some(false));
+        assert_eq!(stub.set_point(true, "0,150"), None);
+        assert_eq!(stub.set_point(true, "0,xyz,1"), None);
+    }
+
+    #[test]
+    fn answers_register_and_memory_packets() {
+        let (mut stub, _client) = connected_stub("gdb-packets");
+
+        // pc is 0x0100 and sent little endian
+        assert_eq!(reply(&mut stub, "p5"), "0001");
+        assert_eq!(reply(&mut stub, "p6"), "E01");
+        assert_eq!(reply(&mut stub, "g").len(), REGISTER_COUNT * 4);
+
+        assert_eq!(reply(&mut stub, "P5=5001"), "OK");
+        assert_eq!(stub.cpu.reg.pc, 0x0150);
+
+        assert_eq!(reply(&mut stub, "m0100,4"), "00c35001");
+        assert_eq!(reply(&mut stub, "Mc000,2:1234"), "OK");
+        assert_eq!(stub.cpu.bus.peek_byte(0xc001), 0x34);
+        assert_eq!(reply(&mut stub, "Mc000,2:12"), "E01");
+
+        assert_eq!(reply(&mut stub, "vMustReplyEmpty"), "");
+        assert!(stub.handle("k").is_none());
+    }
+
+    #[test]
+    fn steps_and_continues_to_breakpoints() {
+        let (mut stub, _client) = connected_stub("gdb-run");
+
+        assert_eq!(reply(&mut stub, "Z0,151,1"), "OK");
+        assert_eq!(reply(&mut stub, "c"), "T05swbreak:;");
+        assert_eq!(stub.cpu.reg.pc, 0x0151);
+
+        // stepping runs the instruction under the breakpoint
+        assert_eq!(reply(&mut stub, "s"), "S05");
+        assert_eq!(stub.cpu.reg.pc, 0x0150);
+
+        // the client writing memory doesn't count as hitting a watchpoint
+        assert_eq!(reply(&mut stub, "Z2,c000,1"), "OK");
+        assert_eq!(reply(&mut stub, "Mc000,1:ff"), "OK");
+        assert_eq!(reply(&mut stub, "c"), "T05swbreak:;");
+    }
+
+    #[test]
+    fn receives_and_acks_packets() {
+        let (mut stub, mut client) = connected_stub("gdb-recv");
+
+        // a bad checksum is nacked and skipped, the next packet is read
+        client.write_all(b"+$m0100,4#00$m0100,4#8e").unwrap();
+        assert_eq!(stub.recv_packet().unwrap(), Some("m0100,4".to_string()));
+
+        let mut acks = [0; 2];
+        client.read_exact(&mut acks).unwrap();
+        assert_eq!(&acks, b"-+");
+
+        stub.send_packet("OK").unwrap();
+        let mut packet = [0; 6];
+        client.read_exact(&mut packet).unwrap();
+        assert_eq!(&packet, b"$OK#9a");
+
+        drop(client);
+        assert_eq!(stub.recv_packet().unwrap(), None);
+    }
+}
@@ -0,0 +1,254 @@
+use crate::gb::hardware::registers::Registers;
+use crate::gb::opcodes::opcode::OPCode;
+
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::fmt;
+
+// instructions remembered for showing what ran before pc
+const HISTORY_SIZE: usize = 16;
+
+// What made the cpu stop
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Break {
+    Breakpoint(u16),
+    Read { addr: u16, val: u8 },
+    Write { addr: u16, val: u8 },
+}
+
+impl fmt::Display for Break {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Break::Breakpoint(addr) => write!(f, "breakpoint at ${:04x}", addr),
+            Break::Read { addr, val } => write!(f, "read ${:02x} from ${:04x}", val, addr),
+            Break::Write { addr, val } => write!(f, "wrote ${:02x} to ${:04x}", val, addr),
+        }
+    }
+}
+
+// Which accesses a watchpoint stops on
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl Access {
+    fn matches(&self, write: bool) -> bool {
+        match self {
+            Access::Read => !write,
+            Access::Write => write,
+            Access::ReadWrite => true,
+        }
+    }
+}
+
+// A call (or interrupt) that hasn't returned yet
+#[derive(Clone, Copy, Debug)]
+pub struct Frame {
+    pub caller: u16,
+    pub target: u16,
+
+    // stack pointer right after the return address was pushed
+    pub sp: u16,
+}
+
+////////// WATCHPOINTS //////////
+// Checked by the memory bus on every cpu read and write
+#[derive(Clone)]
+pub struct Watchpoints {
+    watches: Vec<(u16, Access)>,
+
+    // reads happen through &self, so hits are kept in a cell
+    hit: Cell<Option<Break>>,
+}
+
+impl Watchpoints {
+
+    pub fn init() -> Self {
+        Self {
+            watches: Vec::new(),
+            hit: Cell::new(None),
+        }
+    }
+
+    pub fn add(&mut self, addr: u16, access: Access) {
+        self.remove(addr);
+        self.watches.push((addr, access));
+    }
+
+    pub fn remove(&mut self, addr: u16) -> bool {
+        let len = self.watches.len();
+        self.watches.retain(|&(a, _)| a != addr);
+        self.watches.len() != len
+    }
+
+    pub fn list(&self) -> &[(u16, Access)] {
+        &self.watches
+    }
+
+    pub fn check(&self, addr: u16, val: u8, write: bool) {
+        if self.watches.is_empty() || self.hit.get().is_some() {
+            return;
+        }
+
+        if self.watches.iter().any(|&(a, access)| a == addr && access.matches(write)) {
+            self.hit.set(Some(if write {
+                Break::Write { addr, val }
+            } else {
+                Break::Read { addr, val }
+            }));
+        }
+    }
+
+    pub fn take_hit(&self) -> Option<Break> {
+        self.hit.take()
+    }
+
+}
+
+////////// HOOKS //////////
+// Breakpoints and bookkeeping for the debugger, run from CPU::step
+#[derive(Clone)]
+pub struct Hooks {
+    // bookkeeping is skipped unless a debugger is attached
+    pub enabled: bool,
+
+    breakpoints: Vec<u16>,
+
+    // breakpoint to run past when resuming from it
+    resume: Option<u16>,
+
+    hit: Option<Break>,
+
+    calls: Vec<Frame>,
+    history: VecDeque<u16>,
+}
+
+impl Hooks {
+
+    pub fn init() -> Self {
+        Self {
+            enabled: false,
+
+            breakpoints: Vec::new(),
+
+            resume: None,
+
+            hit: None,
+
+            calls: Vec::new(),
+            history: VecDeque::with_capacity(HISTORY_SIZE),
+        }
+    }
+
+}
+
+impl Hooks {
+
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        if !self.breakpoints.contains(&addr) {
+            self.breakpoints.push(addr);
+        }
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        let len = self.breakpoints.len();
+        self.breakpoints.retain(|&a| a != addr);
+        self.breakpoints.len() != len
+    }
+
+    pub fn get_breakpoints(&self) -> &[u16] {
+        &self.breakpoints
+    }
+
+    // Let the instruction at pc run even if there is a breakpoint on it
+    pub fn resume(&mut self, pc: u16) {
+        self.resume = Some(pc);
+    }
+
+    // Called before running the instruction at pc, true if it should stop there
+    pub fn check_breakpoint(&mut self, pc: u16) -> bool {
+        // resuming only ever covers the next instruction, breakpoint or not
+        let resumed = self.resume.take() == Some(pc);
+        if resumed || self.breakpoints.is_empty() {
+            return false;
+        }
+
+        if self.breakpoints.contains(&pc) {
+            self.hit = Some(Break::Breakpoint(pc));
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn set_break(&mut self, hit: Break) {
+        self.hit = Some(hit);
+    }
+
+    pub fn take_break(&mut self) -> Option<Break> {
+        self.hit.take()
+    }
+
+}
+
+impl Hooks {
+
+    // An interrupt jumped from caller to its handler
+    pub fn enter_interrupt(&mut self, caller: u16, reg: &Registers) {
+        if self.enabled {
+            self.calls.push(Frame { caller, target: reg.pc, sp: reg.sp });
+        }
+    }
+
+    // Called after the instruction at pc ran
+    pub fn after_exec(&mut self, pc: u16, op: &OPCode, reg: &Registers) {
+        if !self.enabled {
+            return;
+        }
+
+        if self.history.len() >= HISTORY_SIZE {
+            self.history.pop_front();
+        }
+        self.history.push_back(pc);
+
+        // anything that returned past a frame's return address has left it
+        while self.calls.last().is_some_and(|frame| frame.sp < reg.sp) {
+            self.calls.pop();
+        }
+
+        let is_call = op.name.starts_with("CALL") || op.name.starts_with("RST");
+        if is_call && reg.pc != pc.wrapping_add(op.size) {
+            self.calls.push(Frame { caller: pc, target: reg.pc, sp: reg.sp });
+        }
+    }
+
+    // Calls that haven't returned, innermost last
+    pub fn get_calls(&self) -> &[Frame] {
+        &self.calls
+    }
+
+    // Addresses of the last instructions run, oldest first
+    pub fn get_history(&self) -> impl Iterator<Item = &u16> {
+        self.history.iter()
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resume_is_used_up_without_breakpoints() {
+        let mut hooks = Hooks::init();
+
+        hooks.resume(0x150);
+        assert!(!hooks.check_breakpoint(0x150));
+
+        hooks.add_breakpoint(0x150);
+        assert!(hooks.check_breakpoint(0x150));
+    }
+}
@@ -0,0 +1,547 @@
+// Interactive command line debugger, started with --debug
+
+pub mod gdb;
+pub mod hooks;
+
+use crate::gb::cpu::CPU;
+use crate::gb::debugger::hooks::{Access, Break};
+use crate::gb::disasm;
+use crate::gb::symbols::Symbols;
+use crate::gb::hardware::io::gpu::FRAME_CYCLES;
+use crate::gb::opcodes::table;
+
+use std::io::{self, BufRead, Write};
+
+const HELP: &str = "\
+Addresses and values are hex ($ or 0x optional), counts are decimal.
+Addresses can also be labels from a symbol file.
+An empty line repeats the last command.
+
+  s, step [n]               run n instructions
+  n, next                   run to the next instruction, stepping over calls
+  c, continue               run until a breakpoint or watchpoint
+  vblank                    run until the next vblank starts
+  cycles <n>                run for n cycles
+  b, break <addr>           stop before running the instruction at addr
+  watch <addr> [r|w|rw]     stop after the cpu reads and/or writes addr
+  d, delete <addr>          remove a breakpoint or watchpoint
+  l, list                   show breakpoints and watchpoints
+  r, regs                   show registers
+  x <addr> [len]            show memory
+  set <reg|addr> <val>      change a register (a, f, .., af, .., sp, pc) or byte
+  bt, backtrace             show calls that haven't returned
+  dis, disasm [addr] [n]    disassemble around pc or from addr
+  h, help                   show this
+  q, quit                   exit";
+
+// instructions shown from pc by disasm
+const DISASM_LINES: usize = 8;
+
+// instructions already run shown before pc
+const DISASM_HISTORY: usize = 3;
+
+// bytes shown by x without a length
+const DUMP_LENGTH: usize = 64;
+
+pub struct Debugger {
+    pub cpu: CPU,
+
+    // names shown in place of addresses
+    pub symbols: Symbols,
+
+    last_command: String,
+}
+
+impl Debugger {
+
+    pub fn init(mut cpu: CPU) -> Self {
+        cpu.hooks.enabled = true;
+
+        Self {
+            cpu,
+
+            symbols: Symbols::hardware(),
+
+            last_command: String::new(),
+        }
+    }
+
+    // Read commands from stdin until quit or end of input
+    pub fn run(&mut self) {
+        println!("Type help for a list of commands");
+        self.show_location();
+
+        let stdin = io::stdin();
+        loop {
+            print!("(gb) ");
+            io::stdout().flush().ok();
+
+            let mut line = String::new();
+            match stdin.lock().read_line(&mut line) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {},
+            }
+
+            let line = match line.trim() {
+                "" => self.last_command.clone(),
+                line => line.to_string(),
+            };
+            self.last_command = line.clone();
+
+            match self.command(&line) {
+                Ok(true) => break,
+                Ok(false) => {},
+                Err(e) => println!("{}", e),
+            }
+        }
+    }
+
+    // Run a line of input, returning true to quit
+    pub fn command(&mut self, line: &str) -> Result<bool, String> {
+        let args: Vec<&str> = line.split_whitespace().collect();
+        let (&name, args) = match args.split_first() {
+            Some(split) => split,
+            None => return Ok(false),
+        };
+
+        match name {
+            "s" | "step" => {
+                let count = parse_count(args.first(), 1)?;
+
+                for _ in 0..count {
+                    if let Some(hit) = self.step_instruction() {
+                        self.report(hit);
+                        break;
+                    }
+                }
+
+                self.show_location();
+            },
+
+            "n" | "next" => {
+                let pc = self.cpu.reg.pc;
+                let op = &table::OP_TABLE[self.cpu.bus.peek_byte(pc) as usize];
+
+                if op.name.starts_with("CALL") || op.name.starts_with("RST") {
+                    let ret = pc.wrapping_add(op.size);
+                    let sp = self.cpu.reg.sp;
+                    self.run_until(None, |cpu| cpu.reg.pc == ret && cpu.reg.sp >= sp);
+                } else if let Some(hit) = self.step_instruction() {
+                    self.report(hit);
+                }
+
+                self.show_location();
+            },
+
+            "c" | "continue" => {
+                self.run_until(None, |_| false);
+                self.show_location();
+            },
+
+            "vblank" => {
+                // give up if the lcd is off and ly never moves
+                let mut last_ly = self.cpu.bus.peek_byte(0xff44);
+                let reached = self.run_until(Some(FRAME_CYCLES * 4), |cpu| {
+                    let ly = cpu.bus.peek_byte(0xff44);
+                    let start = ly == 144 && last_ly != 144;
+                    last_ly = ly;
+                    start
+                });
+
+                if !reached {
+                    println!("No vblank, is the lcd off?");
+                }
+                self.show_location();
+            },
+
+            "cycles" => {
+                if args.is_empty() {
+                    return Err("Expected a number of cycles".to_string());
+                }
+
+                let count = parse_count(args.first(), 0)?;
+                self.run_until(Some(count), |_| false);
+                self.show_location();
+            },
+
+            "b" | "break" => {
+                let addr = self.parse_address(args.first())?;
+                self.cpu.hooks.add_breakpoint(addr);
+                println!("Breakpoint at ${:04x}{}", addr, self.describe(addr));
+            },
+
+            "watch" => {
+                let addr = self.parse_address(args.first())?;
+                let access = match args.get(1).copied() {
+                    Some("r") => Access::Read,
+                    Some("w") | None => Access::Write,
+                    Some("rw") => Access::ReadWrite,
+                    Some(other) => return Err(format!("Unknown access {} [expected r, w or rw]", other)),
+                };
+
+                self.cpu.bus.watchpoints.add(addr, access);
+                println!("Watchpoint on ${:04x}{} ({:?})", addr, self.describe(addr), access);
+            },
+
+            "d" | "delete" => {
+                let addr = self.parse_address(args.first())?;
+                let removed = self.cpu.hooks.remove_breakpoint(addr)
+                    | self.cpu.bus.watchpoints.remove(addr);
+
+                if !removed {
+                    return Err(format!("Nothing set at ${:04x}", addr));
+                }
+            },
+
+            "l" | "list" => {
+                for &addr in self.cpu.hooks.get_breakpoints() {
+                    println!("break  ${:04x}{}", addr, self.describe(addr));
+                }
+
+                for &(addr, access) in self.cpu.bus.watchpoints.list() {
+                    println!("watch  ${:04x}{} ({:?})", addr, self.describe(addr), access);
+                }
+            },
+
+            "r" | "regs" => self.show_registers(),
+
+            "x" => {
+                let addr = self.parse_address(args.first())?;
+                let len = parse_count(args.get(1), DUMP_LENGTH)?;
+                self.dump(addr, len);
+            },
+
+            "set" => {
+                let target = args.first().ok_or("Expected a register or address")?;
+                let val = parse_hex(args.get(1))?;
+                self.set(target, val)?;
+            },
+
+            "bt" | "backtrace" => {
+                println!("#0  ${:04x}{}", self.cpu.reg.pc, self.describe(self.cpu.reg.pc));
+
+                for (i, frame) in self.cpu.hooks.get_calls().iter().rev().enumerate() {
+                    println!(
+                        "#{:<2} ${:04x}{}  called from ${:04x}{}",
+                        i + 1,
+                        frame.target, self.describe(frame.target),
+                        frame.caller, self.describe(frame.caller),
+                    );
+                }
+            },
+
+            "dis" | "disasm" => {
+                match args.first() {
+                    Some(_) => {
+                        let addr = self.parse_address(args.first())?;
+                        let count = parse_count(args.get(1), DISASM_LINES)?;
+                        self.disassemble(addr, count);
+                    },
+
+                    None => {
+                        let history: Vec<u16> = self.cpu.hooks.get_history().copied().collect();
+                        let start = history.len().saturating_sub(DISASM_HISTORY);
+
+                        for &addr in history[start..].iter() {
+                            println!("    {}", self.format_line(addr).0);
+                        }
+                        self.disassemble(self.cpu.reg.pc, DISASM_LINES);
+                    },
+                }
+            },
+
+            "h" | "help" => println!("{}", HELP),
+
+            "q" | "quit" => return Ok(true),
+
+            _ => return Err(format!("Unknown command {}, try help", name)),
+        }
+
+        Ok(false)
+    }
+
+}
+
+impl Debugger {
+
+    // Run one instruction, even if there is a breakpoint on it
+    fn step_instruction(&mut self) -> Option<Break> {
+        self.cpu.hooks.resume(self.cpu.reg.pc);
+        self.cpu.step();
+        self.cpu.hooks.take_break()
+    }
+
+    // Run until `done` returns true, something is hit, or `limit` cycles
+    // pass, returning false if it stopped for any reason other than `done`
+    fn run_until<F: FnMut(&CPU) -> bool>(&mut self, limit: Option<usize>, mut done: F) -> bool {
+        let mut cycles = 0;
+        self.cpu.hooks.resume(self.cpu.reg.pc);
+
+        loop {
+            cycles += self.cpu.step();
+
+            if let Some(hit) = self.cpu.hooks.take_break() {
+                self.report(hit);
+                return false;
+            }
+
+            if done(&self.cpu) {
+                return true;
+            }
+
+            if limit.is_some_and(|limit| cycles >= limit) {
+                return false;
+            }
+        }
+    }
+
+    fn report(&self, hit: Break) {
+        match hit {
+            Break::Breakpoint(addr) => println!("Breakpoint at ${:04x}{}", addr, self.describe(addr)),
+            Break::Read { addr, val } => println!("Read ${:02x} from ${:04x}{}", val, addr, self.describe(addr)),
+            Break::Write { addr, val } => println!("Wrote ${:02x} to ${:04x}{}", val, addr, self.describe(addr)),
+        }
+    }
+
+    fn set(&mut self, target: &str, val: u16) -> Result<(), String> {
+        let reg = &mut self.cpu.reg;
+        let byte = val as u8;
+
+        match target.to_lowercase().as_str() {
+            "a" => reg.a = byte,
+            "f" => reg.f = byte & 0xf0,
+            "b" => reg.b = byte,
+            "c" => reg.c = byte,
+            "d" => reg.d = byte,
+            "e" => reg.e = byte,
+            "h" => reg.h = byte,
+            "l" => reg.l = byte,
+            "af" => reg.set_af(val & 0xfff0),
+            "bc" => reg.set_bc(val),
+            "de" => reg.set_de(val),
+            "hl" => reg.set_hl(val),
+            "sp" => reg.sp = val,
+            "pc" => reg.pc = val,
+
+            _ => {
+                let addr = self.parse_address(Some(&target))?;
+                self.cpu.bus.write_byte(addr, byte);
+
+                // writing from here shouldn't trip a watchpoint
+                self.cpu.bus.watchpoints.take_hit();
+            },
+        }
+
+        Ok(())
+    }
+
+}
+
+impl Debugger {
+
+    fn show_location(&self) {
+        self.show_registers();
+        println!("--> {}", self.format_line(self.cpu.reg.pc).0);
+    }
+
+    // " <Label+offset>" for addresses near a symbol
+    fn describe(&self, addr: u16) -> String {
+        match self.symbols.describe(self.cpu.bus.get_bank_of(addr), addr) {
+            Some(name) => format!(" <{}>", name),
+            None => String::new(),
+        }
+    }
+
+    // Labels are looked up first, since names like `add` are also hex
+    fn parse_address(&self, arg: Option<&&str>) -> Result<u16, String> {
+        match arg.and_then(|name| self.symbols.find(name)) {
+            Some((_, addr)) => Ok(addr),
+            None => parse_hex(arg),
+        }
+    }
+
+    fn show_registers(&self) {
+        let reg = &self.cpu.reg;
+        let flag = |set: bool, name: char| if set { name } else { '-' };
+
+        println!(
+            "AF={:04x} BC={:04x} DE={:04x} HL={:04x} SP={:04x} PC={:04x} [{}{}{}{}]{}{}",
+            reg.get_af(), reg.get_bc(), reg.get_de(), reg.get_hl(), reg.sp, reg.pc,
+            flag(reg.get_z_flag(), 'Z'), flag(reg.get_n_flag(), 'N'),
+            flag(reg.get_h_flag(), 'H'), flag(reg.get_c_flag(), 'C'),
+            if self.cpu.interrupts { " IME" } else { "" },
+            if self.cpu.halted { " HALT" } else { "" },
+        );
+    }
+
+    fn dump(&self, addr: u16, len: usize) {
+        for row in (0..len).step_by(16) {
+            let start = addr.wrapping_add(row as u16);
+            let bytes: Vec<String> = (0..(len - row).min(16))
+                .map(|i| format!("{:02x}", self.cpu.bus.peek_byte(start.wrapping_add(i as u16))))
+                .collect();
+
+            println!("${:04x}: {}", start, bytes.join(" "));
+        }
+    }
+
+    fn disassemble(&self, mut addr: u16, count: usize) {
+        for _ in 0..count {
+            if let Some(name) = self.symbols.get(self.cpu.bus.get_bank_of(addr), addr) {
+                println!("{}:", name);
+            }
+
+            let (line, size) = self.format_line(addr);
+            let marker = if addr == self.cpu.reg.pc { "-->" } else { "   " };
+
+            println!("{} {}", marker, line);
+            addr = addr.wrapping_add(size);
+        }
+    }
+
+    // Instruction at addr with its operands filled in, and its size
+    fn format_line(&self, addr: u16) -> (String, u16) {
+        let bus = &self.cpu.bus;
+        let bank = bus.get_bank_of(addr);
+        let inst = disasm::decode(|a| bus.peek_byte(a), addr, |a| bus.get_bank_of(a), &self.symbols);
+
+        let line = disasm::format_instruction(bank, &inst);
+
+        // where in the code this is, unless the line starts a label
+        let line = match self.symbols.describe(bank, addr) {
+            Some(name) if self.symbols.get(bank, addr).is_none() => format!("{:<32} ; {}", line, name),
+            _ => line,
+        };
+
+        (line, inst.size())
+    }
+
+}
+
+fn parse_hex(arg: Option<&&str>) -> Result<u16, String> {
+    let arg = arg.ok_or("Expected an address or value")?;
+    let digits = arg.trim_start_matches("0x").trim_start_matches('$');
+
+    u16::from_str_radix(digits, 16).map_err(|_| format!("{} isn't a hex number", arg))
+}
+
+fn parse_count(arg: Option<&&str>, default: usize) -> Result<usize, String> {
+    match arg {
+        Some(arg) => arg.parse().map_err(|_| format!("{} isn't a number", arg)),
+        None => Ok(default),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gb::testing::make_cpu;
+
+    // a = 0 then calls a function that calls another, each adding 1 to a
+    const CALLS: [u8; 18] = [
+        0x3e, 0x00,         // 0150: ld a, 0
+        0xcd, 0x5a, 0x01,   // 0152: call 0x015a
+        0xea, 0x00, 0xc0,   // 0155: ld (0xc000), a
+        0x18, 0xfe,         // 0158: jr -2
+        0x3c,               // 015a: inc a
+        0xcd, 0x60, 0x01,   // 015b: call 0x0160
+        0xc9,               // 015e: ret
+        0x00,               // 015f: nop
+        0x3c,               // 0160: inc a
+        0xc9,               // 0161: ret
+    ];
+
+    fn debugger(name: &str, program: &[u8]) -> Debugger {
+        Debugger::init(make_cpu(name, program))
+    }
+
+    fn run_to(debugger: &mut Debugger, addr: &str) {
+        debugger.command(&format!("b {}", addr)).unwrap();
+        debugger.command("c").unwrap();
+        debugger.command(&format!("d {}", addr)).unwrap();
+    }
+
+    #[test]
+    fn next_steps_over_calls() {
+        let mut debugger = debugger("debugger-next", &CALLS);
+        run_to(&mut debugger, "152");
+
+        debugger.command("n").unwrap();
+        assert_eq!(debugger.cpu.reg.pc, 0x155);
+        assert_eq!(debugger.cpu.reg.a, 2);
+        assert!(debugger.cpu.hooks.get_calls().is_empty());
+
+        // anything else is a single step
+        debugger.command("n").unwrap();
+        assert_eq!(debugger.cpu.reg.pc, 0x158);
+    }
+
+    #[test]
+    fn tracks_calls_until_they_return() {
+        let mut debugger = debugger("debugger-calls", &CALLS);
+        run_to(&mut debugger, "160");
+
+        let calls: Vec<(u16, u16)> = debugger.cpu.hooks.get_calls().iter()
+            .map(|frame| (frame.caller, frame.target))
+            .collect();
+        assert_eq!(calls, vec![(0x152, 0x15a), (0x15b, 0x160)]);
+
+        debugger.command("s 2").unwrap();
+        assert_eq!(debugger.cpu.reg.pc, 0x15e);
+        assert_eq!(debugger.cpu.hooks.get_calls().len(), 1);
+
+        debugger.command("s").unwrap();
+        assert_eq!(debugger.cpu.reg.pc, 0x155);
+        assert!(debugger.cpu.hooks.get_calls().is_empty());
+    }
+
+    #[test]
+    fn stops_after_a_watched_write() {
+        let mut debugger = debugger("debugger-watch-write", &CALLS);
+        debugger.command("watch c000 w").unwrap();
+
+        debugger.cpu.hooks.resume(debugger.cpu.reg.pc);
+        let hit = (0..100).find_map(|_| {
+            debugger.cpu.step();
+            debugger.cpu.hooks.take_break()
+        });
+
+        assert_eq!(hit, Some(Break::Write { addr: 0xc000, val: 2 }));
+        assert_eq!(debugger.cpu.reg.pc, 0x158);
+    }
+
+    #[test]
+    fn only_data_reads_hit_read_watchpoints() {
+        let program = [
+            0xfa, 0x00, 0xc0,   // 0150: ld a, (0xc000)
+            0x18, 0xfe,         // 0153: jr -2
+        ];
+        let mut debugger = debugger("debugger-watch-read", &program);
+        debugger.cpu.bus.write_byte(0xc000, 0x42);
+
+        // fetching the instruction itself isn't a read of 0x0151
+        debugger.command("watch 151 r").unwrap();
+        debugger.command("watch c000 r").unwrap();
+
+        let hit = (0..100).find_map(|_| {
+            debugger.cpu.step();
+            debugger.cpu.hooks.take_break()
+        });
+
+        assert_eq!(hit, Some(Break::Read { addr: 0xc000, val: 0x42 }));
+        assert_eq!(debugger.cpu.reg.pc, 0x153);
+    }
+
+    #[test]
+    fn run_frame_stops_at_breakpoints() {
+        let mut cpu = make_cpu("debugger-run-frame", &CALLS);
+        cpu.hooks.add_breakpoint(0x160);
+
+        assert_eq!(cpu.run_frame(), Err(Break::Breakpoint(0x160)));
+        assert_eq!(cpu.reg.pc, 0x160);
+
+        // and runs on once the breakpoint is gone
+        cpu.hooks.remove_breakpoint(0x160);
+        assert!(cpu.run_until_vblank().is_ok());
+    }
+}
@@ -0,0 +1,406 @@
+// Disassembler built on the opcode tables
+
+use crate::gb::opcodes::table::{OP_TABLE, CB_OP_TABLE};
+use crate::gb::symbols::Symbols;
+
+const ROM_BANK_SIZE: usize = 0x4000;
+
+// where the cartridge starts and the interrupt handlers
+const ENTRY_POINTS: [u16; 6] = [0x0100, 0x0040, 0x0048, 0x0050, 0x0058, 0x0060];
+
+// data bytes per DB line
+const DATA_PER_LINE: usize = 8;
+
+// runs of the same byte at least this long are shown with DS
+const FILL_LENGTH: usize = 16;
+
+// Where execution can go after an instruction
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Flow {
+    Next,
+    Jump(u16),
+    Branch(u16),
+    Call(u16),
+    End,
+}
+
+#[derive(Clone, Debug)]
+pub struct Instruction {
+    pub addr: u16,
+    pub bytes: Vec<u8>,
+    pub text: String,
+    pub flow: Flow,
+}
+
+impl Instruction {
+    pub fn size(&self) -> u16 {
+        self.bytes.len() as u16
+    }
+}
+
+// Decode the instruction at addr, where `read` gives the byte at any address
+// and `bank_of` the bank any address is mapped to, for naming addresses
+pub fn decode<F: Fn(u16) -> u8, B: Fn(u16) -> u16>(read: F, addr: u16, bank_of: B, symbols: &Symbols) -> Instruction {
+    let code = read(addr);
+    let op = &OP_TABLE[code as usize];
+
+    if code == 0xcb {
+        let cb = read(addr.wrapping_add(1));
+
+        return Instruction {
+            addr,
+            bytes: vec![code, cb],
+            text: CB_OP_TABLE[cb as usize].name.to_string(),
+            flow: Flow::Next,
+        };
+    }
+
+    if op.size == 0 {
+        return Instruction {
+            addr,
+            bytes: vec![code],
+            text: format!("DB ${:02X}", code),
+            flow: Flow::End,
+        };
+    }
+
+    let bytes: Vec<u8> = (0..op.size).map(|i| read(addr.wrapping_add(i))).collect();
+    let d8 = if bytes.len() > 1 { bytes[1] } else { 0 };
+    let d16 = if bytes.len() > 2 { (d8 as u16) | ((bytes[2] as u16) << 8) } else { 0 };
+
+    let name = |target: u16| name_address(target, bank_of(target), symbols);
+
+    // constants only get a name if one matches exactly
+    let constant = |val: u16| match symbols.get(bank_of(val), val) {
+        Some(name) => name.to_string(),
+        None => format!("${:04X}", val),
+    };
+
+    // relative jumps land after the two byte instruction
+    let jr_target = addr.wrapping_add(2).wrapping_add(d8 as i8 as u16);
+
+    // an opcode has at most one operand, substituted once so that
+    // symbol names with d8 or a8 in them are left alone
+    let operand = ["SP+r8", "d16", "a16", "d8", "a8", "r8"].iter().copied().find(|&token| op.name.contains(token));
+    let text = match operand {
+        Some("r8") if op.name.starts_with("JR") => op.name.replacen("r8", &name(jr_target), 1),
+        Some("SP+r8") => op.name.replacen("SP+r8", &format!("SP{:+}", d8 as i8), 1),
+        Some("d16") => op.name.replacen("d16", &constant(d16), 1),
+        Some("a16") => op.name.replacen("a16", &name(d16), 1),
+        Some("d8") => op.name.replacen("d8", &format!("${:02X}", d8), 1),
+        Some("a8") => op.name.replacen("a8", &name(0xff00 | (d8 as u16)), 1),
+        Some("r8") => op.name.replacen("r8", &format!("{}", d8 as i8), 1),
+        _ => op.name.to_string(),
+    };
+
+    let conditional = op.name.contains(',') || (op.name.starts_with("RET ") && op.name != "RET");
+    let flow = match op.name.split(' ').next().unwrap_or("") {
+        "JP" if op.name == "JP (HL)" => Flow::End,
+        "JP" if conditional => Flow::Branch(d16),
+        "JP" => Flow::Jump(d16),
+        "JR" if conditional => Flow::Branch(jr_target),
+        "JR" => Flow::Jump(jr_target),
+        "CALL" => Flow::Call(d16),
+        "RST" => Flow::Call((code & 0x38) as u16),
+        "RET" if conditional => Flow::Next,
+        "RET" | "RETI" => Flow::End,
+        _ => Flow::Next,
+    };
+
+    Instruction { addr, bytes, text, flow }
+}
+
+// Label+offset if there is a symbol before addr, otherwise the address in hex
+pub fn name_address(addr: u16, bank: u16, symbols: &Symbols) -> String {
+    match symbols.describe(bank, addr) {
+        Some(name) => name,
+        None => format!("${:04X}", addr),
+    }
+}
+
+// BB:AAAA  bytes      text
+pub fn format_instruction(bank: u16, inst: &Instruction) -> String {
+    let bytes: Vec<String> = inst.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+    format!("{:02X}:{:04X}  {:<9} {}", bank, inst.addr, bytes.join(" "), inst.text)
+}
+
+////////// ROM DISASSEMBLY //////////
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mark {
+    Data,
+    Code,
+    Operand,
+}
+
+// Offset into the rom file of a banked address
+fn rom_offset(rom: &[u8], bank: u16, addr: u16) -> Option<usize> {
+    let offset = match (bank, addr) {
+        (0, 0x0000..=0x3fff) => addr as usize,
+        (1..=0xffff, 0x4000..=0x7fff) => (bank as usize) * ROM_BANK_SIZE + (addr as usize) - ROM_BANK_SIZE,
+        _ => return None,
+    };
+
+    if offset < rom.len() { Some(offset) } else { None }
+}
+
+fn read_rom(rom: &[u8], bank: u16, addr: u16) -> u8 {
+    rom_offset(rom, bank, addr).map_or(0xff, |offset| rom[offset])
+}
+
+// Banks addresses are assumed to be in while running code from `bank`,
+// code in bank 0 is assumed to run with bank 1 switched in
+fn listing_bank_of(bank: u16, addr: u16) -> u16 {
+    match addr {
+        0x4000..=0x7fff => bank.max(1),
+        0xd000..=0xdfff => 1,
+        _ => 0,
+    }
+}
+
+// Bank a jump from `bank` to `addr` lands in, if it can be known.
+// `switched` is the bank code in bank 0 was last seen switching in
+fn target_bank(rom: &[u8], bank: u16, addr: u16, switched: Option<u16>) -> Option<u16> {
+    match addr {
+        0x0000..=0x3fff => Some(0),
+
+        // without a mapper the second bank is always there
+        0x4000..=0x7fff if bank == 0 && rom.len() <= 2 * ROM_BANK_SIZE => Some(1),
+        0x4000..=0x7fff if bank == 0 => switched,
+        0x4000..=0x7fff => Some(bank),
+
+        _ => None,
+    }
+}
+
+// Follow jumps and calls from the entry points to find which bytes are code.
+// Labels in the rom are entry points too, which reaches code only called
+// through pointers and banks the bank switch tracking can't follow
+fn find_code(rom: &[u8], symbols: &Symbols) -> Vec<Mark> {
+    let mut marks = vec![Mark::Data; rom.len()];
+    let mut queue: Vec<(u16, u16, Option<u16>)> = ENTRY_POINTS.iter().map(|&addr| (0, addr, None)).collect();
+
+    for (bank, addr) in symbols.locations() {
+        match addr {
+            0x0000..=0x3fff => queue.push((0, addr, None)),
+            0x4000..=0x7fff => queue.push((bank.max(1), addr, None)),
+            _ => {},
+        }
+    }
+
+    while let Some((bank, mut addr, mut switched)) = queue.pop() {
+        // a value loaded into a just before it's written to the bank register
+        let mut a = None;
+
+        loop {
+            let offset = match rom_offset(rom, bank, addr) {
+                Some(offset) if marks[offset] == Mark::Data => offset,
+                _ => break,
+            };
+
+            let inst = decode(|a| read_rom(rom, bank, a), addr, |a| listing_bank_of(bank, a), symbols);
+            let size = inst.size() as usize;
+
+            // stop at instructions that run off the bank or into known code
+            let end = addr.wrapping_add(inst.size() - 1);
+            if rom_offset(rom, bank, end) != Some(offset + size - 1)
+                || marks[offset..(offset + size)].iter().any(|&m| m != Mark::Data) {
+                break;
+            }
+
+            marks[offset] = Mark::Code;
+            for mark in marks[(offset + 1)..(offset + size)].iter_mut() {
+                *mark = Mark::Operand;
+            }
+
+            // ld a, n then ld ($2000-$3fff), a switches in bank n
+            let loaded = a.take();
+            match inst.bytes[..] {
+                [0x3e, n] => a = Some(n),
+                [0xea, _, high] if (0x20..0x40).contains(&high) => switched = loaded.map(|n| (n as u16).max(1)),
+                _ => {},
+            }
+
+            let mut follow = |target: u16| {
+                if let Some(target_bank) = target_bank(rom, bank, target, switched) {
+                    queue.push((target_bank, target, switched));
+                }
+            };
+
+            match inst.flow {
+                Flow::Next => {},
+                Flow::Branch(target) | Flow::Call(target) => follow(target),
+                Flow::Jump(target) => { follow(target); break; },
+                Flow::End => break,
+            }
+
+            addr = addr.wrapping_add(inst.size());
+        }
+    }
+
+    marks
+}
+
+// Disassemble a whole rom, bytes that are never reached from the entry points are shown as data
+pub fn disassemble_rom(rom: &[u8], symbols: &Symbols) -> String {
+    let marks = find_code(rom, symbols);
+    let mut out = String::new();
+
+    let banks = rom.len().div_ceil(ROM_BANK_SIZE);
+    for bank in 0..(banks as u16) {
+        let base: u16 = if bank == 0 { 0x0000 } else { 0x4000 };
+        let start = (bank as usize) * ROM_BANK_SIZE;
+        let end = (start + ROM_BANK_SIZE).min(rom.len());
+
+        out.push_str(&format!("\n; bank {:02X}\n", bank));
+
+        let mut offset = start;
+        while offset < end {
+            let addr = base + (offset - start) as u16;
+
+            if let Some(name) = symbols.get(bank, addr) {
+                out.push_str(&format!("\n{}:\n", name));
+            }
+
+            if marks[offset] == Mark::Code {
+                let inst = decode(|a| read_rom(rom, bank, a), addr, |a| listing_bank_of(bank, a), symbols);
+                out.push_str(&format!("{}\n", format_instruction(bank, &inst)));
+                offset += inst.size() as usize;
+                continue;
+            }
+
+            // data runs until the next instruction or label
+            let mut len = 1;
+            while offset + len < end && marks[offset + len] == Mark::Data
+                && symbols.get(bank, addr + len as u16).is_none() {
+                len += 1;
+            }
+
+            let run = rom[offset..(offset + len)].iter().take_while(|&&b| b == rom[offset]).count();
+            if run >= FILL_LENGTH {
+                out.push_str(&format!("{:02X}:{:04X}  DS {},${:02X}\n", bank, addr, run, rom[offset]));
+                offset += run;
+            } else {
+                let len = len.min(DATA_PER_LINE);
+                let bytes: Vec<String> = rom[offset..(offset + len)].iter().map(|b| format!("${:02X}", b)).collect();
+                out.push_str(&format!("{:02X}:{:04X}  DB {}\n", bank, addr, bytes.join(",")));
+                offset += len;
+            }
+        }
+    }
+
+    out
+}
+
+// Disassemble `count` instructions straight through from addr
+pub fn disassemble_range(rom: &[u8], bank: u16, mut addr: u16, count: usize, symbols: &Symbols) -> String {
+    let mut out = String::new();
+
+    for _ in 0..count {
+        let inst = decode(|a| read_rom(rom, bank, a), addr, |a| listing_bank_of(bank, a), symbols);
+        out.push_str(&format!("{}\n", format_instruction(bank, &inst)));
+        addr = addr.wrapping_add(inst.size());
+    }
+
+    out
+}
+
+// Parse BB:AAAA or AAAA, an address without a bank is in bank 0 or 1
+pub fn parse_bank_address(text: &str) -> Option<(u16, u16)> {
+    let text = text.trim_start_matches('$');
+
+    match text.find(':') {
+        Some(i) => Some((
+            u16::from_str_radix(&text[..i], 16).ok()?,
+            u16::from_str_radix(&text[(i + 1)..], 16).ok()?,
+        )),
+
+        None => {
+            let addr = u16::from_str_radix(text, 16).ok()?;
+            Some((if addr >= 0x4000 { 1 } else { 0 }, addr))
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a rom with four banks and `program` at the entry point
+    fn banked_rom(program: &[u8]) -> Vec<u8> {
+        let mut rom = vec![0xff; 4 * ROM_BANK_SIZE];
+        rom[0x100..(0x100 + program.len())].copy_from_slice(program);
+        rom
+    }
+
+    fn decode_bytes(bytes: &[u8], symbols: &Symbols) -> String {
+        decode(|a| bytes.get(a as usize).copied().unwrap_or(0), 0, |_| 0, symbols).text
+    }
+
+    #[test]
+    fn symbols_with_operand_names() {
+        let mut symbols = Symbols::init();
+        symbols.insert(0, 0xc0a8, "wCard8");
+        symbols.insert(0, 0xc123, "wr8a8d8");
+        symbols.insert(0, 0xff80, "hScr8d8");
+
+        assert_eq!(decode_bytes(&[0xfa, 0xa8, 0xc0], &symbols), "LD A,(wCard8)");
+        assert_eq!(decode_bytes(&[0x21, 0x23, 0xc1], &symbols), "LD HL,wr8a8d8");
+        assert_eq!(decode_bytes(&[0xe0, 0x80], &symbols), "LDH (hScr8d8),A");
+        assert_eq!(decode_bytes(&[0x18, 0xfe], &symbols), "JR $0000");
+    }
+
+    #[test]
+    fn follows_calls_into_switched_bank() {
+        // ld a, 2; ld ($2000), a; call $4000; halt
+        let mut rom = banked_rom(&[0x3e, 0x02, 0xea, 0x00, 0x20, 0xcd, 0x00, 0x40, 0x76]);
+
+        // bank 2: nop; ret
+        rom[2 * ROM_BANK_SIZE..(2 * ROM_BANK_SIZE + 2)].copy_from_slice(&[0x00, 0xc9]);
+
+        let marks = find_code(&rom, &Symbols::init());
+        assert!(marks[2 * ROM_BANK_SIZE] == Mark::Code);
+        assert!(marks[2 * ROM_BANK_SIZE + 1] == Mark::Code);
+
+        // the code after ret in bank 2 isn't reached
+        assert!(marks[2 * ROM_BANK_SIZE + 2] == Mark::Data);
+    }
+
+    #[test]
+    fn unreached_banks_stay_data() {
+        let mut rom = banked_rom(&[0x76]);
+
+        // bank 3: bytes that would decode as ld hl, $1234; inc hl, nothing jumps here
+        rom[3 * ROM_BANK_SIZE..(3 * ROM_BANK_SIZE + 4)].copy_from_slice(&[0x21, 0x34, 0x12, 0x23]);
+
+        let marks = find_code(&rom, &Symbols::init());
+        assert!(marks[ROM_BANK_SIZE..].iter().all(|&m| m == Mark::Data));
+
+        let listing = disassemble_rom(&rom, &Symbols::init());
+        assert!(listing.contains("03:4000  DB $21,$34,$12,$23,"));
+        assert!(!listing.contains("LD HL,$1234"));
+    }
+
+    #[test]
+    fn labels_are_entry_points() {
+        let mut rom = banked_rom(&[0x76]);
+
+        // bank 3: ld hl, $1234; inc hl; ret, only reached through a pointer
+        rom[3 * ROM_BANK_SIZE..(3 * ROM_BANK_SIZE + 5)].copy_from_slice(&[0x21, 0x34, 0x12, 0x23, 0xc9]);
+
+        let mut symbols = Symbols::init();
+        symbols.insert(3, 0x4000, "FarFunction");
+        symbols.insert(0, 0xc000, "wBuffer");
+
+        let marks = find_code(&rom, &symbols);
+        assert!(marks[3 * ROM_BANK_SIZE] == Mark::Code);
+        assert!(marks[3 * ROM_BANK_SIZE + 1] == Mark::Operand);
+        assert!(marks[3 * ROM_BANK_SIZE + 4] == Mark::Code);
+
+        // and what comes after the ret is still data
+        assert!(marks[3 * ROM_BANK_SIZE + 5] == Mark::Data);
+
+        let listing = disassemble_rom(&rom, &symbols);
+        assert!(listing.contains("FarFunction:\n03:4000  21 34 12  LD HL,$1234"));
+    }
+}
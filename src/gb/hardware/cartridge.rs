@@ -1,6 +1,11 @@
+use crate::gb::hardware::header::Header;
+use crate::gb::png::crc32;
+use crate::gb::savestate::State;
+
 use std::fs::File;
-use std::io::Read;
+use std::io::{self, Read};
 
+use std::rc::Rc;
 use std::vec::Vec;
 
 const CARTRIDGE_BANK_NUM: usize = 256;
@@ -15,8 +20,9 @@ pub struct Cartridge {
     // current ROM Bank
     rom_bank : u8, 
 
-    // vector of rom banks due to large memory size
-    rom_banks : Vec<ROMBank>,
+    // vector of rom banks due to large memory size,
+    // shared between clones until one of them patches it
+    rom_banks : Rc<Vec<ROMBank>>,
 
     // RAM Stored on cartridge
     ext_ram : ExtRAM,
@@ -25,23 +31,27 @@ pub struct Cartridge {
 impl Cartridge {
 
     // Create Cartridge
-    pub fn load(cartridge: &mut File) -> Self{
-        let mut o = Self { 
-            rom_bank: 1,
-            rom_banks: vec![[0; CARTRIDGE_BANK_SIZE]; CARTRIDGE_BANK_NUM],
+    pub fn load(cartridge: &mut File) -> io::Result<Self> {
+        let mut rom = Vec::new();
+        cartridge.read_to_end(&mut rom)?;
 
-            ext_ram: vec![0; CARTRIDGE_EXT_RAM_SIZE]
-        };
+        Ok(Self::from_bytes(&rom))
+    }
 
-        for b in o.rom_banks.iter_mut() {
-            let i = cartridge.read(b).expect("Error Reading Rom!");
+    // Anything past the last bank is cut off, short roms are padded with 0
+    pub fn from_bytes(rom: &[u8]) -> Self {
+        let mut rom_banks = vec![[0; CARTRIDGE_BANK_SIZE]; CARTRIDGE_BANK_NUM];
 
-            if i <= 0 {
-                break;
-            }
+        for (b, bytes) in rom_banks.iter_mut().zip(rom.chunks(CARTRIDGE_BANK_SIZE)) {
+            b[..bytes.len()].copy_from_slice(bytes);
         }
 
-        o
+        Self { 
+            rom_bank: 1,
+            rom_banks: Rc::new(rom_banks),
+
+            ext_ram: vec![0; CARTRIDGE_EXT_RAM_SIZE]
+        }
     }
 
 }
@@ -66,6 +76,27 @@ impl Cartridge {
         self.rom_banks[self.rom_bank as usize][idx as usize]
     }
 
+    // Read and Write any bank, writing patches the loaded rom
+    pub fn read_from_bank(&self, bank: u16, idx: u16) -> u8 {
+        self.rom_banks[bank as usize][idx as usize]
+    }
+
+    pub fn write_to_bank(&mut self, bank: u16, idx: u16, val: u8) {
+        Rc::make_mut(&mut self.rom_banks)[bank as usize][idx as usize] = val;
+    }
+
+    // crc32 of the banks the header says the rom has, to tell roms apart
+    pub fn get_checksum(&self) -> u32 {
+        let banks = &self.rom_banks[..(self.get_bank_count() as usize)];
+        crc32(&banks.concat())
+    }
+
+    // Header byte 0x148 gives the rom size as 32kb << n
+    pub fn get_bank_count(&self) -> u16 {
+        let size = self.rom_banks[0][0x0148].min(8);
+        (2u16 << size).min(CARTRIDGE_BANK_NUM as u16)
+    }
+
 }
 
 impl Cartridge {    
@@ -79,6 +110,17 @@ impl Cartridge {
         self.ext_ram[idx as usize] = val;
     }
 
+    // Whole cartridge ram, for battery saves
+    pub fn get_ram(&self) -> &[u8] {
+        &self.ext_ram
+    }
+
+    // Restore a battery save, saves of the wrong size are cut or padded
+    pub fn load_ram(&mut self, ram: &[u8]) {
+        let len = ram.len().min(self.ext_ram.len());
+        self.ext_ram[..len].copy_from_slice(&ram[..len]);
+    }
+
 }
 
 impl Cartridge { 
@@ -90,5 +132,58 @@ impl Cartridge {
                 .expect("Invalid Cartridge Name! [is this a gb rom?]")
         )
     }
+
+    // Everything in the header, bank 0 always has room for it
+    pub fn get_header(&self) -> Header {
+        Header::parse(&self.rom_banks[0]).expect("rom bank is smaller than the header")
+    }
+
+    // Header byte 0x143 is 0x80 for games that support the
+    // gameboy color and 0xc0 for games that require it
+    pub fn is_cgb(&self) -> bool {
+        (self.rom_banks[0][0x0143] & 0x80) != 0
+    }
+
+    // Super Gameboy functions are enabled when header byte 0x146
+    // is 0x03 and the old licensee code (0x14b) is 0x33
+    pub fn is_sgb(&self) -> bool {
+        self.rom_banks[0][0x0146] == 0x03 && self.rom_banks[0][0x014b] == 0x33
+    }
+
+    // Cartridge types (header byte 0x147) with a battery keeping ram alive
+    pub fn has_battery(&self) -> bool {
+        matches!(self.rom_banks[0][0x0147], 0x03 | 0x06 | 0x09 | 0x0d | 0x0f | 0x10 | 0x13 | 0x1b | 0x1e | 0x22 | 0xff)
+    }
     
-}
\ No newline at end of file
+}
+
+impl Cartridge {
+
+    // Save states, the rom itself isn't saved
+    pub fn sync_state(&mut self, state: &mut State) {
+        state.u8(&mut self.rom_bank);
+        state.bytes(&mut self.ext_ram);
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pads_short_roms_and_cuts_off_long_ones() {
+        let mut rom = vec![0x11; CARTRIDGE_BANK_SIZE + 2];
+        rom[CARTRIDGE_BANK_SIZE] = 0x22;
+
+        let cartridge = Cartridge::from_bytes(&rom);
+        assert_eq!(cartridge.read_from_bank(1, 0), 0x22);
+        assert_eq!(cartridge.read_from_bank(1, 1), 0x11);
+        assert_eq!(cartridge.read_from_bank(1, 2), 0x00);
+        assert_eq!(cartridge.read_from_bank(2, 0), 0x00);
+
+        let rom = vec![0x33; (CARTRIDGE_BANK_NUM + 1) * CARTRIDGE_BANK_SIZE];
+        let cartridge = Cartridge::from_bytes(&rom);
+        assert_eq!(cartridge.read_from_bank((CARTRIDGE_BANK_NUM - 1) as u16, 0), 0x33);
+    }
+}
@@ -0,0 +1,133 @@
+// Cartridge header at 0x100-0x14f
+// https://gbdev.io/pandocs/The_Cartridge_Header.html
+
+const HEADER_END: usize = 0x150;
+
+pub struct Header {
+    pub title: String,
+
+    pub cgb_flag: u8,
+    pub sgb_flag: u8,
+
+    pub cartridge_type: u8,
+    pub rom_size: u8,
+    pub ram_size: u8,
+
+    pub destination: u8,
+    pub old_licensee: u8,
+    pub new_licensee: String,
+    pub version: u8,
+
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+}
+
+impl Header {
+
+    pub fn parse(rom: &[u8]) -> Result<Self, String> {
+        if rom.len() < HEADER_END {
+            return Err(format!("{} bytes is too small for a gameboy rom", rom.len()));
+        }
+
+        // titles are padded with zeros, and newer carts reuse the end for other things
+        let title: String = rom[0x134..0x144].iter()
+            .take_while(|&&b| b != 0)
+            .map(|&b| if (0x20..0x7f).contains(&b) { b as char } else { '?' })
+            .collect();
+
+        Ok(Self {
+            title,
+
+            cgb_flag: rom[0x143],
+            sgb_flag: rom[0x146],
+
+            cartridge_type: rom[0x147],
+            rom_size: rom[0x148],
+            ram_size: rom[0x149],
+
+            destination: rom[0x14a],
+            old_licensee: rom[0x14b],
+            new_licensee: String::from_utf8_lossy(&rom[0x144..0x146]).into_owned(),
+            version: rom[0x14c],
+
+            header_checksum: rom[0x14d],
+            global_checksum: ((rom[0x14e] as u16) << 8) | (rom[0x14f] as u16),
+        })
+    }
+
+}
+
+impl Header {
+
+    pub fn get_type_name(&self) -> &'static str {
+        match self.cartridge_type {
+            0x00 => "ROM ONLY",
+            0x01 => "MBC1",
+            0x02 => "MBC1+RAM",
+            0x03 => "MBC1+RAM+BATTERY",
+            0x05 => "MBC2",
+            0x06 => "MBC2+BATTERY",
+            0x08 => "ROM+RAM",
+            0x09 => "ROM+RAM+BATTERY",
+            0x0b => "MMM01",
+            0x0c => "MMM01+RAM",
+            0x0d => "MMM01+RAM+BATTERY",
+            0x0f => "MBC3+TIMER+BATTERY",
+            0x10 => "MBC3+TIMER+RAM+BATTERY",
+            0x11 => "MBC3",
+            0x12 => "MBC3+RAM",
+            0x13 => "MBC3+RAM+BATTERY",
+            0x19 => "MBC5",
+            0x1a => "MBC5+RAM",
+            0x1b => "MBC5+RAM+BATTERY",
+            0x1c => "MBC5+RUMBLE",
+            0x1d => "MBC5+RUMBLE+RAM",
+            0x1e => "MBC5+RUMBLE+RAM+BATTERY",
+            0x20 => "MBC6",
+            0x22 => "MBC7+SENSOR+RUMBLE+RAM+BATTERY",
+            0xfc => "POCKET CAMERA",
+            0xfd => "BANDAI TAMA5",
+            0xfe => "HuC3",
+            0xff => "HuC1+RAM+BATTERY",
+            _ => "unknown",
+        }
+    }
+
+    // 32kb << n
+    pub fn get_rom_bytes(&self) -> Option<usize> {
+        if self.rom_size <= 8 { Some(0x8000 << self.rom_size) } else { None }
+    }
+
+    pub fn get_ram_bytes(&self) -> Option<usize> {
+        match self.ram_size {
+            0x00 => Some(0),
+            0x01 => Some(0x800),
+            0x02 => Some(0x2000),
+            0x03 => Some(0x8000),
+            0x04 => Some(0x20000),
+            0x05 => Some(0x10000),
+            _ => None,
+        }
+    }
+
+    pub fn get_cgb_support(&self) -> &'static str {
+        match self.cgb_flag {
+            0xc0 => "required",
+            0x80 => "supported",
+            _ => "no",
+        }
+    }
+
+}
+
+// The boot rom refuses to start carts where this doesn't match byte 0x14d
+pub fn compute_header_checksum(rom: &[u8]) -> u8 {
+    rom[0x134..=0x14c].iter().fold(0u8, |sum, &b| sum.wrapping_sub(b).wrapping_sub(1))
+}
+
+// Sum of every byte but the checksum itself, which nothing checks
+pub fn compute_global_checksum(rom: &[u8]) -> u16 {
+    rom.iter().enumerate()
+        .filter(|&(i, _)| i != 0x14e && i != 0x14f)
+        .fold(0u16, |sum, (_, &b)| sum.wrapping_add(b as u16))
+}
@@ -1,34 +1,116 @@
+use crate::gb::model::Model;
+use crate::gb::log::Target;
+use crate::gb::savestate::State;
 
-use minifb::{WindowOptions, Window, Key};
-use std::rc::Rc;
+pub const LCD_WIDTH: usize = 160;
+pub const LCD_HEIGHT: usize = 144;
 
-const WIDTH: usize = 256;
-const HEIGHT: usize = 256;
+// 154 lines of 456 cycles
+pub const FRAME_CYCLES: usize = 70224;
+
+// Line timing
+const LINE_CYCLES: usize = 456;
+const OAM_SCAN_CYCLES: usize = 80;
+const TRANSFER_CYCLES: usize = 172;
+
+const VBLANK_LINE: u8 = 144;
+const LAST_LINE: u8 = 153;
+
+// LCD Modes (STAT bits 0-1)
+const MODE_HBLANK: u8 = 0;
+const MODE_VBLANK: u8 = 1;
+const MODE_OAM_SCAN: u8 = 2;
+const MODE_TRANSFER: u8 = 3;
 
 const VRAM_BANK_NUM : usize = 2;
 const VRAM_BANK_SIZE : usize = 0x2000;
 
+const OAM_SIZE: usize = 0xa0;
+const SPRITES_PER_LINE: usize = 10;
+
+// 8 palettes of 4 rgb555 colors
+const PALETTE_RAM_SIZE: usize = 64;
+
+// Colors for the 4 dmg shades
+const DMG_SHADES: [u32; 4] = [0xffffff, 0xaaaaaa, 0x555555, 0x000000];
+
+// Named sets of dmg shades, lightest first
+pub const DMG_PALETTES: [(&str, [u32; 4]); 3] = [
+    ("grey", DMG_SHADES),
+    ("green", [0x9bbc0f, 0x8bac0f, 0x306230, 0x0f380f]),
+    ("pocket", [0xc4cfa1, 0x8b956d, 0x4d533c, 0x1f1f1f]),
+];
+
+// A palette name, or four 0xRRGGBB colors from lightest to darkest
+pub fn parse_dmg_palette(value: &str) -> Result<[u32; 4], String> {
+    if let Some(&(_, colors)) = DMG_PALETTES.iter().find(|&&(name, _)| name == value) {
+        return Ok(colors);
+    }
+
+    let colors: Vec<u32> = value.split(',')
+        .map(|color| u32::from_str_radix(color.trim().trim_start_matches('#'), 16))
+        .filter_map(Result::ok)
+        .filter(|&color| color <= 0xffffff)
+        .collect();
+
+    if colors.len() != 4 || value.split(',').count() != 4 {
+        let names: Vec<&str> = DMG_PALETTES.iter().map(|&(name, _)| name).collect();
+        return Err(format!(
+            "unknown palette '{}' (expected {} or four hex colors like e0f8d0,88c070,346856,081820)",
+            value, names.join(", ")
+        ));
+    }
+
+    Ok([colors[0], colors[1], colors[2], colors[3]])
+}
+
 type VRAMBank = [u8; VRAM_BANK_SIZE];
+type PaletteRAM = [u8; PALETTE_RAM_SIZE];
 
 #[derive(Clone)]
 pub struct GPU {
 
     fbuffer: Vec<u32>,
-    window: Rc<Window>,
 
-    keypad: u8,
-    
+    // dmg shade (0-3) of every pixel, which the super gameboy colors in
+    shades: Vec<u8>,
+
+    // a frame finished and is ready to be shown
+    frame: bool,
+
+    // cycles into the current line
     cycle: usize,
+    mode: u8,
+
+    // line of the window to draw next
+    window_line: u8,
+
+    // STAT interrupt line, interrupts fire when it goes high
+    stat_line: bool,
+
+    // a visible line just entered hblank
+    hblank: bool,
+
+    model: Model,
+
+    // Gameboy Color features are enabled
+    cgb: bool,
+
+    // colors the 4 dmg shades are shown as
+    dmg_colors: [u32; 4],
 
     vram_bank: u8,
     vram_banks: Vec<VRAMBank>,
 
+    // Sprite Attribute Table
+    oam: [u8; OAM_SIZE],
+
     interrupt: u8,
 
     // IO Registers
     ldcd: u8,
     stat: u8,
-    
+
     scy: u8,
     scx: u8,
 
@@ -42,31 +124,49 @@ pub struct GPU {
 
     wy: u8,
     wx: u8,
+
+    // Gameboy Color Palettes
+    bcps: u8,
+    ocps: u8,
+
+    bg_palettes: PaletteRAM,
+    obj_palettes: PaletteRAM,
+
+    // mimic the washed out colors of the gameboy color lcd
+    color_correction: bool,
 }
 
 impl GPU {
 
     // Make new GPU
-    pub fn init() -> Self {
+    pub fn init(model: Model, cgb: bool) -> Self {
         Self {
-            fbuffer: vec![0; WIDTH * HEIGHT],
-            window: Rc::new(Window::new(
-                    "Gameboy LCD",
-                    WIDTH,
-                    HEIGHT,
-                    WindowOptions::default(),
-                ).unwrap_or_else(|e| {
-                    panic!("{}", e);
-                })
-            ),
-
-            keypad: 0xff,
+            fbuffer: vec![DMG_SHADES[0]; LCD_WIDTH * LCD_HEIGHT],
+
+            shades: vec![0; LCD_WIDTH * LCD_HEIGHT],
+
+            frame: false,
 
             cycle: 0,
+            mode: MODE_OAM_SCAN,
+
+            window_line: 0,
+
+            stat_line: false,
+
+            hblank: false,
+
+            model,
+
+            cgb,
+
+            dmg_colors: DMG_SHADES,
 
             vram_bank: 0,
             vram_banks: vec![[0; VRAM_BANK_SIZE]; VRAM_BANK_NUM],
 
+            oam: [0; OAM_SIZE],
+
             interrupt: 0,
 
             ldcd: 0,
@@ -76,7 +176,7 @@ impl GPU {
             scx: 0,
 
             ly: 0,
-            lyc: 0, 
+            lyc: 0,
 
             bgp: 0,
 
@@ -85,6 +185,14 @@ impl GPU {
 
             wy: 0,
             wx: 0,
+
+            bcps: 0,
+            ocps: 0,
+
+            bg_palettes: [0xff; PALETTE_RAM_SIZE],
+            obj_palettes: [0xff; PALETTE_RAM_SIZE],
+
+            color_correction: false,
         }
     }
 
@@ -92,24 +200,53 @@ impl GPU {
 
 impl GPU {
     pub fn step(&mut self, cycles: usize) {
-        self.update_keypad();
-
-        self.cycle += cycles;
-
-        if(self.cycle > 6666) {
-            self.ly += 1; 
-            self.cycle = 0;
+        if !self.is_lcd_on() {
+            return;
         }
 
-        if self.ly >= 153 {
-            self.ly = 0;
-            
-            self.interrupt |= 0x01;
+        self.cycle += cycles;
 
-            self.update_tile_map();
-            if let Some(win) = Rc::get_mut(&mut self.window) {
-                win.update_with_buffer(&self.fbuffer, WIDTH, HEIGHT).unwrap();
+        loop {
+            match self.mode {
+                MODE_OAM_SCAN if self.cycle >= OAM_SCAN_CYCLES => {
+                    self.set_mode(MODE_TRANSFER);
+                },
+
+                MODE_TRANSFER if self.cycle >= OAM_SCAN_CYCLES + TRANSFER_CYCLES => {
+                    self.draw_line();
+                    self.set_mode(MODE_HBLANK);
+                    self.hblank = true;
+                },
+
+                MODE_HBLANK if self.cycle >= LINE_CYCLES => {
+                    self.cycle -= LINE_CYCLES;
+                    self.ly += 1;
+
+                    if self.ly == VBLANK_LINE {
+                        self.set_mode(MODE_VBLANK);
+                        self.interrupt |= 1 << 0;
+                        self.window_line = 0;
+
+                        self.frame = true;
+                    } else {
+                        self.set_mode(MODE_OAM_SCAN);
+                    }
+                },
+
+                MODE_VBLANK if self.cycle >= LINE_CYCLES => {
+                    self.cycle -= LINE_CYCLES;
+                    self.ly += 1;
+
+                    if self.ly > LAST_LINE {
+                        self.ly = 0;
+                        self.set_mode(MODE_OAM_SCAN);
+                    }
+                },
+
+                _ => break,
             }
+
+            self.update_stat_line();
         }
     }
 
@@ -118,6 +255,72 @@ impl GPU {
         self.interrupt = 0;
         ret
     }
+
+    // Whether a hblank started since the last call, for hblank dma
+    pub fn take_hblank(&mut self) -> bool {
+        let ret = self.hblank;
+        self.hblank = false;
+        ret
+    }
+
+    // Whether a frame finished since the last call
+    pub fn take_frame(&mut self) -> bool {
+        let ret = self.frame;
+        self.frame = false;
+        ret
+    }
+}
+
+impl GPU {
+
+    // Gameboy Color features can be turned off for dmg games
+    pub fn set_cgb(&mut self, cgb: bool) {
+        self.cgb = cgb;
+    }
+
+    fn is_lcd_on(&self) -> bool {
+        (self.ldcd & (1 << 7)) != 0
+    }
+
+    fn set_mode(&mut self, mode: u8) {
+        self.mode = mode;
+    }
+
+    fn set_lcdc(&mut self, val: u8) {
+        let was_on = self.is_lcd_on();
+        self.ldcd = val;
+
+        // the lcd restarts from the top of the screen when turned on
+        if was_on && !self.is_lcd_on() {
+            self.ly = 0;
+            self.cycle = 0;
+            self.window_line = 0;
+            self.set_mode(MODE_HBLANK);
+        } else if !was_on && self.is_lcd_on() {
+            self.set_mode(MODE_OAM_SCAN);
+            self.update_stat_line();
+        }
+    }
+
+    fn read_stat(&self) -> u8 {
+        let coincidence = if self.ly == self.lyc { 1 << 2 } else { 0 };
+        0x80 | self.stat | coincidence | self.mode
+    }
+
+    // Fire the LCD STAT interrupt on the rising edge of any enabled source
+    fn update_stat_line(&mut self) {
+        let line = ((self.stat & (1 << 6)) != 0 && self.ly == self.lyc)
+            || ((self.stat & (1 << 5)) != 0 && self.mode == MODE_OAM_SCAN)
+            || ((self.stat & (1 << 4)) != 0 && self.mode == MODE_VBLANK)
+            || ((self.stat & (1 << 3)) != 0 && self.mode == MODE_HBLANK);
+
+        if line && !self.stat_line {
+            self.interrupt |= 1 << 1;
+        }
+
+        self.stat_line = line;
+    }
+
 }
 
 impl GPU {
@@ -127,8 +330,9 @@ impl GPU {
         self.vram_bank
     }
 
+    // VBK only has the one bit
     pub fn set_bank(&mut self, b: u8) {
-        self.vram_bank = b;
+        self.vram_bank = b & 0x01;
     }
 
     // Read and Write to VRAM
@@ -139,16 +343,32 @@ impl GPU {
     pub fn write_vram_byte(&mut self, idx: u16, val: u8) {
         self.vram_banks[self.vram_bank as usize][idx as usize] = val;
     }
+
+    // Read and Write any VRAM bank
+    pub fn read_from_bank(&self, bank: u16, idx: u16) -> u8 {
+        self.vram_banks[bank as usize][idx as usize]
+    }
+
+    pub fn write_to_bank(&mut self, bank: u16, idx: u16, val: u8) {
+        self.vram_banks[bank as usize][idx as usize] = val;
+    }
+
+    // Read and Write to OAM
+    pub fn read_oam_byte(&self, idx: u16) -> u8 {
+        self.oam[idx as usize]
+    }
+
+    pub fn write_oam_byte(&mut self, idx: u16, val: u8) {
+        self.oam[idx as usize] = val;
+    }
 }
 
 impl GPU {
 
     pub fn read_io_byte(&self, idx: u16) -> u8 {
         match idx {
-            0xff00 => self.keypad, 
-
             0xff40 => self.ldcd,
-            0xff41 => self.stat,
+            0xff41 => self.read_stat(),
             0xff42 => self.scy,
             0xff43 => self.scx,
             0xff44 => self.ly,
@@ -162,9 +382,14 @@ impl GPU {
             0xff4b => self.wx,
 
             0xff4f => self.get_bank(),
-            
+
+            0xff68 => self.bcps | 0x40,
+            0xff69 => self.bg_palettes[(self.bcps & 0x3f) as usize],
+            0xff6a => self.ocps | 0x40,
+            0xff6b => self.obj_palettes[(self.ocps & 0x3f) as usize],
+
             _ => {
-                //dprintln!("Unhandled GPU Read from Address [{:#04x?}]", idx);
+                log_trace!(Target::Ppu, "Unhandled GPU Read from Address [{:#04x?}]", idx);
                 0
             }
         }
@@ -172,14 +397,26 @@ impl GPU {
 
     pub fn write_io_byte(&mut self, idx: u16, val: u8) {
         match idx {
-            0xff00 => self.keypad = (self.keypad & 0xcf) | (val & 0x30),
-
-            0xff40 => self.ldcd = (self.keypad & 0x07) | (val & 0xf8),
-            0xff41 => self.stat = val,
+            0xff40 => self.set_lcdc(val),
+            0xff41 => {
+                self.stat = val & 0x78;
+
+                // writing STAT outside of drawing briefly enables
+                // every source on monochrome models (Road Rash relies on it)
+                if !self.model.is_cgb() && self.is_lcd_on()
+                    && (self.mode == MODE_HBLANK || self.mode == MODE_VBLANK) {
+                    self.interrupt |= 1 << 1;
+                }
+
+                self.update_stat_line();
+            },
             0xff42 => self.scy = val,
             0xff43 => self.scx = val,
             0xff44 => self.ly = 0,
-            0xff45 => self.lyc = val,
+            0xff45 => {
+                self.lyc = val;
+                self.update_stat_line();
+            },
 
             0xff47 => self.bgp = val,
             0xff48 => self.obp0 = val,
@@ -190,81 +427,552 @@ impl GPU {
 
             0xff4f => self.set_bank(val),
 
+            0xff68 => self.bcps = val & 0xbf,
+            0xff69 => {
+                self.bg_palettes[(self.bcps & 0x3f) as usize] = val;
+                self.bcps = increment_palette_index(self.bcps);
+            },
+            0xff6a => self.ocps = val & 0xbf,
+            0xff6b => {
+                self.obj_palettes[(self.ocps & 0x3f) as usize] = val;
+                self.ocps = increment_palette_index(self.ocps);
+            },
+
             _ => {
-                println!("Unhandled GPU Write from Address [{:#04x?}] [{:#02x?}]", idx, val);
+                log_debug!(Target::Ppu, "Unhandled GPU Write from Address [{:#04x?}] [{:#02x?}]", idx, val);
             }
         }
     }
 }
 
+// BCPS/OCPS step to the next byte after each write when bit 7 is set
+fn increment_palette_index(ps: u8) -> u8 {
+    if (ps & 0x80) != 0 {
+        (ps & 0x80) | ((ps + 1) & 0x3f)
+    } else {
+        ps
+    }
+}
+
 impl GPU {
 
-    fn update_keypad(&mut self) {
-        self.keypad = 0x00;
-        if self.window.as_ref().is_key_down(Key::Right) { self.keypad |= 1 << 0; }
-        if self.window.as_ref().is_key_down(Key::Left)  { self.keypad |= 1 << 1; }
-        if self.window.as_ref().is_key_down(Key::Up)    { self.keypad |= 1 << 2; }
-        if self.window.as_ref().is_key_down(Key::Down)  { self.keypad |= 1 << 3; }
-        if self.window.as_ref().is_key_down(Key::A)     { self.keypad |= 1 << 4; }
-        if self.window.as_ref().is_key_down(Key::B)     { self.keypad |= 1 << 5; }
-        if self.window.as_ref().is_key_down(Key::Z)     { self.keypad |= 1 << 6; }
-        if self.window.as_ref().is_key_down(Key::X)     { self.keypad |= 1 << 7; }
-
-        if self.keypad != 0 {
-            self.interrupt |= 1 << 4;
-        }
+    // Mimic the gameboy color lcd instead of showing raw rgb555
+    pub fn set_color_correction(&mut self, enabled: bool) {
+        self.color_correction = enabled;
+    }
+
+    // Colors for the 4 dmg shades, lightest first
+    pub fn set_dmg_colors(&mut self, colors: [u32; 4]) {
+        self.dmg_colors = colors;
+    }
+
+    pub fn get_frame_buffer(&self) -> &[u32] {
+        &self.fbuffer
+    }
+
+    pub fn get_shades(&self) -> &[u8] {
+        &self.shades
+    }
+
+    fn dmg_shade(&self, palette: u8, color: u8) -> u8 {
+        (palette >> (color * 2)) & 0x03
+    }
+
+    fn cgb_color(&self, palettes: &PaletteRAM, palette: u8, color: u8) -> u32 {
+        let idx = (palette as usize) * 8 + (color as usize) * 2;
+        let rgb = (palettes[idx] as u16) | ((palettes[idx + 1] as u16) << 8);
+
+        rgb555_to_host(rgb, self.color_correction)
     }
 
 }
 
+// Convert a gameboy color rgb555 color to 0x00RRGGBB
+pub fn rgb555_to_host(rgb: u16, color_correction: bool) -> u32 {
+    let r = (rgb & 0x1f) as u32;
+    let g = ((rgb >> 5) & 0x1f) as u32;
+    let b = ((rgb >> 10) & 0x1f) as u32;
+
+    let (r, g, b) = if color_correction {
+        // channels bleed into each other on the real lcd
+        (
+            (r * 13 + g * 2 + b) >> 1,
+            (g * 3 + b) << 1,
+            (r * 3 + g * 2 + b * 11) >> 1,
+        )
+    } else {
+        ((r << 3) | (r >> 2), (g << 3) | (g >> 2), (b << 3) | (b >> 2))
+    };
+
+    (r << 16) | (g << 8) | b
+}
+
 impl GPU {
 
-    fn get_pixel(&self, x: usize, y: usize) -> u32 {
-        self.fbuffer[(y % HEIGHT) * WIDTH + (x % WIDTH)]
+    // Bytes for one line of a tile, `tile_addr` is relative to 0x8000
+    fn read_tile_line(&self, bank: usize, tile_addr: u16, row: u8) -> (u8, u8) {
+        let addr = (tile_addr + (row as u16) * 2) as usize;
+        (self.vram_banks[bank][addr], self.vram_banks[bank][addr + 1])
     }
 
-    fn set_pixel(&mut self, x: usize, y: usize, val: u32) {
-        self.fbuffer[(y % HEIGHT) * WIDTH + (x % WIDTH)] = val;
+    // LCDC bit 4 picks between unsigned tiles at 0x8000 and signed tiles at 0x9000
+    fn get_bg_tile_addr(&self, tile_id: u8) -> u16 {
+        if (self.ldcd & (1 << 4)) != 0 {
+            (tile_id as u16) * 16
+        } else {
+            (0x1000 + (tile_id as i8 as i16) * 16) as u16
+        }
     }
-    
-    fn write_tile(&mut self, xpos: usize, ypos: usize, mut tile_addr: u16) {
 
-        for y in 0..8 {
-            let line = (
-                (self.read_vram_byte(tile_addr + 0) as u16) << 8 |
-                (self.read_vram_byte(tile_addr + 1) as u16) << 0
-            );
+    fn draw_line(&mut self) {
+        let y = self.ly as usize;
+        if y >= LCD_HEIGHT {
+            return;
+        }
 
-            tile_addr += 2;
-            
-            for x in 0..8 {
-                let bits = (((line >> (2 * x)) & 0x03) as u32) * 0x0f;
-                let color = (bits << 0) | (bits << 8) | (bits << 16) | (bits << 24);
-                self.set_pixel(x + xpos, y + ypos, color);
-            }
+        let mut line = [self.dmg_colors[0]; LCD_WIDTH];
+
+        // background color index and attribute priority of each pixel
+        let mut bg_color = [0_u8; LCD_WIDTH];
+        let mut bg_priority = [false; LCD_WIDTH];
+
+        let mut shades = [0_u8; LCD_WIDTH];
+
+        self.draw_background(&mut line, &mut shades, &mut bg_color, &mut bg_priority);
+        self.draw_sprites(&mut line, &mut shades, &bg_color, &bg_priority);
+
+        self.fbuffer[(y * LCD_WIDTH)..((y + 1) * LCD_WIDTH)].copy_from_slice(&line);
+        self.shades[(y * LCD_WIDTH)..((y + 1) * LCD_WIDTH)].copy_from_slice(&shades);
+    }
+
+    fn draw_background(&mut self, line: &mut [u32; LCD_WIDTH], shades: &mut [u8; LCD_WIDTH], bg_color: &mut [u8; LCD_WIDTH], bg_priority: &mut [bool; LCD_WIDTH]) {
+        // on the gameboy color LCDC bit 0 is master priority instead
+        if !self.cgb && (self.ldcd & (1 << 0)) == 0 {
+            return;
+        }
+
+        let bg_map: u16 = if (self.ldcd & (1 << 3)) != 0 { 0x1c00 } else { 0x1800 };
+        let win_map: u16 = if (self.ldcd & (1 << 6)) != 0 { 0x1c00 } else { 0x1800 };
+
+        let window = (self.ldcd & (1 << 5)) != 0 && self.ly >= self.wy && self.wx <= 166;
+        let mut drew_window = false;
+
+        for x in 0..LCD_WIDTH {
+            let in_window = window && (x + 7) >= (self.wx as usize);
+
+            let (map, mx, my) = if in_window {
+                drew_window = true;
+                (win_map, (x + 7 - self.wx as usize) as u8, self.window_line)
+            } else {
+                (bg_map, (x as u8).wrapping_add(self.scx), self.ly.wrapping_add(self.scy))
+            };
+
+            let map_addr = map + ((my as u16) / 8) * 32 + (mx as u16) / 8;
+            let tile_id = self.vram_banks[0][map_addr as usize];
+
+            // gameboy color keeps tile attributes in the second vram bank
+            let attr = if self.cgb { self.vram_banks[1][map_addr as usize] } else { 0 };
+
+            let bank = if (attr & (1 << 3)) != 0 { 1 } else { 0 };
+            let mut row = my % 8;
+            let mut col = mx % 8;
+
+            if (attr & (1 << 6)) != 0 { row = 7 - row; }
+            if (attr & (1 << 5)) != 0 { col = 7 - col; }
+
+            let (low, high) = self.read_tile_line(bank, self.get_bg_tile_addr(tile_id), row);
+            let bit = 7 - col;
+            let color = (((high >> bit) & 1) << 1) | ((low >> bit) & 1);
+
+            bg_color[x] = color;
+            bg_priority[x] = (attr & (1 << 7)) != 0;
+
+            line[x] = if self.cgb {
+                self.cgb_color(&self.bg_palettes, attr & 0x07, color)
+            } else {
+                shades[x] = self.dmg_shade(self.bgp, color);
+                self.dmg_colors[shades[x] as usize]
+            };
+        }
+
+        if drew_window {
+            self.window_line += 1;
         }
     }
 
-    fn update_tile_map(&mut self) {
-        if (self.ldcd & (1 << 6)) == 0 {
-            for tile in 0x000..0x400 {
-                let tile_id = self.read_vram_byte(tile + 0x1c00) as u8 as u16;
-                let tile_addr = 0x0000 + 32 * tile_id;
-                let x = (tile * 8) as usize;
-                let y = ((tile * 8 / (WIDTH as u16)) * 8) as usize;
+    fn draw_sprites(&self, line: &mut [u32; LCD_WIDTH], shades: &mut [u8; LCD_WIDTH], bg_color: &[u8; LCD_WIDTH], bg_priority: &[bool; LCD_WIDTH]) {
+        if (self.ldcd & (1 << 1)) == 0 {
+            return;
+        }
 
-                self.write_tile(x, y, tile_addr);
+        let height: i16 = if (self.ldcd & (1 << 2)) != 0 { 16 } else { 8 };
+        let ly = self.ly as i16;
+
+        // only the first 10 sprites on the line in oam order are drawn
+        let mut sprites: Vec<usize> = (0..(OAM_SIZE / 4))
+            .filter(|&i| {
+                let y = (self.oam[i * 4] as i16) - 16;
+                ly >= y && ly < y + height
+            })
+            .take(SPRITES_PER_LINE)
+            .collect();
+
+        // the dmg favours sprites further left, the cgb only goes by oam order
+        if !self.cgb {
+            sprites.sort_by_key(|&i| self.oam[i * 4 + 1]);
+        }
+
+        // pixels already covered by a sprite with higher priority
+        let mut taken = [false; LCD_WIDTH];
+
+        for &i in sprites.iter() {
+            let sy = (self.oam[i * 4] as i16) - 16;
+            let sx = (self.oam[i * 4 + 1] as i16) - 8;
+            let mut tile = self.oam[i * 4 + 2];
+            let attr = self.oam[i * 4 + 3];
+
+            if height == 16 {
+                tile &= 0xfe;
+            }
+
+            let mut row = (ly - sy) as u8;
+            if (attr & (1 << 6)) != 0 {
+                row = (height as u8) - 1 - row;
+            }
+
+            let bank = if self.cgb && (attr & (1 << 3)) != 0 { 1 } else { 0 };
+            let (low, high) = self.read_tile_line(bank, (tile as u16) * 16, row);
+
+            for px in 0..8 {
+                let x = sx + px;
+                if x < 0 || x >= LCD_WIDTH as i16 || taken[x as usize] {
+                    continue;
+                }
+                let x = x as usize;
+
+                let bit = if (attr & (1 << 5)) != 0 { px } else { 7 - px };
+                let color = (((high >> bit) & 1) << 1) | ((low >> bit) & 1);
+
+                // color 0 is transparent
+                if color == 0 {
+                    continue;
+                }
+                taken[x] = true;
+
+                let behind_bg = if self.cgb {
+                    (self.ldcd & (1 << 0)) != 0 && bg_color[x] != 0
+                        && ((attr & (1 << 7)) != 0 || bg_priority[x])
+                } else {
+                    (attr & (1 << 7)) != 0 && bg_color[x] != 0
+                };
+
+                if behind_bg {
+                    continue;
+                }
+
+                line[x] = if self.cgb {
+                    self.cgb_color(&self.obj_palettes, attr & 0x07, color)
+                } else {
+                    let palette = if (attr & (1 << 4)) != 0 { self.obp1 } else { self.obp0 };
+                    shades[x] = self.dmg_shade(palette, color);
+                    self.dmg_colors[shades[x] as usize]
+                };
             }
+        }
+    }
+}
+
+impl GPU {
+
+    // Save states, the model and display options stay as they are
+    pub fn sync_state(&mut self, state: &mut State) {
+        state.u32s(&mut self.fbuffer);
+        state.bytes(&mut self.shades);
+
+        state.bool(&mut self.frame);
+
+        state.usize(&mut self.cycle);
+        state.u8(&mut self.mode);
+
+        state.u8(&mut self.window_line);
+        state.bool(&mut self.stat_line);
+        state.bool(&mut self.hblank);
+
+        state.bool(&mut self.cgb);
+
+        state.u8(&mut self.vram_bank);
+        for bank in self.vram_banks.iter_mut() {
+            state.bytes(bank);
+        }
+
+        state.bytes(&mut self.oam);
+
+        state.u8(&mut self.interrupt);
+
+        for reg in [
+            &mut self.ldcd, &mut self.stat, &mut self.scy, &mut self.scx,
+            &mut self.ly, &mut self.lyc, &mut self.bgp, &mut self.obp0, &mut self.obp1,
+            &mut self.wy, &mut self.wx, &mut self.bcps, &mut self.ocps,
+        ].iter_mut() {
+            state.u8(reg);
+        }
+
+        state.bytes(&mut self.bg_palettes);
+        state.bytes(&mut self.obj_palettes);
+    }
+
+}
+
+////////// DEBUG VIEWS //////////
+// Pictures of vram and oam for the vram inspector, or anything else that wants them
+
+// tiles in each vram bank, shown 16 to a row
+pub const TILES_PER_BANK: usize = 384;
+const TILE_SHEET_COLUMNS: usize = 16;
+
+// tile maps are 32x32 tiles
+pub const TILE_MAP_SIZE: usize = 256;
+
+const VIEWPORT_COLOR: u32 = 0xff0000;
+
+// An image with 0x00RRGGBB pixels, row by row
+#[derive(Clone)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u32>,
+}
+
+impl Image {
+    pub fn init(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0; width * height],
+        }
+    }
+
+    fn set_pixel(&mut self, x: usize, y: usize, color: u32) {
+        self.pixels[y * self.width + x] = color;
+    }
+}
+
+// Palette tiles are drawn with when they aren't drawn as the game would
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ViewPalette {
+    // color numbers as plain shades of grey
+    Grey,
+
+    Bgp,
+    Obp0,
+    Obp1,
+
+    // gameboy color palettes 0-7
+    Background(u8),
+    Object(u8),
+}
+
+impl ViewPalette {
+    // Every palette a model has, in order
+    pub fn get_all(cgb: bool) -> Vec<ViewPalette> {
+        let mut palettes = vec![ViewPalette::Grey];
+
+        if cgb {
+            palettes.extend((0..8).map(ViewPalette::Background));
+            palettes.extend((0..8).map(ViewPalette::Object));
         } else {
-            for tile in 0x000..0x400 {
-                let tile_id = self.read_vram_byte(tile + 0x1800) as i8 as i32;
-                let tile_addr = ((0x1000 as i32) + (32 * tile_id)) as u16;
-                let x = (tile * 8) as usize;
-                let y = ((tile * 8 / (WIDTH as u16)) * 8) as usize;
+            palettes.extend_from_slice(&[ViewPalette::Bgp, ViewPalette::Obp0, ViewPalette::Obp1]);
+        }
+
+        palettes
+    }
+
+    pub fn get_name(&self) -> String {
+        match self {
+            ViewPalette::Grey => "grey".to_string(),
+            ViewPalette::Bgp => "BGP".to_string(),
+            ViewPalette::Obp0 => "OBP0".to_string(),
+            ViewPalette::Obp1 => "OBP1".to_string(),
+            ViewPalette::Background(n) => format!("BG {}", n),
+            ViewPalette::Object(n) => format!("OBJ {}", n),
+        }
+    }
+}
+
+// A sprite's four bytes of oam
+#[derive(Clone, Copy, Debug)]
+pub struct OamEntry {
+    pub index: usize,
+
+    // as stored, 16 below and 8 right of where the sprite is drawn
+    pub y: u8,
+    pub x: u8,
+
+    pub tile: u8,
+    pub attr: u8,
+}
+
+impl OamEntry {
+    pub fn is_behind_bg(&self) -> bool { (self.attr & (1 << 7)) != 0 }
+    pub fn is_flip_y(&self) -> bool { (self.attr & (1 << 6)) != 0 }
+    pub fn is_flip_x(&self) -> bool { (self.attr & (1 << 5)) != 0 }
+
+    // OBP1 on the dmg
+    pub fn get_dmg_palette(&self) -> u8 { (self.attr >> 4) & 1 }
+
+    pub fn get_bank(&self) -> u8 { (self.attr >> 3) & 1 }
+    pub fn get_cgb_palette(&self) -> u8 { self.attr & 0x07 }
+
+    // somewhere on the screen, for at least one line
+    pub fn is_visible(&self, height: u8) -> bool {
+        self.x > 0 && self.x < 168 && (self.y as u16) + (height as u16) > 16 && self.y < 160
+    }
+}
+
+impl GPU {
+
+    pub fn is_cgb(&self) -> bool {
+        self.cgb
+    }
+
+    // Scroll and window registers, as scx, scy, wx, wy
+    pub fn get_scroll(&self) -> (u8, u8, u8, u8) {
+        (self.scx, self.scy, self.wx, self.wy)
+    }
+
+    // 8 or 16, from LCDC bit 2
+    pub fn get_sprite_height(&self) -> u8 {
+        if (self.ldcd & (1 << 2)) != 0 { 16 } else { 8 }
+    }
 
-                self.write_tile(x, y, tile_addr);
+    // Tile maps used by the background and window (0 for 0x9800, 1 for 0x9c00)
+    pub fn get_map_selection(&self) -> (usize, usize) {
+        (((self.ldcd >> 3) & 1) as usize, ((self.ldcd >> 6) & 1) as usize)
+    }
+
+    fn view_color(&self, palette: ViewPalette, color: u8) -> u32 {
+        let shade = match palette {
+            ViewPalette::Grey => color,
+            ViewPalette::Bgp => self.dmg_shade(self.bgp, color),
+            ViewPalette::Obp0 => self.dmg_shade(self.obp0, color),
+            ViewPalette::Obp1 => self.dmg_shade(self.obp1, color),
+            ViewPalette::Background(n) => return self.cgb_color(&self.bg_palettes, n & 0x07, color),
+            ViewPalette::Object(n) => return self.cgb_color(&self.obj_palettes, n & 0x07, color),
+        };
+
+        self.dmg_colors[shade as usize]
+    }
+
+    // Color number of a pixel in a tile, `tile_addr` is relative to 0x8000
+    fn tile_pixel(&self, bank: usize, tile_addr: u16, x: u8, y: u8) -> u8 {
+        let (low, high) = self.read_tile_line(bank, tile_addr, y);
+        let bit = 7 - x;
+        (((high >> bit) & 1) << 1) | ((low >> bit) & 1)
+    }
+
+    // Every tile in vram, 16 to a row, with the second bank to the right on the gameboy color
+    pub fn render_tiles(&self, palette: ViewPalette) -> Image {
+        let banks = if self.cgb { VRAM_BANK_NUM } else { 1 };
+        let rows = TILES_PER_BANK / TILE_SHEET_COLUMNS;
+        let mut image = Image::init(banks * TILE_SHEET_COLUMNS * 8, rows * 8);
+
+        for bank in 0..banks {
+            for tile in 0..TILES_PER_BANK {
+                let left = (bank * TILE_SHEET_COLUMNS + tile % TILE_SHEET_COLUMNS) * 8;
+                let top = (tile / TILE_SHEET_COLUMNS) * 8;
+
+                for y in 0..8 {
+                    for x in 0..8 {
+                        let color = self.tile_pixel(bank, (tile * 16) as u16, x as u8, y as u8);
+                        image.set_pixel(left + x, top + y, self.view_color(palette, color));
+                    }
+                }
             }
+        }
 
+        image
+    }
+
+    // One of the 32x32 tile maps (0 for 0x9800, 1 for 0x9c00) as the background
+    // would draw it, with the part of it scrolled onto the screen outlined
+    pub fn render_tile_map(&self, map: usize) -> Image {
+        let base: u16 = if map == 0 { 0x1800 } else { 0x1c00 };
+        let mut image = Image::init(TILE_MAP_SIZE, TILE_MAP_SIZE);
+
+        for ty in 0..32 {
+            for tx in 0..32 {
+                let map_addr = (base + ty * 32 + tx) as usize;
+                let tile_id = self.vram_banks[0][map_addr];
+                let attr = if self.cgb { self.vram_banks[1][map_addr] } else { 0 };
+
+                let bank = if (attr & (1 << 3)) != 0 { 1 } else { 0 };
+                let palette = if self.cgb { ViewPalette::Background(attr & 0x07) } else { ViewPalette::Bgp };
+
+                for y in 0..8 {
+                    for x in 0..8 {
+                        let row = if (attr & (1 << 6)) != 0 { 7 - y } else { y };
+                        let col = if (attr & (1 << 5)) != 0 { 7 - x } else { x };
+
+                        let color = self.tile_pixel(bank, self.get_bg_tile_addr(tile_id), col, row);
+                        image.set_pixel((tx * 8) as usize + x as usize, (ty * 8) as usize + y as usize, self.view_color(palette, color));
+                    }
+                }
+            }
         }
+
+        // the viewport wraps around the edges of the map
+        let (scx, scy) = (self.scx as usize, self.scy as usize);
+        let wrap = |v: usize| v % TILE_MAP_SIZE;
+
+        for x in 0..LCD_WIDTH {
+            image.set_pixel(wrap(scx + x), scy, VIEWPORT_COLOR);
+            image.set_pixel(wrap(scx + x), wrap(scy + LCD_HEIGHT - 1), VIEWPORT_COLOR);
+        }
+        for y in 0..LCD_HEIGHT {
+            image.set_pixel(scx, wrap(scy + y), VIEWPORT_COLOR);
+            image.set_pixel(wrap(scx + LCD_WIDTH - 1), wrap(scy + y), VIEWPORT_COLOR);
+        }
+
+        image
     }
-}
\ No newline at end of file
+
+    pub fn get_oam_entries(&self) -> Vec<OamEntry> {
+        (0..(OAM_SIZE / 4))
+            .map(|i| OamEntry {
+                index: i,
+                y: self.oam[i * 4],
+                x: self.oam[i * 4 + 1],
+                tile: self.oam[i * 4 + 2],
+                attr: self.oam[i * 4 + 3],
+            })
+            .collect()
+    }
+
+    // A sprite as it would be drawn, 8x8 or 8x16, with color 0 left in
+    pub fn render_sprite(&self, sprite: &OamEntry) -> Image {
+        let height = self.get_sprite_height();
+        let tile = if height == 16 { sprite.tile & 0xfe } else { sprite.tile };
+        let bank = if self.cgb { sprite.get_bank() as usize } else { 0 };
+
+        let palette = match (self.cgb, sprite.get_dmg_palette()) {
+            (true, _) => ViewPalette::Object(sprite.get_cgb_palette()),
+            (false, 0) => ViewPalette::Obp0,
+            (false, _) => ViewPalette::Obp1,
+        };
+
+        let mut image = Image::init(8, height as usize);
+
+        for y in 0..height {
+            for x in 0..8 {
+                let row = if sprite.is_flip_y() { height - 1 - y } else { y };
+                let col = if sprite.is_flip_x() { 7 - x } else { x };
+
+                let color = self.tile_pixel(bank, (tile as u16) * 16, col, row);
+                image.set_pixel(x as usize, y as usize, self.view_color(palette, color));
+            }
+        }
+
+        image
+    }
+
+}
@@ -0,0 +1,205 @@
+use crate::gb::log::Target;
+use crate::gb::savestate::State;
+
+// Bits of the button state
+pub const BUTTON_RIGHT: u8 = 1 << 0;
+pub const BUTTON_LEFT: u8 = 1 << 1;
+pub const BUTTON_UP: u8 = 1 << 2;
+pub const BUTTON_DOWN: u8 = 1 << 3;
+pub const BUTTON_A: u8 = 1 << 4;
+pub const BUTTON_B: u8 = 1 << 5;
+pub const BUTTON_SELECT: u8 = 1 << 6;
+pub const BUTTON_START: u8 = 1 << 7;
+
+// Super Gameboy packets are 16 bytes, sent a bit at a time through P1
+pub const SGB_PACKET_SIZE: usize = 16;
+pub type SGBPacket = [u8; SGB_PACKET_SIZE];
+
+#[derive(Clone)]
+pub struct Joypad {
+
+    interrupt: u8,
+
+    // buttons held down, see BUTTON_*
+    buttons: u8,
+
+    // P1 bits 4 (directions) and 5 (buttons), selected when low
+    select: u8,
+
+    // Super Gameboy packet decoding
+    sgb: bool,
+    receiving: bool,
+    packet: SGBPacket,
+    packet_bits: usize,
+    packets: Vec<SGBPacket>,
+
+    // Super Gameboy multiplayer (MLT_REQ)
+    players: u8,
+    player: u8,
+
+}
+
+impl Joypad {
+
+    pub fn init(sgb: bool) -> Self {
+        Self {
+            interrupt: 0,
+
+            buttons: 0,
+
+            select: 0x30,
+
+            sgb,
+            receiving: false,
+            packet: [0; SGB_PACKET_SIZE],
+            packet_bits: 0,
+            packets: Vec::new(),
+
+            players: 1,
+            player: 0,
+        }
+    }
+
+}
+
+impl Joypad {
+
+    // Update which buttons are held, pressing a button fires the joypad interrupt
+    pub fn set_buttons(&mut self, buttons: u8) {
+        if (buttons & !self.buttons) != 0 {
+            self.interrupt |= 1 << 4;
+        }
+
+        self.buttons = buttons;
+    }
+
+    pub fn get_buttons(&self) -> u8 {
+        self.buttons
+    }
+
+    pub fn get_interrupt(&mut self) -> u8 {
+        let ret = self.interrupt;
+        self.interrupt = 0;
+        ret
+    }
+
+}
+
+impl Joypad {
+
+    // Packets that have finished sending since the last call
+    pub fn take_sgb_packets(&mut self) -> Vec<SGBPacket> {
+        std::mem::take(&mut self.packets)
+    }
+
+    // Number of controllers the Super Gameboy reports (1, 2 or 4)
+    pub fn set_players(&mut self, players: u8) {
+        if self.players != players {
+            self.players = players;
+            self.player = 0;
+        }
+    }
+
+    // Each bit is a pulse on P14 (0) or P15 (1) with both lines going back high
+    // in between, a pulse on both resets and starts a new packet
+    fn sgb_write(&mut self, select: u8) {
+        match select {
+            0x00 => {
+                self.receiving = true;
+                self.packet = [0; SGB_PACKET_SIZE];
+                self.packet_bits = 0;
+            },
+
+            0x10 | 0x20 if self.receiving && self.select == 0x30 => {
+                let bit = if select == 0x10 { 1 } else { 0 };
+
+                if self.packet_bits < SGB_PACKET_SIZE * 8 {
+                    self.packet[self.packet_bits / 8] |= bit << (self.packet_bits % 8);
+                    self.packet_bits += 1;
+                } else {
+                    // the stop bit after the packet is always 0
+                    if bit == 0 {
+                        self.packets.push(self.packet);
+                    }
+                    self.receiving = false;
+                }
+            },
+
+            // controllers are switched on by deselecting the buttons
+            0x30 if self.players > 1 && (self.select & 0x20) == 0 => {
+                self.player = (self.player + 1) % self.players;
+            },
+
+            _ => {},
+        }
+    }
+
+}
+
+impl Joypad {
+
+    pub fn read_io_byte(&self, idx: u16) -> u8 {
+        match idx {
+            0xff00 => {
+                let mut low = 0x0f;
+
+                // only the first controller has anything plugged in
+                if self.player == 0 {
+                    if (self.select & 0x10) == 0 { low &= !(self.buttons & 0x0f); }
+                    if (self.select & 0x20) == 0 { low &= !(self.buttons >> 4); }
+                }
+
+                // with nothing selected the super gameboy reports the controller
+                if self.select == 0x30 && self.players > 1 {
+                    low = 0x0f - self.player;
+                }
+
+                0xc0 | self.select | low
+            },
+
+            _ => {
+                log_trace!(Target::Bus, "Unhandled Joypad Read from Address [{:#04x?}]", idx);
+                0
+            }
+        }
+    }
+
+    pub fn write_io_byte(&mut self, idx: u16, val: u8) {
+        match idx {
+            0xff00 => {
+                let select = val & 0x30;
+
+                if self.sgb {
+                    self.sgb_write(select);
+                }
+
+                self.select = select;
+            },
+
+            _ => {
+                log_debug!(Target::Bus, "Unhandled Joypad Write from Address [{:#04x?}] [{:#02x?}]", idx, val);
+            }
+        }
+    }
+
+}
+
+impl Joypad {
+
+    // Save states
+    pub fn sync_state(&mut self, state: &mut State) {
+        state.u8(&mut self.interrupt);
+
+        state.u8(&mut self.buttons);
+        state.u8(&mut self.select);
+
+        state.bool(&mut self.receiving);
+        state.bytes(&mut self.packet);
+        state.usize(&mut self.packet_bits);
+        state.vec(&mut self.packets, |state, packet| state.bytes(packet));
+
+        state.u8(&mut self.players);
+        state.u8(&mut self.player);
+    }
+
+}
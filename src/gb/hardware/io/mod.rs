@@ -1,4 +1,5 @@
 pub mod gpu;
+pub mod joypad;
 pub mod serial;
 pub mod sound;
 pub mod timer;
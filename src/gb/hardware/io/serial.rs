@@ -1,12 +1,56 @@
+use crate::gb::hardware::link::{SerialDevice, POLL_CYCLES};
+use crate::gb::log::Target;
+use crate::gb::savestate::State;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// 8 bits shifted out at 8192hz on the internal clock
+const TRANSFER_CYCLES: usize = 4096;
 
-#[derive(Clone)]
 pub struct Serial {
 
     interrupt: u8,
 
-    sb: u8, 
+    sb: u8,
     sc: u8,
 
+    // cycles left until the current transfer finishes
+    transfer: usize,
+
+    // cycles since the link cable was last polled
+    poll: usize,
+
+    // every byte transmitted, when capturing is enabled
+    capture: Option<Vec<u8>>,
+
+    // whatever is on the other end of the link cable, which is
+    // never cloned or saved since there's only one of it
+    device: Option<Rc<RefCell<dyn SerialDevice>>>,
+
+}
+
+// A copy of the serial port starts with nothing plugged in, otherwise two
+// copies could both drive one cable. Use move_device to hand it over
+impl Clone for Serial {
+
+    fn clone(&self) -> Self {
+        Self {
+            interrupt: self.interrupt,
+
+            sb: self.sb,
+            sc: self.sc,
+
+            transfer: self.transfer,
+
+            poll: self.poll,
+
+            capture: self.capture.clone(),
+
+            device: None,
+        }
+    }
+
 }
 
 impl Serial {
@@ -17,6 +61,14 @@ impl Serial {
 
             sb: 0,
             sc: 0,
+
+            transfer: 0,
+
+            poll: 0,
+
+            capture: None,
+
+            device: None,
         }
     }
 
@@ -25,7 +77,51 @@ impl Serial {
 impl Serial {
 
     pub fn step(&mut self, cycles: usize) {
-        // TODO: idk
+        if self.is_transferring() && self.is_internal_clock() {
+            if self.transfer > cycles {
+                self.transfer -= cycles;
+            } else {
+                let received = match &self.device {
+                    Some(device) => device.borrow_mut().exchange(self.sb),
+                    None => 0xff,
+                };
+
+                self.finish_transfer(received);
+            }
+        } else {
+            self.poll_device(cycles);
+        }
+    }
+
+    // Look for bytes clocked in by the other end of the cable
+    fn poll_device(&mut self, cycles: usize) {
+        let interval = match &self.device {
+            Some(device) => device.borrow().get_poll_cycles(),
+            None => POLL_CYCLES,
+        };
+
+        self.poll += cycles;
+        if self.poll < interval {
+            return;
+        }
+        self.poll = 0;
+
+        self.update_device();
+    }
+
+    // Tell the other end which byte we're waiting to have clocked out, if any,
+    // and pick up one it clocked in. Done whenever SB or SC change too, so a
+    // master clocking right after we start listening doesn't miss us
+    fn update_device(&mut self) {
+        let out = if self.is_transferring() && !self.is_internal_clock() { Some(self.sb) } else { None };
+        let received = match &self.device {
+            Some(device) => device.borrow_mut().poll(out),
+            None => None,
+        };
+
+        if let (Some(val), Some(_)) = (received, out) {
+            self.finish_transfer(val);
+        }
     }
 
     pub fn get_interrupt(&mut self) -> u8 {
@@ -36,15 +132,100 @@ impl Serial {
 
 }
 
+impl Serial {
+
+    fn is_transferring(&self) -> bool {
+        (self.sc & 0x80) != 0
+    }
+
+    fn is_internal_clock(&self) -> bool {
+        (self.sc & 0x01) != 0
+    }
+
+    fn start_transfer(&mut self) {
+        self.transfer = TRANSFER_CYCLES;
+
+        if let Some(capture) = self.capture.as_mut() {
+            capture.push(self.sb);
+        }
+    }
+
+    fn finish_transfer(&mut self, received: u8) {
+        self.sb = received;
+        self.sc &= 0x7f;
+        self.transfer = 0;
+        self.interrupt |= 1 << 3;
+    }
+
+}
+
+impl Serial {
+
+    // Plug something into the link cable port
+    pub fn connect(&mut self, device: Rc<RefCell<dyn SerialDevice>>) {
+        self.device = Some(device);
+    }
+
+    pub fn disconnect(&mut self) {
+        self.device = None;
+    }
+
+    // Let the device save anything it has pending before shutting down
+    pub fn finish_device(&mut self) -> Result<(), String> {
+        match &self.device {
+            Some(device) => device.borrow_mut().finish(),
+            None => Ok(()),
+        }
+    }
+
+    // Unplug the device and plug it into `other`
+    pub fn move_device(&mut self, other: &mut Serial) {
+        other.device = self.device.take();
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.device.is_some()
+    }
+
+}
+
+impl Serial {
+
+    // Start recording every transmitted byte
+    pub fn enable_capture(&mut self) {
+        if self.capture.is_none() {
+            self.capture = Some(Vec::new());
+        }
+    }
+
+    pub fn get_capture(&self) -> &[u8] {
+        match &self.capture {
+            Some(capture) => capture,
+            None => &[],
+        }
+    }
+
+    pub fn get_capture_string(&self) -> String {
+        String::from_utf8_lossy(self.get_capture()).into_owned()
+    }
+
+    pub fn clear_capture(&mut self) {
+        if let Some(capture) = self.capture.as_mut() {
+            capture.clear();
+        }
+    }
+
+}
+
 impl Serial {
 
     pub fn read_io_byte(&self, idx: u16) -> u8 {
         match idx {
             0xff01 => self.sb,
-            0xff02 => self.sc,
-            
+            0xff02 => self.sc | 0x7e,
+
             _ => {
-                //println!("Unhandled Serial Read from Address [{:#04x?}]", idx);
+                log_trace!(Target::Serial, "Unhandled Serial Read from Address [{:#04x?}]", idx);
                 0
             }
         }
@@ -52,13 +233,69 @@ impl Serial {
 
     pub fn write_io_byte(&mut self, idx: u16, val: u8) {
         match idx {
-            0xff01 => self.sb = val,
-            0xff02 => self.sc = val,
+            0xff01 => {
+                self.sb = val;
+                self.update_device();
+            },
+
+            0xff02 => {
+                let starting = !self.is_transferring() && (val & 0x80) != 0;
+                self.sc = val & 0x81;
+
+                if starting {
+                    self.start_transfer();
+                }
+
+                self.update_device();
+            },
 
             _ => {
-                println!("Unhandled Serial Read from Address [{:#04x?}] [{:#02x?}]", idx, val);
+                log_debug!(Target::Serial, "Unhandled Serial Write from Address [{:#04x?}] [{:#02x?}]", idx, val);
             }
         }
     }
 
-}
\ No newline at end of file
+}
+
+impl Serial {
+
+    // Save states, the link cable and captured output stay as they are
+    pub fn sync_state(&mut self, state: &mut State) {
+        state.u8(&mut self.interrupt);
+
+        state.u8(&mut self.sb);
+        state.u8(&mut self.sc);
+
+        state.usize(&mut self.transfer);
+
+        state.usize(&mut self.poll);
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Loopback;
+
+    impl SerialDevice for Loopback {
+        fn exchange(&mut self, out: u8) -> u8 {
+            out
+        }
+    }
+
+    #[test]
+    fn clones_start_unplugged() {
+        let mut serial = Serial::init();
+        serial.connect(Rc::new(RefCell::new(Loopback)));
+
+        let mut copy = serial.clone();
+        assert!(serial.is_connected());
+        assert!(!copy.is_connected());
+
+        serial.move_device(&mut copy);
+        assert!(!serial.is_connected());
+        assert!(copy.is_connected());
+    }
+}
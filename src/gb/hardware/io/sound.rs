@@ -1,18 +1,46 @@
+use crate::gb::log::Target;
 
 #[derive(Clone)]
 pub struct Sound {
+    // no sound is made yet, so this is only kept for when it is
+    enabled: bool,
 
+    // output volume, 0 to 100, also unused until there's sound
+    volume: u8,
 }
 
 impl Sound {
 
     pub fn init() -> Self {
         Self {
+            enabled: true,
+
+            volume: 100,
         }
     }
 
 }
 
+impl Sound {
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn get_volume(&self) -> u8 {
+        self.volume
+    }
+
+    pub fn set_volume(&mut self, volume: u8) {
+        self.volume = volume.min(100);
+    }
+
+}
+
 impl Sound {
 
     pub fn step(&mut self, cycles: usize) {
@@ -27,7 +55,7 @@ impl Sound {
         match idx {
             
             _ => {
-                //println!("Unhandled Sound Read from Address [{:#04x?}]", idx);
+                log_trace!(Target::Apu, "Unhandled Sound Read from Address [{:#04x?}]", idx);
                 0
             }
         }
@@ -37,7 +65,7 @@ impl Sound {
         match idx {
             
             _ => {
-                println!("Unhandled Sound Write from Address [{:#04x?}] [{:#02x?}]", idx, val);
+                log_debug!(Target::Apu, "Unhandled Sound Write from Address [{:#04x?}] [{:#02x?}]", idx, val);
             }
         }
     }
@@ -1,13 +1,23 @@
+use crate::gb::log::Target;
+use crate::gb::savestate::State;
+
+// cycles per TIMA increment for each TAC clock select
+const TIMA_PERIODS: [usize; 4] = [1024, 16, 64, 256];
 
 #[derive(Clone)]
 pub struct Timer {
 
     interrupt: u8,
 
-    div: u8, 
-    tima: u8, 
-    tma: u8, 
-    tac: u8, 
+    // DIV is the top byte of this counter
+    counter: u16,
+
+    tima: u8,
+    tma: u8,
+    tac: u8,
+
+    // cycles since TIMA last increased
+    tima_cycles: usize,
 
 }
 
@@ -17,10 +27,13 @@ impl Timer {
         Self {
             interrupt: 0,
 
-            div: 0,
+            counter: 0,
+
             tima: 0,
             tma: 0,
             tac: 0,
+
+            tima_cycles: 0,
         }
     }
 
@@ -28,11 +41,35 @@ impl Timer {
 
 impl Timer {
 
+    // Timer runs off the cpu clock, so it speeds up in double speed mode
     pub fn step(&mut self, cycles: usize) {
-        // TODO: idk
+        self.counter = self.counter.wrapping_add(cycles as u16);
+
+        if (self.tac & 0x04) == 0 {
+            return;
+        }
+
+        let period = TIMA_PERIODS[(self.tac & 0x03) as usize];
+        self.tima_cycles += cycles;
+
+        while self.tima_cycles >= period {
+            self.tima_cycles -= period;
+
+            if self.tima == 0xff {
+                self.tima = self.tma;
+                self.interrupt |= 1 << 2;
+            } else {
+                self.tima += 1;
+            }
+        }
     }
 
 
+    // Set the internal counter that DIV counts from
+    pub fn set_counter(&mut self, counter: u16) {
+        self.counter = counter;
+    }
+
     pub fn get_interrupt(&mut self) -> u8 {
         let ret = self.interrupt;
         self.interrupt = 0;
@@ -44,14 +81,14 @@ impl Timer {
 
     pub fn read_io_byte(&self, idx: u16) -> u8 {
         match idx {
-            0xff04 => self.div,
+            0xff04 => (self.counter >> 8) as u8,
             0xff05 => self.tima,
             0xff06 => self.tma,
-            0xff07 => self.tac,
-            
+            0xff07 => self.tac | 0xf8,
+
 
             _ => {
-                //println!("Unhandled Timer Read from Address [{:#04x?}]", idx);
+                log_trace!(Target::Timer, "Unhandled Timer Read from Address [{:#04x?}]", idx);
                 0
             }
         }
@@ -59,15 +96,88 @@ impl Timer {
 
     pub fn write_io_byte(&mut self, idx: u16, val: u8) {
         match idx {
-            0xff04 => self.div = 0x00,
+            0xff04 => {
+                self.counter = 0x0000;
+                self.tima_cycles = 0;
+            },
             0xff05 => self.tima = val,
             0xff06 => self.tma = val,
-            0xff07 => self.tac = val,
+            0xff07 => self.tac = val & 0x07,
 
             _ => {
-                println!("Unhandled Timer Read from Address [{:#04x?}] [{:#02x?}]", idx, val);
+                log_debug!(Target::Timer, "Unhandled Timer Write from Address [{:#04x?}] [{:#02x?}]", idx, val);
             }
         }
     }
 
-}
\ No newline at end of file
+}
+
+impl Timer {
+
+    // Save states
+    pub fn sync_state(&mut self, state: &mut State) {
+        state.u8(&mut self.interrupt);
+
+        state.u16(&mut self.counter);
+
+        state.u8(&mut self.tima);
+        state.u8(&mut self.tma);
+        state.u8(&mut self.tac);
+
+        state.usize(&mut self.tima_cycles);
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn div_counts_every_256_cycles() {
+        let mut timer = Timer::init();
+        timer.step(255);
+        assert_eq!(timer.read_io_byte(0xff04), 0);
+        timer.step(1);
+        assert_eq!(timer.read_io_byte(0xff04), 1);
+
+        // any write clears it
+        timer.write_io_byte(0xff04, 0x12);
+        assert_eq!(timer.read_io_byte(0xff04), 0);
+    }
+
+    #[test]
+    fn tima_only_counts_when_enabled() {
+        let mut timer = Timer::init();
+        timer.write_io_byte(0xff07, 0x01);
+        timer.step(1024);
+        assert_eq!(timer.read_io_byte(0xff05), 0);
+        assert_eq!(timer.read_io_byte(0xff07), 0xf9);
+
+        // 16 cycles a tick
+        timer.write_io_byte(0xff07, 0x05);
+        timer.step(16 * 3 + 15);
+        assert_eq!(timer.read_io_byte(0xff05), 3);
+    }
+
+    #[test]
+    fn tima_overflow_reloads_tma() {
+        let mut timer = Timer::init();
+        timer.write_io_byte(0xff05, 0xfe);
+        timer.write_io_byte(0xff06, 0x80);
+        timer.write_io_byte(0xff07, 0x04);
+
+        timer.step(1024);
+        assert_eq!(timer.read_io_byte(0xff05), 0xff);
+        assert_eq!(timer.get_interrupt(), 0);
+
+        timer.step(1024);
+        assert_eq!(timer.read_io_byte(0xff05), 0x80);
+        assert_eq!(timer.get_interrupt(), 1 << 2);
+
+        // and keeps counting from tma
+        timer.step(1024 * 2);
+        assert_eq!(timer.read_io_byte(0xff05), 0x82);
+        assert_eq!(timer.get_interrupt(), 0);
+    }
+}
@@ -0,0 +1,32 @@
+pub mod printer;
+pub mod tcp;
+pub mod wire;
+
+// cycles between checks for transfers clocked by the other end
+pub const POLL_CYCLES: usize = 512;
+
+// Something plugged into the other end of the link cable
+pub trait SerialDevice {
+
+    // We clocked a byte out on the internal clock,
+    // returns the byte that was shifted in from the other end
+    fn exchange(&mut self, out: u8) -> u8;
+
+    // Check for a transfer clocked by the other end. `out` is the byte
+    // we have waiting in SB, or None if no transfer has been started on
+    // our end. Returns the byte shifted in if a transfer happened.
+    fn poll(&mut self, _out: Option<u8>) -> Option<u8> {
+        None
+    }
+
+    // How often to poll, devices that are cheap to check can ask for every step
+    fn get_poll_cycles(&self) -> usize {
+        POLL_CYCLES
+    }
+
+    // The emulator is shutting down, save anything that hasn't been yet
+    fn finish(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+
+}
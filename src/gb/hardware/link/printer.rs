@@ -0,0 +1,451 @@
+// Gameboy Printer
+// https://gbdev.io/pandocs/Gameboy_Printer.html
+
+use crate::gb::hardware::link::SerialDevice;
+use crate::gb::png;
+use crate::gb::log::Target;
+
+use std::path::PathBuf;
+
+// printouts are always 20 tiles across
+const PRINT_WIDTH: usize = 160;
+
+// the printer can hold 9 data packets (18 tile rows) at a time
+const BUFFER_SIZE: usize = 0x2400;
+
+// pixel rows fed per unit of margin
+const MARGIN_ROWS: usize = 8;
+
+// status inquiries the printer stays busy for after printing
+const BUSY_INQUIRIES: u8 = 4;
+
+// games that send a palette of 0 get the usual 3 2 1 0
+const DEFAULT_PALETTE: u8 = 0xe4;
+
+// Commands
+const CMD_INIT: u8 = 0x01;
+const CMD_PRINT: u8 = 0x02;
+const CMD_DATA: u8 = 0x04;
+const CMD_STATUS: u8 = 0x0f;
+
+// Status bits
+const STATUS_CHECKSUM_ERROR: u8 = 1 << 0;
+const STATUS_BUSY: u8 = 1 << 1;
+const STATUS_FULL: u8 = 1 << 2;
+const STATUS_UNPROCESSED: u8 = 1 << 3;
+
+// Shades printed for each palette color
+const SHADES: [u32; 4] = [0xffffff, 0xaaaaaa, 0x555555, 0x000000];
+
+#[derive(Clone, Copy, PartialEq)]
+enum PacketState {
+    Magic1,
+    Magic2,
+    Command,
+    Compression,
+    LengthLow,
+    LengthHigh,
+    Data,
+    ChecksumLow,
+    ChecksumHigh,
+    Alive,
+    Status,
+}
+
+pub struct Printer {
+    // where printouts are saved
+    directory: PathBuf,
+
+    state: PacketState,
+
+    // packet being received
+    command: u8,
+    compressed: bool,
+    length: u16,
+    data: Vec<u8>,
+    checksum: u16,
+    sum: u16,
+
+    status: u8,
+    busy: u8,
+
+    // 2bpp tile data waiting to be printed
+    buffer: Vec<u8>,
+
+    // shades of the sheet coming out of the printer, until the paper is cut
+    sheet: Vec<u32>,
+
+    // every png that has been written
+    printed: Vec<PathBuf>,
+}
+
+impl Printer {
+
+    pub fn init(directory: PathBuf) -> Self {
+        Self {
+            directory,
+
+            state: PacketState::Magic1,
+
+            command: 0,
+            compressed: false,
+            length: 0,
+            data: Vec::new(),
+            checksum: 0,
+            sum: 0,
+
+            status: 0,
+            busy: 0,
+
+            buffer: Vec::new(),
+            sheet: Vec::new(),
+
+            printed: Vec::new(),
+        }
+    }
+
+    pub fn get_printed(&self) -> &[PathBuf] {
+        &self.printed
+    }
+
+}
+
+impl Printer {
+
+    // Feed a byte through the packet state machine
+    fn receive(&mut self, val: u8) {
+        self.state = match self.state {
+            PacketState::Magic1 => {
+                if val == 0x88 { PacketState::Magic2 } else { PacketState::Magic1 }
+            },
+
+            // another 0x88 could still be the start of the packet
+            PacketState::Magic2 => match val {
+                0x33 => PacketState::Command,
+                0x88 => PacketState::Magic2,
+                _ => PacketState::Magic1,
+            },
+
+            PacketState::Command => {
+                self.command = val;
+                self.sum = val as u16;
+                PacketState::Compression
+            },
+
+            PacketState::Compression => {
+                self.compressed = (val & 0x01) != 0;
+                self.sum = self.sum.wrapping_add(val as u16);
+                PacketState::LengthLow
+            },
+
+            PacketState::LengthLow => {
+                self.length = val as u16;
+                self.sum = self.sum.wrapping_add(val as u16);
+                PacketState::LengthHigh
+            },
+
+            PacketState::LengthHigh => {
+                self.length |= (val as u16) << 8;
+                self.sum = self.sum.wrapping_add(val as u16);
+                self.data.clear();
+
+                if self.length == 0 { PacketState::ChecksumLow } else { PacketState::Data }
+            },
+
+            PacketState::Data => {
+                self.data.push(val);
+                self.sum = self.sum.wrapping_add(val as u16);
+
+                if self.data.len() >= self.length as usize {
+                    PacketState::ChecksumLow
+                } else {
+                    PacketState::Data
+                }
+            },
+
+            PacketState::ChecksumLow => {
+                self.checksum = val as u16;
+                PacketState::ChecksumHigh
+            },
+
+            PacketState::ChecksumHigh => {
+                self.checksum |= (val as u16) << 8;
+
+                if self.checksum == self.sum {
+                    self.status &= !STATUS_CHECKSUM_ERROR;
+                    self.run_command();
+                } else {
+                    self.status |= STATUS_CHECKSUM_ERROR;
+                }
+
+                PacketState::Alive
+            },
+
+            PacketState::Alive => PacketState::Status,
+            PacketState::Status => PacketState::Magic1,
+        };
+    }
+
+    fn run_command(&mut self) {
+        match self.command {
+            CMD_INIT => {
+                self.buffer.clear();
+                self.status = 0;
+                self.busy = 0;
+            },
+
+            CMD_DATA => {
+                let data = if self.compressed {
+                    decompress(&self.data)
+                } else {
+                    self.data.clone()
+                };
+
+                // an empty data packet just marks the end of the image
+                if !data.is_empty() {
+                    self.buffer.extend_from_slice(&data);
+                    self.buffer.truncate(BUFFER_SIZE);
+                    self.status |= STATUS_UNPROCESSED;
+                }
+
+                if self.buffer.len() >= BUFFER_SIZE {
+                    self.status |= STATUS_FULL;
+                }
+            },
+
+            CMD_PRINT => {
+                if self.data.len() >= 4 {
+                    let margins = self.data[1];
+                    let palette = match self.data[2] {
+                        0 => DEFAULT_PALETTE,
+                        palette => palette,
+                    };
+
+                    self.print(margins >> 4, margins & 0x0f, palette);
+                }
+
+                self.status &= !STATUS_UNPROCESSED;
+                self.status |= STATUS_BUSY;
+                self.busy = BUSY_INQUIRIES;
+            },
+
+            CMD_STATUS => {
+                if self.busy > 0 {
+                    self.busy -= 1;
+
+                    if self.busy == 0 {
+                        self.status &= !(STATUS_BUSY | STATUS_FULL);
+                    }
+                }
+            },
+
+            _ => {
+                log_debug!(Target::Serial, "Unhandled Printer Command [{:#02x?}]", self.command);
+            }
+        }
+    }
+
+}
+
+impl Printer {
+
+    // Print the buffered tiles onto the sheet
+    fn print(&mut self, before: u8, after: u8, palette: u8) {
+        self.feed(before as usize * MARGIN_ROWS);
+
+        // each tile row is 20 tiles of 16 bytes
+        for tile_row in self.buffer.chunks(20 * 16) {
+            for y in 0..8 {
+                for x in 0..PRINT_WIDTH {
+                    let line = (x / 8) * 16 + y * 2;
+                    if line + 1 >= tile_row.len() {
+                        self.sheet.push(SHADES[0]);
+                        continue;
+                    }
+
+                    let low = tile_row[line];
+                    let high = tile_row[line + 1];
+                    let bit = 7 - (x % 8);
+
+                    let color = (((high >> bit) & 1) << 1) | ((low >> bit) & 1);
+                    let shade = (palette >> (color * 2)) & 0x03;
+
+                    self.sheet.push(SHADES[shade as usize]);
+                }
+            }
+        }
+
+        self.buffer.clear();
+        self.feed(after as usize * MARGIN_ROWS);
+
+        // without a margin after, the next print continues on the same sheet
+        if after > 0 {
+            if let Err(e) = self.cut() {
+                log_error!(Target::Serial, "{}", e);
+            }
+        }
+    }
+
+    fn feed(&mut self, rows: usize) {
+        let len = self.sheet.len() + rows * PRINT_WIDTH;
+        self.sheet.resize(len, SHADES[0]);
+    }
+
+    // Save the sheet as a png
+    fn cut(&mut self) -> Result<(), String> {
+        if self.sheet.is_empty() {
+            return Ok(());
+        }
+
+        let mut index = self.printed.len();
+        let mut path = self.directory.join(format!("print_{:03}.png", index));
+        while path.exists() {
+            index += 1;
+            path = self.directory.join(format!("print_{:03}.png", index));
+        }
+
+        let height = self.sheet.len() / PRINT_WIDTH;
+        let written = png::write_png(&path, PRINT_WIDTH, height, &self.sheet);
+        self.sheet.clear();
+
+        written.map_err(|e| format!("can't save printout {}: {}", path.display(), e))?;
+        log_info!(Target::Serial, "Printed {}", path.display());
+        self.printed.push(path);
+        Ok(())
+    }
+
+}
+
+// Printer rle: a control byte with bit 7 set repeats the next byte
+// (n & 0x7f) + 2 times, otherwise the next n + 1 bytes are copied
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+
+    while i < data.len() {
+        let control = data[i];
+        i += 1;
+
+        if (control & 0x80) != 0 {
+            let len = (control & 0x7f) as usize + 2;
+            if let Some(&val) = data.get(i) {
+                out.extend(std::iter::repeat_n(val, len));
+            }
+            i += 1;
+        } else {
+            let len = control as usize + 1;
+            let end = (i + len).min(data.len());
+            out.extend_from_slice(&data[i..end]);
+            i = end;
+        }
+    }
+
+    out
+}
+
+impl SerialDevice for Printer {
+
+    fn exchange(&mut self, out: u8) -> u8 {
+        // the last two bytes of a packet are answered with
+        // the device id and the status after the command ran
+        let reply = match self.state {
+            PacketState::Alive => 0x81,
+            PacketState::Status => self.status,
+            _ => 0x00,
+        };
+
+        self.receive(out);
+        reply
+    }
+
+    // whatever is still on the sheet comes out too
+    fn finish(&mut self) -> Result<(), String> {
+        self.cut()
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::fs;
+
+    // A whole packet with its checksum and the two bytes the printer answers
+    fn packet(command: u8, data: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x88, 0x33, command, 0x00, data.len() as u8, (data.len() >> 8) as u8];
+        packet.extend_from_slice(data);
+
+        let sum = packet[2..].iter().fold(0u16, |sum, &b| sum.wrapping_add(b as u16));
+        packet.extend_from_slice(&sum.to_le_bytes());
+        packet.extend_from_slice(&[0x00, 0x00]);
+        packet
+    }
+
+    fn send(printer: &mut Printer, bytes: &[u8]) -> Vec<u8> {
+        bytes.iter().map(|&b| printer.exchange(b)).collect()
+    }
+
+    fn temp_printer(name: &str) -> Printer {
+        let dir = env::temp_dir().join(format!("samb_gb-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        Printer::init(dir)
+    }
+
+    #[test]
+    fn decompresses_runs_and_literals() {
+        // 3 literal bytes, then 0xaa 5 times, then 1 literal
+        let data = [0x02, 1, 2, 3, 0x83, 0xaa, 0x00, 9];
+        assert_eq!(decompress(&data), vec![1, 2, 3, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 9]);
+
+        // cut short, what's there is kept
+        assert_eq!(decompress(&[0x05, 1, 2]), vec![1, 2]);
+        assert_eq!(decompress(&[0x80]), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn answers_status_after_packets() {
+        let mut printer = temp_printer("printer-status");
+
+        let reply = send(&mut printer, &packet(CMD_INIT, &[]));
+        assert_eq!(&reply[reply.len() - 2..], &[0x81, 0x00]);
+
+        let reply = send(&mut printer, &packet(CMD_DATA, &[0x00; 0x280]));
+        assert_eq!(reply[reply.len() - 1], STATUS_UNPROCESSED);
+
+        // a bad checksum is reported and the command doesn't run
+        let mut bad = packet(CMD_INIT, &[]);
+        bad[6] ^= 0xff;
+        let reply = send(&mut printer, &bad);
+        assert_eq!(reply[reply.len() - 1], STATUS_UNPROCESSED | STATUS_CHECKSUM_ERROR);
+    }
+
+    #[test]
+    fn stays_in_sync_after_repeated_magic() {
+        let mut printer = temp_printer("printer-magic");
+
+        // a stray 0x88 before the real start of the packet
+        let mut bytes = vec![0x88];
+        bytes.extend(packet(CMD_STATUS, &[]));
+        let reply = send(&mut printer, &bytes);
+        assert_eq!(&reply[reply.len() - 2..], &[0x81, 0x00]);
+    }
+
+    #[test]
+    fn finishing_saves_the_sheet() {
+        let mut printer = temp_printer("printer-finish");
+
+        send(&mut printer, &packet(CMD_INIT, &[]));
+        send(&mut printer, &packet(CMD_DATA, &[0xff; 0x280]));
+
+        // no margin after, so the sheet stays in the printer
+        send(&mut printer, &packet(CMD_PRINT, &[0x01, 0x00, 0xe4, 0x40]));
+        assert!(printer.get_printed().is_empty());
+
+        printer.finish().unwrap();
+        assert_eq!(printer.get_printed().len(), 1);
+        assert!(printer.get_printed()[0].exists());
+    }
+}
@@ -0,0 +1,208 @@
+use crate::gb::hardware::link::SerialDevice;
+use crate::gb::log::Target;
+
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
+
+// Every message on the wire is a tag, a sequence number and the data byte.
+// Replies carry the sequence number of the byte they answer, so a late
+// reply to an exchange that already gave up isn't taken for the next one
+const MSG_MASTER: u8 = 0x01; // byte clocked out by the sender
+const MSG_SLAVE: u8 = 0x02;  // reply to a MSG_MASTER
+const MSG_SIZE: usize = 3;
+
+// how long a master waits for the other emulator before giving up
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Link cable to another emulator over a local tcp socket
+pub struct TcpLink {
+    stream: Option<TcpStream>,
+
+    // bytes read that don't form a whole message yet
+    incoming: Vec<u8>,
+
+    // sequence number of the last byte we clocked out
+    sequence: u8,
+}
+
+impl TcpLink {
+
+    // Wait for the other emulator to connect on localhost
+    pub fn listen(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        let (stream, _) = listener.accept()?;
+        Self::open(stream)
+    }
+
+    // Connect to an emulator listening on localhost
+    pub fn connect(port: u16) -> io::Result<Self> {
+        let stream = TcpStream::connect(("127.0.0.1", port))?;
+        Self::open(stream)
+    }
+
+    fn open(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+
+        Ok(Self {
+            stream: Some(stream),
+            incoming: Vec::new(),
+
+            sequence: 0,
+        })
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+}
+
+impl TcpLink {
+
+    fn send(&mut self, tag: u8, sequence: u8, val: u8) {
+        let sent = match self.stream.as_mut() {
+            Some(stream) => stream.write_all(&[tag, sequence, val]).is_ok(),
+            None => return,
+        };
+
+        if !sent {
+            self.disconnect();
+        }
+    }
+
+    // Read a message, waiting for one if `blocking` is set
+    fn recv(&mut self, blocking: bool) -> Option<(u8, u8, u8)> {
+        while self.incoming.len() < MSG_SIZE {
+            let stream = self.stream.as_mut()?;
+
+            let setup = if blocking {
+                stream.set_nonblocking(false)
+                    .and_then(|_| stream.set_read_timeout(Some(REPLY_TIMEOUT)))
+            } else {
+                stream.set_nonblocking(true)
+            };
+
+            if setup.is_err() {
+                self.disconnect();
+                return None;
+            }
+
+            let mut buffer = [0; 64];
+            match stream.read(&mut buffer) {
+                Ok(0) => {
+                    self.disconnect();
+                    return None;
+                },
+                Ok(n) => self.incoming.extend_from_slice(&buffer[..n]),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return None,
+                Err(ref e) if e.kind() == io::ErrorKind::TimedOut => return None,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(_) => {
+                    self.disconnect();
+                    return None;
+                },
+            }
+        }
+
+        let msg = (self.incoming[0], self.incoming[1], self.incoming[2]);
+        self.incoming.drain(..MSG_SIZE);
+        Some(msg)
+    }
+
+    fn disconnect(&mut self) {
+        log_warn!(Target::Serial, "Link cable disconnected!");
+        self.stream = None;
+        self.incoming.clear();
+    }
+
+}
+
+impl SerialDevice for TcpLink {
+
+    fn exchange(&mut self, out: u8) -> u8 {
+        self.sequence = self.sequence.wrapping_add(1);
+        self.send(MSG_MASTER, self.sequence, out);
+
+        // block until the other end has shifted our byte in, this keeps
+        // both emulators in lockstep for every transfer
+        loop {
+            match self.recv(true) {
+                Some((MSG_SLAVE, sequence, val)) if sequence == self.sequence => return val,
+
+                // both ends clocked a byte at once, they each
+                // see the other's byte and nobody replies
+                Some((MSG_MASTER, _, val)) => return val,
+
+                // replies to earlier exchanges that timed out
+                Some(_) => {},
+                None => return 0xff,
+            }
+        }
+    }
+
+    fn poll(&mut self, out: Option<u8>) -> Option<u8> {
+        // stale replies to a master that already gave up are skipped
+        loop {
+            if let (MSG_MASTER, sequence, val) = self.recv(false)? {
+                // the master always gets a reply so it never stalls,
+                // but if we weren't listening the byte is lost
+                self.send(MSG_SLAVE, sequence, out.unwrap_or(0xff));
+
+                if out.is_some() {
+                    return Some(val);
+                }
+            }
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    // Both ends of a cable over a socket on localhost
+    fn connected_pair() -> (TcpLink, TcpLink) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let (server, _) = listener.accept().unwrap();
+
+        (TcpLink::open(server).unwrap(), TcpLink::open(client).unwrap())
+    }
+
+    // Poll until the master's byte comes in, answering with `out`
+    fn answer(mut link: TcpLink, out: u8) -> thread::JoinHandle<u8> {
+        thread::spawn(move || loop {
+            if let Some(val) = link.poll(Some(out)) {
+                return val;
+            }
+            thread::yield_now();
+        })
+    }
+
+    #[test]
+    fn exchanges_a_byte() {
+        let (mut master, slave) = connected_pair();
+        let slave = answer(slave, 0x22);
+
+        assert_eq!(master.exchange(0x11), 0x22);
+        assert_eq!(slave.join().unwrap(), 0x11);
+    }
+
+    #[test]
+    fn skips_stale_replies() {
+        let (mut master, mut slave) = connected_pair();
+
+        // a reply to an earlier exchange that arrived after it gave up
+        slave.send(MSG_SLAVE, master.sequence, 0x99);
+        let slave = answer(slave, 0x22);
+
+        assert_eq!(master.exchange(0x11), 0x22);
+        assert_eq!(slave.join().unwrap(), 0x11);
+    }
+}
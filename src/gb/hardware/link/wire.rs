@@ -0,0 +1,72 @@
+use crate::gb::hardware::link::SerialDevice;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// State of a link cable between two emulators in the same process
+#[derive(Clone, Default)]
+struct Wire {
+    // byte each end has waiting for the other end's clock
+    ready: [Option<u8>; 2],
+
+    // byte clocked into each end that it hasn't picked up yet
+    inbox: [Option<u8>; 2],
+}
+
+// One end of an in memory link cable
+pub struct WireEnd {
+    wire: Rc<RefCell<Wire>>,
+    side: usize,
+}
+
+impl WireEnd {
+
+    // Make both ends of a new cable
+    pub fn pair() -> (Self, Self) {
+        let wire = Rc::new(RefCell::new(Wire::default()));
+
+        (
+            Self { wire: wire.clone(), side: 0 },
+            Self { wire, side: 1 },
+        )
+    }
+
+}
+
+impl SerialDevice for WireEnd {
+
+    fn exchange(&mut self, out: u8) -> u8 {
+        let mut wire = self.wire.borrow_mut();
+        let other = 1 - self.side;
+
+        // the other end only shifts if it started a transfer
+        match wire.ready[other].take() {
+            Some(val) => {
+                wire.inbox[other] = Some(out);
+                val
+            },
+            None => 0xff,
+        }
+    }
+
+    fn poll(&mut self, out: Option<u8>) -> Option<u8> {
+        let mut wire = self.wire.borrow_mut();
+
+        wire.ready[self.side] = out;
+        let val = wire.inbox[self.side].take()?;
+
+        if out.is_some() {
+            wire.ready[self.side] = None;
+            Some(val)
+        } else {
+            None
+        }
+    }
+
+    // both ends are in the same process, so checking is cheap and
+    // keeps the pair in step to the cycle
+    fn get_poll_cycles(&self) -> usize {
+        1
+    }
+
+}
@@ -1,103 +1,503 @@
 use crate::gb::hardware::cartridge::Cartridge;
 use crate::gb::hardware::work_ram::WorkRAM;
 
-use crate::gb::hardware::io::gpu::GPU;
+use crate::gb::hardware::sgb::{SGB, SGB_WIDTH, SGB_HEIGHT};
+
+use crate::gb::hardware::io::gpu::{GPU, LCD_WIDTH, LCD_HEIGHT};
+use crate::gb::hardware::io::joypad::Joypad;
 use crate::gb::hardware::io::serial::Serial;
 use crate::gb::hardware::io::sound::Sound;
 use crate::gb::hardware::io::timer::Timer;
 
-use std::fs::File;
+use crate::gb::model::Model;
+use crate::gb::debugger::hooks::Watchpoints;
+use crate::gb::log::Target;
+use crate::gb::savestate::State;
+
+
+////////// POST BOOT IO //////////
+// https://gbdev.io/pandocs/Power_Up_Sequence.html#hardware-registers
+// io registers every model has the same after the boot rom finishes
+const POST_BOOT_IO: [(u16, u8); 35] = [
+    (0xff00, 0xcf), (0xff01, 0x00),
+    (0xff05, 0x00), (0xff06, 0x00), (0xff07, 0xf8),
+    (0xff10, 0x80), (0xff11, 0xbf), (0xff12, 0xf3), (0xff13, 0xff), (0xff14, 0xbf),
+    (0xff16, 0x3f), (0xff17, 0x00), (0xff18, 0xff), (0xff19, 0xbf),
+    (0xff1a, 0x7f), (0xff1b, 0xff), (0xff1c, 0x9f), (0xff1d, 0xff), (0xff1e, 0xbf),
+    (0xff20, 0xff), (0xff21, 0x00), (0xff22, 0x00), (0xff23, 0xbf),
+    (0xff24, 0x77), (0xff25, 0xf3), (0xff26, 0xf1),
+    (0xff40, 0x91), (0xff42, 0x00), (0xff43, 0x00), (0xff45, 0x00),
+    (0xff47, 0xfc), (0xff48, 0xff), (0xff49, 0xff), (0xff4a, 0x00), (0xff4b, 0x00),
+];
+
+// and the ones that differ, written after the common ones
+const POST_BOOT_IO_DMG: [(u16, u8); 1] = [
+    (0xff02, 0x7e),
+];
+
+const POST_BOOT_IO_SGB: [(u16, u8); 2] = [
+    (0xff02, 0x7e),
+    // sound channel 1 reported as off
+    (0xff26, 0xf0),
+];
+
+// the cgb only registers are left alone in dmg mode, where they read 0xff
+const POST_BOOT_IO_CGB: [(u16, u8); 4] = [
+    // serial on the internal clock
+    (0xff02, 0x7f),
+    // KEY1 normal speed with no switch armed (0x7e)
+    (0xff4d, 0x00),
+    // VBK vram bank 0 (0xfe)
+    (0xff4f, 0x00),
+    // SVBK wram bank 1 (0xf8)
+    (0xff70, 0x00),
+];
 
 ////////// HIGH RAM //////////
 const HIGH_RAM_SIZE : usize = 128;
 pub type HighRAM = [u8; HIGH_RAM_SIZE];
 
+////////// HDMA //////////
+// bytes copied per hdma block
+const HDMA_BLOCK_SIZE: u16 = 0x10;
+
+// cpu cycles a block takes in normal speed, double speed takes twice as many
+const HDMA_BLOCK_CYCLES: usize = 32;
+
+#[derive(Clone)]
+pub struct HDMA {
+    src: u16,
+    dst: u16,
+
+    // blocks left to copy
+    blocks: u8,
+
+    // copying a block every hblank
+    hblank: bool,
+
+    // cycles the cpu is stalled for by the copy
+    stall: usize,
+}
+
+impl HDMA {
+    pub fn init() -> Self {
+        Self {
+            src: 0,
+            dst: 0,
+            blocks: 0,
+            hblank: false,
+            stall: 0,
+        }
+    }
+
+    pub fn sync_state(&mut self, state: &mut State) {
+        state.u16(&mut self.src);
+        state.u16(&mut self.dst);
+        state.u8(&mut self.blocks);
+        state.bool(&mut self.hblank);
+        state.usize(&mut self.stall);
+    }
+}
+
+////////// OAM DMA //////////
+const OAM_DMA_LENGTH: u16 = 0xa0;
+
+// one byte is copied every m-cycle
+const OAM_DMA_BYTE_CYCLES: usize = 4;
+
+// a transfer starts copying one m-cycle after 0xff46 is written
+const OAM_DMA_STARTUP_CYCLES: usize = 4;
+
+#[derive(Clone)]
+pub struct OamDMA {
+    // last value written to 0xff46
+    reg: u8,
+
+    src: u16,
+
+    // bytes copied so far
+    copied: u16,
+
+    active: bool,
+
+    // cycles until the transfer starts copying
+    startup: usize,
+
+    // cycles towards copying the next byte
+    cycles: usize,
+
+    // the cpu is off the bus, which a restart doesn't give back
+    // since the old transfer carries on until the new one starts
+    blocking: bool,
+}
+
+impl OamDMA {
+    pub fn init() -> Self {
+        Self {
+            reg: 0xff,
+            src: 0,
+            copied: 0,
+            active: false,
+            startup: 0,
+            cycles: 0,
+            blocking: false,
+        }
+    }
+
+    // the cpu is locked off the bus once bytes are being copied
+    pub fn is_running(&self) -> bool {
+        self.blocking
+    }
+
+    pub fn sync_state(&mut self, state: &mut State) {
+        state.u8(&mut self.reg);
+        state.u16(&mut self.src);
+        state.u16(&mut self.copied);
+        state.bool(&mut self.active);
+        state.usize(&mut self.startup);
+        state.usize(&mut self.cycles);
+        state.bool(&mut self.blocking);
+    }
+}
+
+////////// MEMORY SPACES //////////
+// Memory as the cpu sees it, or any bank of one kind of memory
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MemorySpace {
+    Bus,
+    Rom,
+    Vram,
+    Sram,
+    Wram,
+}
+
+pub const MEMORY_SPACES: [MemorySpace; 5] = [
+    MemorySpace::Bus, MemorySpace::Rom, MemorySpace::Vram, MemorySpace::Sram, MemorySpace::Wram,
+];
+
+impl MemorySpace {
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            MemorySpace::Bus => "BUS",
+            MemorySpace::Rom => "ROM",
+            MemorySpace::Vram => "VRAM",
+            MemorySpace::Sram => "SRAM",
+            MemorySpace::Wram => "WRAM",
+        }
+    }
+
+    // Bytes in each bank
+    pub fn get_size(&self) -> usize {
+        match self {
+            MemorySpace::Bus => 0x10000,
+            MemorySpace::Rom => 0x4000,
+            MemorySpace::Vram => 0x2000,
+            MemorySpace::Sram => 0x2000,
+            MemorySpace::Wram => 0x1000,
+        }
+    }
+
+    // Address the start of a bank is mapped to
+    pub fn get_base(&self, bank: u16) -> u16 {
+        match self {
+            MemorySpace::Bus => 0x0000,
+            MemorySpace::Rom => if bank == 0 { 0x0000 } else { 0x4000 },
+            MemorySpace::Vram => 0x8000,
+            MemorySpace::Sram => 0xa000,
+            MemorySpace::Wram => if bank == 0 { 0xc000 } else { 0xd000 },
+        }
+    }
+}
+
 ////////// MEMORY BUS //////////
 #[derive(Clone)]
 pub struct MemoryBus {
     pub rom: Cartridge,
+
+    pub model: Model,
+
+    // mapped over the cartridge until 0xff50 is written
+    boot_rom: Option<Vec<u8>>,
     
     pub gpu: GPU,
+    pub joypad: Joypad,
     pub serial: Serial,
     pub sound: Sound,
     pub timer: Timer,
 
+    // Super Gameboy, which listens for packets sent through the joypad
+    pub sgb: Option<SGB>,
+
     pub intf: u8,
     pub inte: u8,
 
+    // Gameboy Color
+    pub cgb: bool,
+    pub double_speed: bool,
+    pub speed_switch: bool,
+
+    // Sprite DMA
+    pub oam_dma: OamDMA,
+
+    // Gameboy Color VRAM DMA
+    pub hdma: HDMA,
+
     pub ram: WorkRAM,
     pub hram: HighRAM,
+
+    // addresses the debugger stops on when the cpu touches them
+    pub watchpoints: Watchpoints,
+
+    // ly always reads 0x90 (the first line of vblank), which
+    // gameboy doctor traces expect since they don't emulate the lcd
+    pub stub_ly: bool,
+
+    // frames the lcd has finished drawing
+    frame_count: u64,
 }
 
 impl MemoryBus {
 
-    // initialize everything with default values and rom
-    pub fn init(cartridge: &mut File) -> Self {
+    // initialize everything with default values and rom, running the
+    // boot rom if one is given and starting where it leaves off otherwise
+    pub fn init(rom: Cartridge, model: Option<Model>, boot_rom: Option<Vec<u8>>) -> Self {
+        // without a model to go by, use the one the boot rom was made
+        // for, or else the one the cartridge was made for
+        let model = model.unwrap_or_else(|| match &boot_rom {
+            Some(boot) if boot.len() == Model::CGB.get_boot_rom_size() => Model::CGB,
+            Some(_) => Model::DMG,
+            None => Model::from_cartridge(&rom),
+        });
+
+        // the cgb boot rom needs cgb features even for dmg games
+        let cgb = model.is_cgb() && (rom.is_cgb() || boot_rom.is_some());
+
+        // the super gameboy only listens to games that say they support it
+        let sgb = if model == Model::SGB { Some(SGB::init(rom.is_sgb())) } else { None };
+
         let mut i = Self {
-            rom: Cartridge::load(cartridge),
+            rom,
+
+            model,
+
+            boot_rom,
 
-            gpu: GPU::init(),
+            gpu: GPU::init(model, cgb),
+            joypad: Joypad::init(model == Model::SGB),
             serial: Serial::init(),
             sound: Sound::init(),
             timer: Timer::init(),
 
+            sgb,
+
             intf: 0,
             inte: 0,
 
+            cgb,
+            double_speed: false,
+            speed_switch: false,
+
+            oam_dma: OamDMA::init(),
+
+            hdma: HDMA::init(),
+
             ram: WorkRAM::init(),
             hram: [0; HIGH_RAM_SIZE],
+
+            watchpoints: Watchpoints::init(),
+
+            stub_ly: false,
+
+            frame_count: 0,
         };
-        
-        i.write_byte(0xFF05, 0);
-        i.write_byte(0xFF06, 0);
-        i.write_byte(0xFF07, 0);
-        i.write_byte(0xFF10, 0x80);
-        i.write_byte(0xFF11, 0xBF);
-        i.write_byte(0xFF12, 0xF3);
-        i.write_byte(0xFF14, 0xBF);
-        i.write_byte(0xFF16, 0x3F);
-        i.write_byte(0xFF16, 0x3F);
-        i.write_byte(0xFF17, 0);
-        i.write_byte(0xFF19, 0xBF);
-        i.write_byte(0xFF1A, 0x7F);
-        i.write_byte(0xFF1B, 0xFF);
-        i.write_byte(0xFF1C, 0x9F);
-        i.write_byte(0xFF1E, 0xFF);
-        i.write_byte(0xFF20, 0xFF);
-        i.write_byte(0xFF21, 0);
-        i.write_byte(0xFF22, 0);
-        i.write_byte(0xFF23, 0xBF);
-        i.write_byte(0xFF24, 0x77);
-        i.write_byte(0xFF25, 0xF3);
-        i.write_byte(0xFF26, 0xF1);
-        i.write_byte(0xFF40, 0x91);
-        i.write_byte(0xFF42, 0);
-        i.write_byte(0xFF43, 0);
-        i.write_byte(0xFF45, 0);
-        i.write_byte(0xFF47, 0xFC);
-        i.write_byte(0xFF48, 0xFF);
-        i.write_byte(0xFF49, 0xFF);
-        i.write_byte(0xFF4A, 0);
-        i.write_byte(0xFF4B, 0);
-        
+
+        if i.boot_rom.is_none() {
+            i.skip_boot();
+        }
+
         i
     }
 
+    // Set the io registers to how the boot rom leaves them
+    fn skip_boot(&mut self) {
+        let model_io: &[(u16, u8)] = match self.model {
+            Model::DMG | Model::MGB => &POST_BOOT_IO_DMG,
+            Model::SGB => &POST_BOOT_IO_SGB,
+            Model::CGB | Model::AGB => &POST_BOOT_IO_CGB,
+        };
+
+        for &(idx, val) in POST_BOOT_IO.iter().chain(model_io) {
+            self.write_byte(idx, val);
+        }
+
+        // set directly, writing it would start a transfer
+        self.oam_dma.reg = if self.model.is_cgb() { 0x00 } else { 0xff };
+
+        let div = match self.model {
+            Model::DMG | Model::MGB => 0xabcc,
+            Model::SGB => 0x0000,
+            Model::CGB | Model::AGB => 0x1ea0,
+        };
+        self.timer.set_counter(div);
+
+        self.intf = 0xe1;
+    }
+
+    pub fn is_boot_rom_mapped(&self) -> bool {
+        self.boot_rom.is_some()
+    }
+
+    fn read_boot_rom_byte(&self, idx: u16) -> Option<u8> {
+        let boot_rom = self.boot_rom.as_ref()?;
+
+        // cgb boot roms skip over the cartridge header
+        match idx {
+            0x0100..=0x01ff => None,
+            _ => boot_rom.get(idx as usize).copied(),
+        }
+    }
+
+    // Writing 0xff50 unmaps the boot rom for good
+    fn unmap_boot_rom(&mut self) {
+        if self.boot_rom.take().is_none() {
+            return;
+        }
+
+        // dmg games on the gameboy color drop to compatibility mode
+        self.cgb = self.model.is_cgb() && self.rom.is_cgb();
+        self.gpu.set_cgb(self.cgb);
+    }
+
 }
 
 impl MemoryBus {
 
+    // cycles are cpu cycles, which run twice as fast in double speed mode
     pub fn step(&mut self, cycles: usize) {
-        self.gpu.step(cycles);
+        let lcd_cycles = if self.double_speed { cycles / 2 } else { cycles };
+
+        self.gpu.step(lcd_cycles);
         self.intf |= self.gpu.get_interrupt();
 
+        if self.gpu.take_frame() {
+            self.frame_count += 1;
+
+            if let Some(sgb) = &mut self.sgb {
+                sgb.update_frame(self.gpu.get_shades());
+            }
+        }
+
+        self.intf |= self.joypad.get_interrupt();
+
         self.serial.step(cycles);
         self.intf |= self.serial.get_interrupt();
 
-        self.sound.step(cycles);
+        self.sound.step(lcd_cycles);
 
         self.timer.step(cycles);
         self.intf |= self.timer.get_interrupt();
+
+        self.oam_dma_step(cycles);
+
+        if self.gpu.take_hblank() && self.hdma.hblank {
+            self.hdma_copy_block();
+        }
+    }
+
+    // Frames finished since power on, which stops counting while the lcd is off
+    pub fn get_frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    // What should be on screen, the super gameboy draws the frame into its own picture
+    pub fn get_screen(&self) -> (&[u32], usize, usize) {
+        match &self.sgb {
+            Some(sgb) => (sgb.get_frame_buffer(), SGB_WIDTH, SGB_HEIGHT),
+            None => (self.gpu.get_frame_buffer(), LCD_WIDTH, LCD_HEIGHT),
+        }
+    }
+
+    // Pass packets sent through P1 on to the super gameboy
+    fn receive_sgb_packets(&mut self) {
+        let packets = self.joypad.take_sgb_packets();
+
+        if let Some(sgb) = &mut self.sgb {
+            for packet in packets.iter() {
+                sgb.receive_packet(packet);
+            }
+
+            self.joypad.set_players(sgb.get_players());
+        }
+    }
+
+    // Bank addr is mapped to right now, numbered like symbol files do
+    pub fn get_bank_of(&self, idx: u16) -> u16 {
+        match idx {
+            0x4000..=0x7fff => self.rom.get_bank() as u16,
+            0x8000..=0x9fff => self.gpu.get_bank() as u16,
+            0xd000..=0xdfff => self.ram.get_bank() as u16,
+            _ => 0,
+        }
+    }
+
+    // Banks a memory space has on this cartridge and model
+    pub fn get_bank_count(&self, space: MemorySpace) -> u16 {
+        match space {
+            MemorySpace::Bus | MemorySpace::Sram => 1,
+            MemorySpace::Rom => self.rom.get_bank_count(),
+            MemorySpace::Vram => if self.cgb { 2 } else { 1 },
+            MemorySpace::Wram => if self.cgb { 8 } else { 2 },
+        }
+    }
+
+    // Read any bank without switching to it, the bus is read like the debugger does
+    pub fn read_space_byte(&self, space: MemorySpace, bank: u16, idx: u16) -> u8 {
+        match space {
+            MemorySpace::Bus => self.peek_byte(idx),
+            MemorySpace::Rom => self.rom.read_from_bank(bank, idx),
+            MemorySpace::Vram => self.gpu.read_from_bank(bank, idx),
+            MemorySpace::Sram => self.rom.read_ram_byte(idx),
+            MemorySpace::Wram => self.ram.read_from_bank(bank, idx),
+        }
+    }
+
+    // Write any bank without switching to it, the bus is written like the cpu does
+    pub fn write_space_byte(&mut self, space: MemorySpace, bank: u16, idx: u16, val: u8) {
+        match space {
+            MemorySpace::Bus => {
+                self.write_byte(idx, val);
+                self.watchpoints.take_hit();
+            },
+            MemorySpace::Rom => self.rom.write_to_bank(bank, idx, val),
+            MemorySpace::Vram => self.gpu.write_to_bank(bank, idx, val),
+            MemorySpace::Sram => self.rom.write_ram_byte(idx, val),
+            MemorySpace::Wram => self.ram.write_to_bank(bank, idx, val),
+        }
+    }
+
+    // Called by STOP, switches cpu speed if one was requested through KEY1
+    pub fn switch_speed(&mut self) -> bool {
+        if !self.cgb || !self.speed_switch {
+            return false;
+        }
+
+        self.double_speed = !self.double_speed;
+        self.speed_switch = false;
+        true
+    }
+
+    fn read_key1(&self) -> u8 {
+        let speed = if self.double_speed { 0x80 } else { 0x00 };
+        let switch = if self.speed_switch { 0x01 } else { 0x00 };
+
+        speed | switch | 0x7e
+    }
+
+    // Cycles the cpu has to wait for dma to finish
+    pub fn take_dma_stall(&mut self) -> usize {
+        let stall = self.hdma.stall;
+        self.hdma.stall = 0;
+        stall
     }
 
     pub fn get_interrupts(&mut self) -> u8 {
@@ -118,8 +518,129 @@ impl MemoryBus {
 }
 
 impl MemoryBus {
-    // read byte from memory map
+
+    fn start_oam_dma(&mut self, val: u8) {
+        // writing again mid transfer restarts it from the new source
+        self.oam_dma.reg = val;
+        self.oam_dma.src = (val as u16) << 8;
+        self.oam_dma.copied = 0;
+        self.oam_dma.active = true;
+        self.oam_dma.startup = OAM_DMA_STARTUP_CYCLES;
+        self.oam_dma.cycles = 0;
+    }
+
+    fn oam_dma_step(&mut self, mut cycles: usize) {
+        if !self.oam_dma.active {
+            return;
+        }
+
+        let startup = self.oam_dma.startup.min(cycles);
+        self.oam_dma.startup -= startup;
+        cycles -= startup;
+
+        if self.oam_dma.startup == 0 {
+            self.oam_dma.blocking = true;
+        }
+
+        self.oam_dma.cycles += cycles;
+        while self.oam_dma.active && self.oam_dma.cycles >= OAM_DMA_BYTE_CYCLES {
+            self.oam_dma.cycles -= OAM_DMA_BYTE_CYCLES;
+
+            // sources past work ram read from echo ram
+            let mut src = self.oam_dma.src + self.oam_dma.copied;
+            if src >= 0xe000 {
+                src -= 0x2000;
+            }
+
+            let val = self.peek_byte(src);
+            self.gpu.write_oam_byte(self.oam_dma.copied, val);
+
+            self.oam_dma.copied += 1;
+            if self.oam_dma.copied >= OAM_DMA_LENGTH {
+                self.oam_dma.active = false;
+                self.oam_dma.blocking = false;
+            }
+        }
+    }
+
+    // While oam dma runs the cpu can only reach the high page (io and hram)
+    fn is_dma_blocked(&self, idx: u16) -> bool {
+        self.oam_dma.is_running() && idx < 0xff00
+    }
+
+}
+
+impl MemoryBus {
+
+    fn read_hdma5(&self) -> u8 {
+        let remaining = self.hdma.blocks.wrapping_sub(1) & 0x7f;
+
+        // bit 7 is clear while a hblank dma is running
+        if self.hdma.hblank { remaining } else { 0x80 | remaining }
+    }
+
+    fn write_hdma5(&mut self, val: u8) {
+        // clearing bit 7 during a hblank dma cancels it
+        if self.hdma.hblank && (val & 0x80) == 0 {
+            self.hdma.hblank = false;
+            return;
+        }
+
+        self.hdma.blocks = (val & 0x7f) + 1;
+
+        if (val & 0x80) != 0 {
+            self.hdma.hblank = true;
+        } else {
+            // general purpose dma copies everything at once
+            while self.hdma.blocks > 0 {
+                self.hdma_copy_block();
+            }
+        }
+    }
+
+    fn hdma_copy_block(&mut self) {
+        for i in 0..HDMA_BLOCK_SIZE {
+            let val = self.peek_byte(self.hdma.src.wrapping_add(i));
+            let dst = (self.hdma.dst.wrapping_add(i)) & 0x1fff;
+            self.gpu.write_vram_byte(dst, val);
+        }
+
+        self.hdma.src = self.hdma.src.wrapping_add(HDMA_BLOCK_SIZE);
+        self.hdma.dst = (self.hdma.dst.wrapping_add(HDMA_BLOCK_SIZE)) & 0x1ff0;
+        self.hdma.blocks -= 1;
+
+        self.hdma.stall += if self.double_speed { HDMA_BLOCK_CYCLES * 2 } else { HDMA_BLOCK_CYCLES };
+
+        if self.hdma.blocks == 0 {
+            self.hdma.hblank = false;
+        }
+    }
+
+}
+
+impl MemoryBus {
+    // read byte from memory map as the cpu sees it
     pub fn read_byte(&self, idx: u16) -> u8 {
+        if self.is_dma_blocked(idx) {
+            return 0xff;
+        }
+
+        let val = self.peek_byte(idx);
+        self.watchpoints.check(idx, val, false);
+        val
+    }
+
+    // read byte as the cpu fetches code, which read watchpoints don't see
+    pub fn fetch_byte(&self, idx: u16) -> u8 {
+        if self.is_dma_blocked(idx) { 0xff } else { self.peek_byte(idx) }
+    }
+
+    // read byte from memory map, ignoring bus conflicts
+    pub fn peek_byte(&self, idx: u16) -> u8 {
+        if let Some(val) = self.read_boot_rom_byte(idx) {
+            return val;
+        }
+
         match idx {
             // 16kb ROM Bank 00
             0x0000..=0x3fff => self.rom.read_byte(idx),
@@ -140,22 +661,29 @@ impl MemoryBus {
             0xd000..=0xdfff => self.ram.read_bank_byte(idx - 0xd000),
             
             // ECHO Space (0xc000 - 0xddff)
-            0xe000..=0xfdff => self.read_byte(idx - 0x2000),
+            0xe000..=0xfdff => self.peek_byte(idx - 0x2000),
             
             // Sprite Attribute Table (OAM)
-            // 0xfe00..=0xfe9f => 0, // TODO: figure this out
+            0xfe00..=0xfe9f => self.gpu.read_oam_byte(idx - 0xfe00),
             
             // Not Usable
             // 0xfea0..=0xfeff => 0, // TODO: figure this out
             
             // I/O Ports
-            0xff00 => self.gpu.read_io_byte(idx),
+            0xff00 => self.joypad.read_io_byte(idx),
             0xff01..=0xff02 => self.serial.read_io_byte(idx),
             0xff01..=0xff0e => self.timer.read_io_byte(idx),
             0xff0f => self.intf,
             0xff10..=0xff3f => self.sound.read_io_byte(idx),
-            0xff46 => 0,
-            0xff40..=0xff4f => self.gpu.read_io_byte(idx),
+            0xff46 => self.oam_dma.reg,
+            0xff4d if self.cgb => self.read_key1(),
+            0xff4f if self.cgb => self.gpu.get_bank() | 0xfe,
+            0xff51..=0xff54 if self.cgb => 0xff,
+            0xff55 if self.cgb => self.read_hdma5(),
+            0xff44 if self.stub_ly => 0x90,
+            0xff40..=0xff4b => self.gpu.read_io_byte(idx),
+            0xff68..=0xff6b if self.cgb => self.gpu.read_io_byte(idx),
+            0xff70 if self.cgb => self.ram.get_svbk() | 0xf8,
 
             // High RAM
             0xff80..=0xfffe => self.hram[(idx - 0xff80) as usize],
@@ -164,7 +692,7 @@ impl MemoryBus {
             0xffff => self.inte,
 
             _ => {
-                // println!("Unhandled Read from Address [{:#04x?}]", idx);
+                log_trace!(Target::Bus, "Unhandled Read from Address [{:#04x?}]", idx);
                 0
             }
         }
@@ -172,6 +700,12 @@ impl MemoryBus {
 
     // write byte to memory map
     pub fn write_byte(&mut self, idx: u16, val: u8) {
+        if self.is_dma_blocked(idx) {
+            return;
+        }
+
+        self.watchpoints.check(idx, val, true);
+
         match idx {
             // Switch ROM Bank
             0x2000 => self.rom.set_bank(val),
@@ -192,29 +726,32 @@ impl MemoryBus {
             0xe000..=0xfdff => self.write_byte(idx - 0x2000, val),
             
             // Sprite Attribute Table (OAM)
-            // 0xfe00..=0xfe9f => 0, // TODO: figure this out
+            0xfe00..=0xfe9f => self.gpu.write_oam_byte(idx - 0xfe00, val),
             
             // Not Usable
             // 0xfea0..=0xfeff => 0, // TODO: figure this out
             
             // I/O Ports
-            0xff00 => self.gpu.write_io_byte(idx, val),
+            0xff00 => {
+                self.joypad.write_io_byte(idx, val);
+                self.receive_sgb_packets();
+            },
             0xff01..=0xff02 => self.serial.write_io_byte(idx, val),
             0xff01..=0xff0e => self.timer.write_io_byte(idx, val),
             0xff0f => self.intf = val,
             0xff10..=0xff3f => self.sound.write_io_byte(idx, val),
-            0xff46 => {
-                // TODO: Fix This
-                // DMA Transfer, 
-                let base_addr : u16 = (val as u16) << 8;
-                let dest_addr : u16 = 0xfe00;
-
-                for i in 0x00..=0x9f {
-                    let source = self.read_byte(base_addr + i);
-                    self.write_byte(dest_addr + i, source);
-                }
-            },
-            0xff40..=0xff4f => self.gpu.write_io_byte(idx, val),
+            0xff46 => self.start_oam_dma(val),
+            0xff4d if self.cgb => self.speed_switch = (val & 0x01) != 0,
+            0xff4f if self.cgb => self.gpu.set_bank(val),
+            0xff50 => if val != 0 { self.unmap_boot_rom() },
+            0xff51 if self.cgb => self.hdma.src = (self.hdma.src & 0x00ff) | ((val as u16) << 8),
+            0xff52 if self.cgb => self.hdma.src = (self.hdma.src & 0xff00) | ((val & 0xf0) as u16),
+            0xff53 if self.cgb => self.hdma.dst = (self.hdma.dst & 0x00ff) | (((val & 0x1f) as u16) << 8),
+            0xff54 if self.cgb => self.hdma.dst = (self.hdma.dst & 0xff00) | ((val & 0xf0) as u16),
+            0xff55 if self.cgb => self.write_hdma5(val),
+            0xff40..=0xff4b => self.gpu.write_io_byte(idx, val),
+            0xff68..=0xff6b if self.cgb => self.gpu.write_io_byte(idx, val),
+            0xff70 if self.cgb => self.ram.set_bank(val),
             
             // High RAM
             0xff80..=0xfffe => self.hram[(idx - 0xff80) as usize] = val,
@@ -223,7 +760,7 @@ impl MemoryBus {
             0xffff => self.inte = val,
 
             _ => {
-                println!("Unhandled Write to Address [{:#04x?}] [val: {:#02x?}]", idx, val);
+                log_debug!(Target::Bus, "Unhandled Write to Address [{:#04x?}] [val: {:#02x?}]", idx, val);
             },
         }
     }
@@ -239,6 +776,13 @@ impl MemoryBus {
         ((h as u16) << 8) | (l as u16)
     }
 
+    pub fn fetch_word(&self, idx: u16) -> u16 {
+        let h = self.fetch_byte(idx.wrapping_add(1));
+        let l = self.fetch_byte(idx.wrapping_add(0));
+
+        ((h as u16) << 8) | (l as u16)
+    }
+
     pub fn write_word(&mut self, idx: u16, val: u16) {
         let h = ((val >> 8) & 0xff) as u8;
         let l = ((val >> 0) & 0xff) as u8;
@@ -248,3 +792,119 @@ impl MemoryBus {
     }
     
 }
+
+impl MemoryBus {
+
+    // Save states, the model has to match and watchpoints stay as they are
+    pub fn sync_state(&mut self, state: &mut State) {
+        self.rom.sync_state(state);
+
+        state.option(&mut self.boot_rom, |state, boot_rom| {
+            state.vec(boot_rom, |state, byte| state.u8(byte));
+        });
+
+        self.gpu.sync_state(state);
+        self.joypad.sync_state(state);
+        self.serial.sync_state(state);
+        self.timer.sync_state(state);
+
+        let mut sgb = self.sgb.is_some();
+        state.bool(&mut sgb);
+        match &mut self.sgb {
+            Some(s) if sgb => s.sync_state(state),
+            None if !sgb => {},
+            _ => return state.fail(String::from("state doesn't match the super gameboy")),
+        }
+
+        state.u8(&mut self.intf);
+        state.u8(&mut self.inte);
+
+        state.bool(&mut self.cgb);
+        state.bool(&mut self.double_speed);
+        state.bool(&mut self.speed_switch);
+
+        self.oam_dma.sync_state(state);
+        self.hdma.sync_state(state);
+
+        self.ram.sync_state(state);
+        state.bytes(&mut self.hram);
+
+        state.u64(&mut self.frame_count);
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gb::testing::{make_cpu, make_rom_bytes};
+
+    use super::*;
+
+    // Step the bus an m-cycle at a time, checking wram can't be read
+    fn assert_blocked(bus: &mut MemoryBus, m_cycles: usize) {
+        for i in 0..m_cycles {
+            bus.step(OAM_DMA_BYTE_CYCLES);
+            assert_eq!(bus.read_byte(0xc000), 0xff, "readable after {} m-cycles", i + 1);
+        }
+    }
+
+    #[test]
+    fn oam_dma_blocks_the_bus_for_160_m_cycles() {
+        let mut cpu = make_cpu("oam-dma", &[0x76]);
+        let bus = &mut cpu.bus;
+        bus.write_byte(0xc000, 0x42);
+
+        bus.write_byte(0xff46, 0xc0);
+        assert_eq!(bus.read_byte(0xc000), 0x42);
+
+        assert_blocked(bus, OAM_DMA_LENGTH as usize);
+        bus.step(OAM_DMA_BYTE_CYCLES);
+        assert_eq!(bus.read_byte(0xc000), 0x42);
+        assert_eq!(bus.gpu.read_oam_byte(0), 0x42);
+    }
+
+    #[test]
+    fn restarting_oam_dma_keeps_the_bus_blocked() {
+        let mut cpu = make_cpu("oam-dma-restart", &[0x76]);
+        let bus = &mut cpu.bus;
+        bus.write_byte(0xc000, 0x42);
+
+        bus.write_byte(0xff46, 0xc0);
+        assert_blocked(bus, 80);
+
+        // the startup m-cycle of the new transfer is still blocked
+        bus.write_byte(0xff46, 0xc0);
+        assert_eq!(bus.read_byte(0xc000), 0xff);
+        assert_blocked(bus, OAM_DMA_LENGTH as usize);
+        bus.step(OAM_DMA_BYTE_CYCLES);
+        assert_eq!(bus.read_byte(0xc000), 0x42);
+    }
+
+    #[test]
+    fn boot_rom_picks_the_model() {
+        // a super gameboy game, but not a color one
+        let mut rom = make_rom_bytes(&[0x76]);
+        rom[0x146] = 0x03;
+        rom[0x14b] = 0x33;
+
+        let bus = MemoryBus::init(Cartridge::from_bytes(&rom), None, None);
+        assert_eq!(bus.model, Model::SGB);
+
+        let bus = MemoryBus::init(Cartridge::from_bytes(&rom), None, Some(vec![0; 0x100]));
+        assert_eq!(bus.model, Model::DMG);
+
+        let bus = MemoryBus::init(Cartridge::from_bytes(&rom), None, Some(vec![0; 0x900]));
+        assert_eq!(bus.model, Model::CGB);
+    }
+
+    #[test]
+    fn stubbed_ly_always_reads_0x90() {
+        let mut cpu = make_cpu("stub-ly", &[0x18, 0xfe]);
+        cpu.bus.stub_ly = true;
+
+        for _ in 0..1000 {
+            cpu.step();
+            assert_eq!(cpu.bus.read_byte(0xff44), 0x90);
+        }
+    }
+}
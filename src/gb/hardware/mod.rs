@@ -1,5 +1,8 @@
 pub mod io;
+pub mod link;
 pub mod cartridge;
+pub mod header;
 pub mod memory_bus;
 pub mod work_ram;
 pub mod registers;
+pub mod sgb;
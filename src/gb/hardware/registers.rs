@@ -1,3 +1,6 @@
+use crate::gb::model::Model;
+use crate::gb::savestate::State;
+
 use std::fmt;
 
 #[derive(Debug, Clone)]
@@ -34,6 +37,30 @@ impl Registers {
             h: 0x00, l: 0x00,
         }
     }
+
+    // Registers as the boot rom leaves them when it jumps to the cartridge
+    pub fn init_post_boot(model: Model, cgb_game: bool) -> Self {
+        let mut r = Self::init();
+        r.pc = 0x0100;
+
+        let (af, bc, de, hl) = match model {
+            Model::DMG => (0x01b0, 0x0013, 0x00d8, 0x014d),
+            Model::MGB => (0xffb0, 0x0013, 0x00d8, 0x014d),
+            Model::SGB => (0x0100, 0x0014, 0x0000, 0xc060),
+            Model::CGB if cgb_game => (0x1180, 0x0000, 0xff56, 0x000d),
+            Model::CGB => (0x1180, 0x0000, 0x0008, 0x007c),
+
+            // games check B to tell a gameboy advance apart
+            Model::AGB if cgb_game => (0x1100, 0x0100, 0xff56, 0x000d),
+            Model::AGB => (0x1100, 0x0100, 0x0008, 0x007c),
+        };
+
+        r.set_af(af);
+        r.set_bc(bc);
+        r.set_de(de);
+        r.set_hl(hl);
+        r
+    }
 }
 
 impl Registers {
@@ -137,4 +164,19 @@ impl fmt::Display for Registers {
             self.h, self.l
         )
     }
-}
\ No newline at end of file
+}
+
+impl Registers {
+
+    // Save states
+    pub fn sync_state(&mut self, state: &mut State) {
+        state.u16(&mut self.pc);
+        state.u16(&mut self.sp);
+
+        for reg in [&mut self.a, &mut self.f, &mut self.b, &mut self.c,
+                    &mut self.d, &mut self.e, &mut self.h, &mut self.l].iter_mut() {
+            state.u8(reg);
+        }
+    }
+
+}
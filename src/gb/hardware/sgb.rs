@@ -0,0 +1,572 @@
+// Super Gameboy
+// https://gbdev.io/pandocs/SGB_Functions.html
+
+use crate::gb::hardware::io::gpu::{rgb555_to_host, LCD_WIDTH, LCD_HEIGHT};
+use crate::gb::hardware::io::joypad::{SGBPacket, SGB_PACKET_SIZE};
+use crate::gb::savestate::State;
+
+// the tv picture, with the gameboy screen in the middle of the border
+pub const SGB_WIDTH: usize = 256;
+pub const SGB_HEIGHT: usize = 224;
+
+const SCREEN_X: usize = 48;
+const SCREEN_Y: usize = 40;
+
+// palettes are picked for each 8x8 cell of the screen
+const ATTR_WIDTH: usize = 20;
+const ATTR_HEIGHT: usize = 18;
+const ATTR_SIZE: usize = ATTR_WIDTH * ATTR_HEIGHT;
+
+// attribute files are 2 bits a cell, 4 cells a byte
+const ATTR_FILES: usize = 45;
+const ATTR_FILE_SIZE: usize = ATTR_SIZE / 4;
+
+const SYSTEM_PALETTES: usize = 512;
+
+// *_TRN commands copy 4kb out of whatever is on the screen
+const TRANSFER_SIZE: usize = 0x1000;
+
+// border is a 32x28 map of 4bpp snes tiles
+const BORDER_TILES: usize = 256;
+const BORDER_TILE_SIZE: usize = 32;
+const BORDER_MAP_WIDTH: usize = 32;
+const BORDER_MAP_HEIGHT: usize = 28;
+
+// PCT_TRN puts the border palettes after the map
+const BORDER_PALETTE_OFFSET: usize = 0x800;
+
+// Commands
+const CMD_PAL01: u8 = 0x00;
+const CMD_PAL23: u8 = 0x01;
+const CMD_PAL03: u8 = 0x02;
+const CMD_PAL12: u8 = 0x03;
+const CMD_ATTR_BLK: u8 = 0x04;
+const CMD_ATTR_LIN: u8 = 0x05;
+const CMD_ATTR_DIV: u8 = 0x06;
+const CMD_ATTR_CHR: u8 = 0x07;
+const CMD_PAL_SET: u8 = 0x0a;
+const CMD_PAL_TRN: u8 = 0x0b;
+const CMD_MLT_REQ: u8 = 0x11;
+const CMD_CHR_TRN: u8 = 0x13;
+const CMD_PCT_TRN: u8 = 0x14;
+const CMD_ATTR_TRN: u8 = 0x15;
+const CMD_ATTR_SET: u8 = 0x16;
+const CMD_MASK_EN: u8 = 0x17;
+
+// MASK_EN modes
+const MASK_FREEZE: u8 = 1;
+const MASK_BLACK: u8 = 2;
+const MASK_COLOR0: u8 = 3;
+
+// colors the super gameboy starts out with
+const DEFAULT_PALETTE: [u16; 4] = [0x67bf, 0x265b, 0x10b5, 0x2866];
+
+// What the next frame gets copied into
+#[derive(Clone, Copy, PartialEq)]
+enum Transfer {
+    Palettes,
+    Tiles(usize),
+    Border,
+    Attributes,
+}
+
+#[derive(Clone)]
+pub struct SGB {
+
+    // commands are ignored unless the cartridge header asks for the super gameboy
+    enabled: bool,
+
+    // packets of the command being received
+    command: Vec<u8>,
+
+    // palettes used on the screen, color 0 is shared by all of them
+    palettes: [[u16; 4]; 4],
+
+    // palettes PAL_SET picks from
+    system_palettes: Vec<[u16; 4]>,
+
+    // palette of each 8x8 cell of the screen
+    attributes: Vec<u8>,
+    attr_files: Vec<u8>,
+
+    mask: u8,
+
+    transfer: Option<Transfer>,
+
+    border_tiles: Vec<u8>,
+    border_map: Vec<u16>,
+    border_palettes: [[u16; 16]; 4],
+
+    players: u8,
+
+    fbuffer: Vec<u32>,
+
+}
+
+impl SGB {
+
+    pub fn init(enabled: bool) -> Self {
+        Self {
+            enabled,
+
+            command: Vec::new(),
+
+            palettes: [DEFAULT_PALETTE; 4],
+
+            system_palettes: vec![DEFAULT_PALETTE; SYSTEM_PALETTES],
+
+            attributes: vec![0; ATTR_SIZE],
+            attr_files: vec![0; ATTR_FILES * ATTR_FILE_SIZE],
+
+            mask: 0,
+
+            transfer: None,
+
+            border_tiles: vec![0; BORDER_TILES * BORDER_TILE_SIZE],
+            border_map: vec![0; BORDER_MAP_WIDTH * BORDER_MAP_HEIGHT],
+            border_palettes: [[0; 16]; 4],
+
+            players: 1,
+
+            fbuffer: vec![rgb555_to_host(DEFAULT_PALETTE[0], false); SGB_WIDTH * SGB_HEIGHT],
+        }
+    }
+
+    // Number of controllers asked for with MLT_REQ
+    pub fn get_players(&self) -> u8 {
+        self.players
+    }
+
+    pub fn get_frame_buffer(&self) -> &[u32] {
+        &self.fbuffer
+    }
+
+}
+
+impl SGB {
+
+    // Commands are 1 to 7 packets long, the first byte
+    // holds the command and how many packets it takes
+    pub fn receive_packet(&mut self, packet: &SGBPacket) {
+        if !self.enabled {
+            return;
+        }
+
+        self.command.extend_from_slice(packet);
+
+        let length = (self.command[0] & 0x07).max(1) as usize;
+        if self.command.len() >= length * SGB_PACKET_SIZE {
+            let command = std::mem::take(&mut self.command);
+            self.run_command(&command);
+        }
+    }
+
+    fn run_command(&mut self, data: &[u8]) {
+        match data[0] >> 3 {
+            CMD_PAL01 => self.set_palette_pair(0, 1, data),
+            CMD_PAL23 => self.set_palette_pair(2, 3, data),
+            CMD_PAL03 => self.set_palette_pair(0, 3, data),
+            CMD_PAL12 => self.set_palette_pair(1, 2, data),
+
+            CMD_ATTR_BLK => self.attr_block(data),
+            CMD_ATTR_LIN => self.attr_line(data),
+            CMD_ATTR_DIV => self.attr_divide(data),
+            CMD_ATTR_CHR => self.attr_char(data),
+
+            CMD_PAL_SET => {
+                for i in 0..4 {
+                    let idx = read_color(data, 1 + i * 2) as usize % SYSTEM_PALETTES;
+                    self.palettes[i] = self.system_palettes[idx];
+                }
+
+                self.set_attr_file(data[9]);
+            },
+
+            CMD_PAL_TRN => self.transfer = Some(Transfer::Palettes),
+
+            CMD_MLT_REQ => {
+                self.players = match data[1] & 0x03 {
+                    1 => 2,
+                    3 => 4,
+                    _ => 1,
+                };
+            },
+
+            CMD_CHR_TRN => {
+                let first = if (data[1] & 0x01) != 0 { BORDER_TILES / 2 } else { 0 };
+                self.transfer = Some(Transfer::Tiles(first));
+            },
+
+            CMD_PCT_TRN => self.transfer = Some(Transfer::Border),
+            CMD_ATTR_TRN => self.transfer = Some(Transfer::Attributes),
+            CMD_ATTR_SET => self.set_attr_file(data[1] | 0x80),
+
+            CMD_MASK_EN => self.mask = data[1] & 0x03,
+
+            // sound and snes programs aren't emulated
+            _ => {},
+        }
+    }
+
+    // PAL01, PAL23, PAL03 and PAL12 set color 0 then colors 1-3 of two palettes
+    fn set_palette_pair(&mut self, a: usize, b: usize, data: &[u8]) {
+        let color0 = read_color(data, 1);
+        for palette in self.palettes.iter_mut() {
+            palette[0] = color0;
+        }
+
+        for i in 1..4 {
+            self.palettes[a][i] = read_color(data, 1 + i * 2);
+            self.palettes[b][i] = read_color(data, 7 + i * 2);
+        }
+    }
+
+    // Bit 7 applies an attribute file (bits 0-5), bit 6 cancels MASK_EN
+    fn set_attr_file(&mut self, val: u8) {
+        let file = (val & 0x3f) as usize;
+
+        if (val & 0x80) != 0 && file < ATTR_FILES {
+            for i in 0..ATTR_SIZE {
+                let byte = self.attr_files[file * ATTR_FILE_SIZE + i / 4];
+                self.attributes[i] = (byte >> (6 - (i % 4) * 2)) & 0x03;
+            }
+        }
+
+        if (val & 0x40) != 0 {
+            self.mask = 0;
+        }
+    }
+
+}
+
+impl SGB {
+
+    // ATTR_BLK colors the inside, edge and outside of rectangles
+    fn attr_block(&mut self, data: &[u8]) {
+        let sets = (data[1] & 0x1f) as usize;
+
+        for set in data[2..].chunks(6).take(sets) {
+            if set.len() < 6 {
+                break;
+            }
+
+            let mut control = set[0] & 0x07;
+            let inside = set[1] & 0x03;
+            let mut edge = (set[1] >> 2) & 0x03;
+            let outside = (set[1] >> 4) & 0x03;
+
+            // only coloring the inside or outside colors the edge along with it
+            if control == 0x01 {
+                edge = inside;
+                control |= 0x02;
+            } else if control == 0x04 {
+                edge = outside;
+                control |= 0x02;
+            }
+
+            let x1 = (set[2] & 0x1f) as usize;
+            let y1 = (set[3] & 0x1f) as usize;
+            let x2 = (set[4] & 0x1f) as usize;
+            let y2 = (set[5] & 0x1f) as usize;
+
+            for y in 0..ATTR_HEIGHT {
+                for x in 0..ATTR_WIDTH {
+                    let within_x = x >= x1 && x <= x2;
+                    let within_y = y >= y1 && y <= y2;
+                    let on_edge = (within_x && (y == y1 || y == y2)) || (within_y && (x == x1 || x == x2));
+
+                    let palette = if on_edge {
+                        if (control & 0x02) != 0 { Some(edge) } else { None }
+                    } else if within_x && within_y {
+                        if (control & 0x01) != 0 { Some(inside) } else { None }
+                    } else {
+                        if (control & 0x04) != 0 { Some(outside) } else { None }
+                    };
+
+                    if let Some(palette) = palette {
+                        self.attributes[y * ATTR_WIDTH + x] = palette;
+                    }
+                }
+            }
+        }
+    }
+
+    // ATTR_LIN colors whole rows or columns
+    fn attr_line(&mut self, data: &[u8]) {
+        let sets = data[1] as usize;
+
+        for &set in data[2..].iter().take(sets) {
+            let line = (set & 0x1f) as usize;
+            let palette = (set >> 5) & 0x03;
+
+            if (set & 0x80) != 0 {
+                if line < ATTR_HEIGHT {
+                    for x in 0..ATTR_WIDTH {
+                        self.attributes[line * ATTR_WIDTH + x] = palette;
+                    }
+                }
+            } else if line < ATTR_WIDTH {
+                for y in 0..ATTR_HEIGHT {
+                    self.attributes[y * ATTR_WIDTH + line] = palette;
+                }
+            }
+        }
+    }
+
+    // ATTR_DIV splits the screen in two along a row or column
+    fn attr_divide(&mut self, data: &[u8]) {
+        let after = data[1] & 0x03;
+        let before = (data[1] >> 2) & 0x03;
+        let on_line = (data[1] >> 4) & 0x03;
+        let horizontal = (data[1] & 0x40) != 0;
+        let line = (data[2] & 0x1f) as usize;
+
+        for y in 0..ATTR_HEIGHT {
+            for x in 0..ATTR_WIDTH {
+                let pos = if horizontal { y } else { x };
+
+                self.attributes[y * ATTR_WIDTH + x] = if pos < line {
+                    before
+                } else if pos == line {
+                    on_line
+                } else {
+                    after
+                };
+            }
+        }
+    }
+
+    // ATTR_CHR sets cells one by one, across or down the screen
+    fn attr_char(&mut self, data: &[u8]) {
+        let mut x = (data[1] & 0x1f) as usize;
+        let mut y = (data[2] & 0x1f) as usize;
+        let count = ((data[3] as usize) | ((data[4] as usize) << 8)).min(ATTR_SIZE);
+        let vertical = (data[5] & 0x01) != 0;
+
+        for i in 0..count {
+            let byte = match data.get(6 + i / 4) {
+                Some(&byte) => byte,
+                None => break,
+            };
+
+            if x < ATTR_WIDTH && y < ATTR_HEIGHT {
+                self.attributes[y * ATTR_WIDTH + x] = (byte >> (6 - (i % 4) * 2)) & 0x03;
+            }
+
+            if vertical {
+                y += 1;
+                if y >= ATTR_HEIGHT { y = 0; x += 1; }
+            } else {
+                x += 1;
+                if x >= ATTR_WIDTH { x = 0; y += 1; }
+            }
+        }
+    }
+
+}
+
+impl SGB {
+
+    // Called with the dmg shades of every finished frame
+    pub fn update_frame(&mut self, shades: &[u8]) {
+        if let Some(transfer) = self.transfer.take() {
+            self.run_transfer(transfer, &read_transfer(shades));
+        }
+
+        self.draw_border();
+        self.draw_screen(shades);
+    }
+
+    fn run_transfer(&mut self, transfer: Transfer, data: &[u8]) {
+        match transfer {
+            Transfer::Palettes => {
+                for (i, palette) in self.system_palettes.iter_mut().enumerate() {
+                    for (c, color) in palette.iter_mut().enumerate() {
+                        *color = read_color(data, i * 8 + c * 2);
+                    }
+                }
+            },
+
+            Transfer::Tiles(first) => {
+                let start = first * BORDER_TILE_SIZE;
+                self.border_tiles[start..(start + TRANSFER_SIZE)].copy_from_slice(data);
+            },
+
+            Transfer::Border => {
+                for (i, entry) in self.border_map.iter_mut().enumerate() {
+                    *entry = read_color(data, i * 2);
+                }
+
+                for (p, palette) in self.border_palettes.iter_mut().enumerate() {
+                    for (c, color) in palette.iter_mut().enumerate() {
+                        *color = read_color(data, BORDER_PALETTE_OFFSET + p * 32 + c * 2);
+                    }
+                }
+            },
+
+            Transfer::Attributes => {
+                let len = self.attr_files.len();
+                self.attr_files.copy_from_slice(&data[..len]);
+            },
+        }
+    }
+
+    fn draw_border(&mut self) {
+        let backdrop = rgb555_to_host(self.palettes[0][0], false);
+
+        for ty in 0..BORDER_MAP_HEIGHT {
+            for tx in 0..BORDER_MAP_WIDTH {
+                let entry = self.border_map[ty * BORDER_MAP_WIDTH + tx];
+
+                // bits 0-7 tile, 10-12 palette (4-7), 14 x flip, 15 y flip
+                let tile = (entry & 0xff) as usize * BORDER_TILE_SIZE;
+                let palette = &self.border_palettes[((entry >> 10) & 0x03) as usize];
+                let x_flip = (entry & (1 << 14)) != 0;
+                let y_flip = (entry & (1 << 15)) != 0;
+
+                for row in 0..8 {
+                    let py = ty * 8 + row;
+                    let tile_row = if y_flip { 7 - row } else { row };
+                    let line = tile + tile_row * 2;
+
+                    for col in 0..8 {
+                        let px = tx * 8 + col;
+
+                        // the gameboy screen covers the middle
+                        if (SCREEN_X..(SCREEN_X + LCD_WIDTH)).contains(&px)
+                            && (SCREEN_Y..(SCREEN_Y + LCD_HEIGHT)).contains(&py) {
+                            continue;
+                        }
+
+                        let bit = if x_flip { col } else { 7 - col };
+                        let planes = [
+                            self.border_tiles[line],
+                            self.border_tiles[line + 1],
+                            self.border_tiles[line + 16],
+                            self.border_tiles[line + 17],
+                        ];
+
+                        let color = planes.iter().enumerate()
+                            .fold(0, |color, (i, plane)| color | (((plane >> bit) & 1) << i));
+
+                        // color 0 shows the backdrop
+                        self.fbuffer[py * SGB_WIDTH + px] = if color == 0 {
+                            backdrop
+                        } else {
+                            rgb555_to_host(palette[color as usize], false)
+                        };
+                    }
+                }
+            }
+        }
+    }
+
+    fn draw_screen(&mut self, shades: &[u8]) {
+        // a frozen screen keeps showing the last frame
+        if self.mask == MASK_FREEZE {
+            return;
+        }
+
+        for y in 0..LCD_HEIGHT {
+            for x in 0..LCD_WIDTH {
+                let shade = shades[y * LCD_WIDTH + x] as usize;
+                let palette = self.attributes[(y / 8) * ATTR_WIDTH + (x / 8)] as usize;
+
+                let rgb = match self.mask {
+                    MASK_BLACK => 0x0000,
+                    MASK_COLOR0 => self.palettes[0][0],
+                    _ if shade == 0 => self.palettes[0][0],
+                    _ => self.palettes[palette][shade],
+                };
+
+                self.fbuffer[(SCREEN_Y + y) * SGB_WIDTH + SCREEN_X + x] = rgb555_to_host(rgb, false);
+            }
+        }
+    }
+
+}
+
+impl SGB {
+
+    // Save states
+    pub fn sync_state(&mut self, state: &mut State) {
+        state.vec(&mut self.command, |state, byte| state.u8(byte));
+
+        for palette in self.palettes.iter_mut() {
+            state.u16s(palette);
+        }
+
+        for palette in self.system_palettes.iter_mut() {
+            state.u16s(palette);
+        }
+
+        state.bytes(&mut self.attributes);
+        state.bytes(&mut self.attr_files);
+
+        state.u8(&mut self.mask);
+
+        // none, then each kind of transfer with the tile bank for CHR_TRN
+        let (mut kind, mut bank) = match self.transfer {
+            None => (0, 0),
+            Some(Transfer::Palettes) => (1, 0),
+            Some(Transfer::Tiles(bank)) => (2, bank),
+            Some(Transfer::Border) => (3, 0),
+            Some(Transfer::Attributes) => (4, 0),
+        };
+
+        state.u8(&mut kind);
+        state.usize(&mut bank);
+
+        self.transfer = match kind {
+            0 => None,
+            1 => Some(Transfer::Palettes),
+            2 => Some(Transfer::Tiles(bank)),
+            3 => Some(Transfer::Border),
+            4 => Some(Transfer::Attributes),
+            _ => return state.fail(format!("unknown super gameboy transfer {}", kind)),
+        };
+
+        state.bytes(&mut self.border_tiles);
+        state.u16s(&mut self.border_map);
+        for palette in self.border_palettes.iter_mut() {
+            state.u16s(palette);
+        }
+
+        state.u8(&mut self.players);
+
+        state.u32s(&mut self.fbuffer);
+    }
+
+}
+
+// Little endian rgb555 color (or any other word) at `idx`
+fn read_color(data: &[u8], idx: usize) -> u16 {
+    (data[idx] as u16) | ((data[idx + 1] as u16) << 8)
+}
+
+// Games send data by showing tiles 0-255 across the screen with BGP 0xe4,
+// so turn the top of the screen back into 2bpp tile data
+fn read_transfer(shades: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(TRANSFER_SIZE);
+
+    for tile in 0..(TRANSFER_SIZE / 16) {
+        let tx = (tile % ATTR_WIDTH) * 8;
+        let ty = (tile / ATTR_WIDTH) * 8;
+
+        for row in 0..8 {
+            let mut low = 0;
+            let mut high = 0;
+
+            for col in 0..8 {
+                let shade = shades[(ty + row) * LCD_WIDTH + tx + col];
+                low |= (shade & 1) << (7 - col);
+                high |= ((shade >> 1) & 1) << (7 - col);
+            }
+
+            data.push(low);
+            data.push(high);
+        }
+    }
+
+    data
+}
@@ -1,13 +1,15 @@
+use crate::gb::savestate::State;
+
 use std::vec::Vec;
 
 const RAM_BANK_NUM: usize = 8;
-const RAM_BANK_SIZE: usize = 0x4000;
+const RAM_BANK_SIZE: usize = 0x1000;
 
 type RAMBank = [u8; RAM_BANK_SIZE];
 
 #[derive(Clone)]
 pub struct WorkRAM {
-    // current work ram bank
+    // bank selected by SVBK, where 0 maps bank 1
     ram_bank: u8,
 
     // ram banks stored in vector due to large memory size
@@ -18,7 +20,7 @@ impl WorkRAM {
 
     pub fn init() -> Self {
         Self {
-            ram_bank: 1,
+            ram_bank: 0,
             ram_banks: vec![[0; RAM_BANK_SIZE]; RAM_BANK_NUM],
         }
     }
@@ -26,13 +28,18 @@ impl WorkRAM {
 }
 
 impl WorkRAM {
-    // Get and set bank location
+    // Bank mapped at 0xd000, 1-7
     pub fn get_bank(&self) -> u8 {
+        self.ram_bank.max(1)
+    }
+
+    // SVBK as written, which reads back 0 even though bank 1 is mapped
+    pub fn get_svbk(&self) -> u8 {
         self.ram_bank
     }
 
     pub fn set_bank(&mut self, b: u8) {
-        self.ram_bank = b;
+        self.ram_bank = b & 0x07;
     }
 
     // Read and Write to RAM
@@ -46,10 +53,69 @@ impl WorkRAM {
 
     // Read and Write to RAM Bank
     pub fn read_bank_byte(&self, idx: u16) -> u8 {
-        self.ram_banks[self.ram_bank as usize][idx as usize]
+        self.ram_banks[self.get_bank() as usize][idx as usize]
     }
 
     pub fn write_bank_byte(&mut self, idx: u16, val: u8) {
-        self.ram_banks[self.ram_bank as usize][idx as usize] = val;
+        let bank = self.get_bank() as usize;
+        self.ram_banks[bank][idx as usize] = val;
+    }
+
+    // Read and Write any bank, including bank 0
+    pub fn read_from_bank(&self, bank: u16, idx: u16) -> u8 {
+        self.ram_banks[bank as usize][idx as usize]
+    }
+
+    pub fn write_to_bank(&mut self, bank: u16, idx: u16, val: u8) {
+        self.ram_banks[bank as usize][idx as usize] = val;
+    }
+}
+
+impl WorkRAM {
+
+    // Save states
+    pub fn sync_state(&mut self, state: &mut State) {
+        state.u8(&mut self.ram_bank);
+        self.ram_bank &= 0x07;
+
+        for bank in self.ram_banks.iter_mut() {
+            state.bytes(bank);
+        }
     }
-}
\ No newline at end of file
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bank_0_maps_bank_1() {
+        let mut ram = WorkRAM::init();
+        ram.set_bank(1);
+        ram.write_bank_byte(0x10, 0x42);
+
+        ram.set_bank(0xf8);
+        assert_eq!(ram.get_svbk(), 0);
+        assert_eq!(ram.get_bank(), 1);
+        assert_eq!(ram.read_bank_byte(0x10), 0x42);
+
+        ram.set_bank(0xff);
+        assert_eq!(ram.get_svbk(), 7);
+        assert_eq!(ram.read_bank_byte(0x10), 0);
+    }
+
+    #[test]
+    fn states_cant_pick_a_missing_bank() {
+        let mut data = vec![0xff];
+        data.extend(std::iter::repeat_n(0, RAM_BANK_NUM * RAM_BANK_SIZE));
+
+        let mut ram = WorkRAM::init();
+        let mut state = State::load(&data);
+        ram.sync_state(&mut state);
+        state.finish().unwrap();
+
+        assert_eq!(ram.get_bank(), 7);
+        ram.write_bank_byte(0, 1);
+    }
+}
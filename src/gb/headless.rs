@@ -0,0 +1,68 @@
+use crate::gb::cpu::CPU;
+use crate::gb::debugger::hooks::Break;
+
+// cycles per second of the dmg clock
+pub const CLOCK_SPEED: usize = 4194304;
+
+// give up on test roms that haven't reported after 2 emulated minutes
+pub const TEST_TIMEOUT_CYCLES: usize = 120 * CLOCK_SPEED;
+
+pub enum TestResult {
+    Passed(String),
+    Failed(String),
+    Timeout(String),
+
+    // a breakpoint or watchpoint was hit before the result came in
+    Stopped(Break, String),
+}
+
+impl TestResult {
+    pub fn get_output(&self) -> &str {
+        match self {
+            TestResult::Passed(out) => out,
+            TestResult::Failed(out) => out,
+            TestResult::Timeout(out) => out,
+            TestResult::Stopped(_, out) => out,
+        }
+    }
+
+    pub fn is_passed(&self) -> bool {
+        matches!(self, TestResult::Passed(_))
+    }
+}
+
+// Run a test rom (blargg style) without a display until it reports
+// its result over the serial port
+pub fn run_test(cpu: &mut CPU, max_cycles: usize) -> TestResult {
+    cpu.bus.serial.enable_capture();
+
+    let mut cycles = 0;
+    let mut printed = 0;
+    while cycles < max_cycles {
+        cycles += cpu.step();
+
+        if let Some(hit) = cpu.hooks.take_break() {
+            return TestResult::Stopped(hit, cpu.get_serial_output());
+        }
+
+        let capture = cpu.bus.serial.get_capture();
+        if capture.len() == printed {
+            continue;
+        }
+        printed = capture.len();
+
+        // results are only checked once the line has finished printing
+        let output = cpu.get_serial_output();
+        if output.ends_with('\n') {
+            if output.contains("Passed") {
+                return TestResult::Passed(output);
+            }
+
+            if output.contains("Failed") {
+                return TestResult::Failed(output);
+            }
+        }
+    }
+
+    TestResult::Timeout(cpu.get_serial_output())
+}
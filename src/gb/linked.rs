@@ -0,0 +1,164 @@
+use crate::gb::cpu::CPU;
+use crate::gb::debugger::hooks::Break;
+use crate::gb::hardware::cartridge::Cartridge;
+use crate::gb::hardware::io::gpu::FRAME_CYCLES;
+use crate::gb::hardware::link::wire::WireEnd;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// Two gameboys connected by a link cable, stepped together so
+// that they never drift apart by more than a single instruction
+pub struct LinkedPair {
+    pub a: CPU,
+    pub b: CPU,
+
+    // total cycles each side has run for
+    cycles_a: usize,
+    cycles_b: usize,
+}
+
+impl LinkedPair {
+
+    pub fn init(rom_a: Cartridge, rom_b: Cartridge) -> Self {
+        Self::connect(CPU::init(rom_a), CPU::init(rom_b))
+    }
+
+    // Plug a link cable between two existing gameboys
+    pub fn connect(mut a: CPU, mut b: CPU) -> Self {
+        let (end_a, end_b) = WireEnd::pair();
+
+        a.bus.serial.connect(Rc::new(RefCell::new(end_a)));
+        b.bus.serial.connect(Rc::new(RefCell::new(end_b)));
+
+        Self {
+            a, b,
+
+            cycles_a: 0,
+            cycles_b: 0,
+        }
+    }
+
+}
+
+impl LinkedPair {
+
+    // Step whichever side is behind, returns the cycles it ran for,
+    // or what stopped it if that side hit a breakpoint or watchpoint
+    pub fn step(&mut self) -> Result<usize, Break> {
+        let (cpu, total) = if self.cycles_a <= self.cycles_b {
+            (&mut self.a, &mut self.cycles_a)
+        } else {
+            (&mut self.b, &mut self.cycles_b)
+        };
+
+        let cycles = cpu.step();
+        *total += cycles;
+
+        match cpu.hooks.take_break() {
+            Some(hit) => Err(hit),
+            None => Ok(cycles),
+        }
+    }
+
+    // Run both sides until they have each run for another `cycles`,
+    // stopping early if either side hits something
+    pub fn run_cycles(&mut self, cycles: usize) -> Result<(), Break> {
+        let target = self.cycles_a.max(self.cycles_b) + cycles;
+
+        while self.cycles_a < target || self.cycles_b < target {
+            self.step()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn run_frames(&mut self, frames: usize) -> Result<(), Break> {
+        self.run_cycles(frames * FRAME_CYCLES)
+    }
+
+    // 0 for side a, 1 for side b
+    pub fn get_side(&self, side: usize) -> &CPU {
+        match side {
+            0 => &self.a,
+            _ => &self.b,
+        }
+    }
+
+    pub fn get_side_mut(&mut self, side: usize) -> &mut CPU {
+        match side {
+            0 => &mut self.a,
+            _ => &mut self.b,
+        }
+    }
+
+    pub fn get_cycles(&self) -> (usize, usize) {
+        (self.cycles_a, self.cycles_b)
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gb::testing::make_cpu;
+
+    // Wait about 16 * `delay` cycles, then send `first` with the given clock
+    // (0x81 internal, 0x80 external), then swap roles and send `second`,
+    // keeping what came back at 0xc000 and 0xc001
+    fn transfer_program(delay: u8, first: u8, first_clock: u8, second: u8, second_clock: u8) -> Vec<u8> {
+        let mut program = vec![
+            0x06, delay.max(1),     // ld b, delay
+            0x05,                   // dec b
+            0x20, 0xfd,             // jr nz, -3
+        ];
+
+        for &(out, clock, store) in [(first, first_clock, 0x00), (second, second_clock, 0x01)].iter() {
+            program.extend_from_slice(&[
+                0x3e, out,          // ld a, out
+                0xe0, 0x01,         // ldh (SB), a
+                0x3e, clock,        // ld a, clock
+                0xe0, 0x02,         // ldh (SC), a
+                0xf0, 0x02,         // ldh a, (SC)
+                0xe6, 0x80,         // and 0x80
+                0x20, 0xfa,         // jr nz, -6
+                0xf0, 0x01,         // ldh a, (SB)
+                0xea, store, 0xc0,  // ld (0xc0xx), a
+            ]);
+        }
+
+        program.extend_from_slice(&[0x18, 0xfe]); // jr -2
+        program
+    }
+
+    // The slave starts listening anywhere from well before the master's
+    // transfer ends to a few cycles before it does
+    #[test]
+    fn exchanges_bytes_both_ways() {
+        for delay in (0..200).step_by(25).chain(200..=255) {
+            let a = make_cpu("link-a", &transfer_program(1, 0x12, 0x81, 0x34, 0x80));
+            let b = make_cpu("link-b", &transfer_program(delay as u8, 0x56, 0x80, 0x78, 0x81));
+
+            let mut pair = LinkedPair::connect(a, b);
+            pair.run_frames(2).unwrap();
+
+            assert_eq!(pair.a.bus.read_byte(0xc000), 0x56, "delay {}", delay);
+            assert_eq!(pair.b.bus.read_byte(0xc000), 0x12, "delay {}", delay);
+
+            assert_eq!(pair.a.bus.read_byte(0xc001), 0x78, "delay {}", delay);
+            assert_eq!(pair.b.bus.read_byte(0xc001), 0x34, "delay {}", delay);
+        }
+    }
+
+    #[test]
+    fn stops_when_either_side_hits_a_breakpoint() {
+        let a = make_cpu("link-break-a", &[0x18, 0xfe]);
+        let b = make_cpu("link-break-b", &[0x00, 0x18, 0xfd]);
+
+        let mut pair = LinkedPair::connect(a, b);
+        pair.b.hooks.add_breakpoint(0x151);
+
+        assert_eq!(pair.run_frames(1), Err(Break::Breakpoint(0x151)));
+        assert_eq!(pair.b.reg.pc, 0x151);
+    }
+}
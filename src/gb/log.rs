@@ -0,0 +1,158 @@
+// Diagnostics for each part of the emulator, all off unless turned on
+// with set_level or a filter like "cpu=trace,bus=warn" (--log)
+
+use std::sync::atomic::{AtomicU8, Ordering};
+
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum Level {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "off" => Some(Level::Off),
+            "error" => Some(Level::Error),
+            "warn" => Some(Level::Warn),
+            "info" => Some(Level::Info),
+            "debug" => Some(Level::Debug),
+            "trace" => Some(Level::Trace),
+            _ => None,
+        }
+    }
+
+    fn from_u8(val: u8) -> Self {
+        match val {
+            1 => Level::Error,
+            2 => Level::Warn,
+            3 => Level::Info,
+            4 => Level::Debug,
+            5 => Level::Trace,
+            _ => Level::Off,
+        }
+    }
+
+    fn get_name(&self) -> &'static str {
+        match self {
+            Level::Off => "OFF",
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Target {
+    Cpu,
+    Bus,
+    Ppu,
+    Timer,
+    Serial,
+    Apu,
+}
+
+pub const TARGETS: [Target; 6] = [
+    Target::Cpu, Target::Bus, Target::Ppu, Target::Timer, Target::Serial, Target::Apu,
+];
+
+impl Target {
+    pub fn from_name(name: &str) -> Option<Self> {
+        TARGETS.iter().copied().find(|target| target.get_name() == name.to_lowercase())
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Target::Cpu => "cpu",
+            Target::Bus => "bus",
+            Target::Ppu => "ppu",
+            Target::Timer => "timer",
+            Target::Serial => "serial",
+            Target::Apu => "apu",
+        }
+    }
+}
+
+// level of each target, indexed in the order of TARGETS
+static LEVELS: [AtomicU8; 6] = [
+    AtomicU8::new(0), AtomicU8::new(0), AtomicU8::new(0),
+    AtomicU8::new(0), AtomicU8::new(0), AtomicU8::new(0),
+];
+
+pub fn set_level(target: Target, level: Level) {
+    LEVELS[target as usize].store(level as u8, Ordering::Relaxed);
+}
+
+pub fn get_level(target: Target) -> Level {
+    Level::from_u8(LEVELS[target as usize].load(Ordering::Relaxed))
+}
+
+pub fn is_enabled(target: Target, level: Level) -> bool {
+    level != Level::Off && level <= get_level(target)
+}
+
+// Set levels from a comma separated list of target=level,
+// where "all" sets every target and a bare level means all=level
+pub fn set_filter(filter: &str) -> Result<(), String> {
+    for part in filter.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+        let (target, level) = match part.find('=') {
+            Some(i) => (&part[..i], &part[(i + 1)..]),
+            None => ("all", part),
+        };
+
+        let level = Level::from_name(level)
+            .ok_or_else(|| format!("Unknown log level {} [expected off, error, warn, info, debug or trace]", level))?;
+
+        if target == "all" {
+            for &target in TARGETS.iter() {
+                set_level(target, level);
+            }
+        } else {
+            let target = Target::from_name(target)
+                .ok_or_else(|| format!("Unknown log target {} [expected cpu, bus, ppu, timer, serial, apu or all]", target))?;
+            set_level(target, level);
+        }
+    }
+
+    Ok(())
+}
+
+pub fn write(target: Target, level: Level, args: std::fmt::Arguments) {
+    eprintln!("[{} {}] {}", target.get_name(), level.get_name(), args);
+}
+
+// log!(Target::Cpu, Level::Debug, "format", args..)
+macro_rules! log {
+    ($target:expr, $level:expr, $($arg:tt)+) => {
+        if $crate::gb::log::is_enabled($target, $level) {
+            $crate::gb::log::write($target, $level, format_args!($($arg)+));
+        }
+    };
+}
+
+macro_rules! log_error {
+    ($target:expr, $($arg:tt)+) => { log!($target, $crate::gb::log::Level::Error, $($arg)+) };
+}
+
+macro_rules! log_warn {
+    ($target:expr, $($arg:tt)+) => { log!($target, $crate::gb::log::Level::Warn, $($arg)+) };
+}
+
+macro_rules! log_info {
+    ($target:expr, $($arg:tt)+) => { log!($target, $crate::gb::log::Level::Info, $($arg)+) };
+}
+
+macro_rules! log_debug {
+    ($target:expr, $($arg:tt)+) => { log!($target, $crate::gb::log::Level::Debug, $($arg)+) };
+}
+
+macro_rules! log_trace {
+    ($target:expr, $($arg:tt)+) => { log!($target, $crate::gb::log::Level::Trace, $($arg)+) };
+}
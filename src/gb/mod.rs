@@ -1,3 +1,21 @@
+#[macro_use]
+pub mod log;
+
 pub mod cpu;
+pub mod debugger;
+pub mod disasm;
+pub mod headless;
 pub mod hardware;
-pub mod opcodes;
\ No newline at end of file
+pub mod linked;
+pub mod model;
+pub mod movie;
+pub mod opcodes;
+pub mod png;
+pub mod rewind;
+pub mod savestate;
+pub mod symbols;
+pub mod toml;
+pub mod trace;
+pub mod ui;
+#[cfg(test)]
+pub mod testing;
@@ -0,0 +1,57 @@
+use crate::gb::hardware::cartridge::Cartridge;
+
+// Gameboy hardware revisions
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Model {
+    DMG, // Gameboy
+    MGB, // Gameboy Pocket
+    SGB, // Super Gameboy
+    CGB, // Gameboy Color
+    AGB, // Gameboy Advance
+}
+
+impl Model {
+
+    // Pick the model a cartridge was made for
+    pub fn from_cartridge(rom: &Cartridge) -> Self {
+        if rom.is_cgb() {
+            Model::CGB
+        } else if rom.is_sgb() {
+            Model::SGB
+        } else {
+            Model::DMG
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "dmg" => Some(Model::DMG),
+            "mgb" => Some(Model::MGB),
+            "sgb" => Some(Model::SGB),
+            "cgb" => Some(Model::CGB),
+            "agb" => Some(Model::AGB),
+            _ => None,
+        }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Model::DMG => "dmg",
+            Model::MGB => "mgb",
+            Model::SGB => "sgb",
+            Model::CGB => "cgb",
+            Model::AGB => "agb",
+        }
+    }
+
+    // Gameboy Color hardware, which the gameboy advance also has
+    pub fn is_cgb(&self) -> bool {
+        *self == Model::CGB || *self == Model::AGB
+    }
+
+    // Size of the boot rom mapped over the start of the cartridge
+    pub fn get_boot_rom_size(&self) -> usize {
+        if self.is_cgb() { 0x900 } else { 0x100 }
+    }
+
+}
@@ -0,0 +1,289 @@
+// Input movies, the buttons held each frame from a known starting point,
+// for reproducing bugs and regression testing.
+//
+// Nothing in the emulator core looks at the clock, so starting from the same
+// state and feeding the same buttons before each frame plays back exactly.
+// Power on movies start with blank cartridge ram rather than a battery save.
+//
+//   "SMBM" version
+//   rom crc32 (u32), model name (u8 length + bytes)
+//   start: 0 power on, 1 power on with a boot rom (u32 crc32 of it),
+//          2 save state (u32 length + state)
+//   then one byte of buttons (joypad BUTTON_* bits) for every frame
+//
+// All numbers are little endian. Frames are appended as they're recorded and
+// written out every second, so a movie is playable up to about then even if
+// the emulator doesn't get to close it.
+
+use crate::gb::cpu::CPU;
+use crate::gb::model::Model;
+use crate::gb::png::crc32;
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"SMBM";
+const VERSION: u8 = 1;
+
+const START_POWER_ON: u8 = 0;
+const START_BOOT_ROM: u8 = 1;
+const START_STATE: u8 = 2;
+
+// frames recorded between writing them out to the file
+const FLUSH_FRAMES: usize = 60;
+
+// Where a movie starts from
+#[derive(Clone, Debug, PartialEq)]
+pub enum Start {
+    PowerOn,
+    // crc32 of the boot rom
+    BootRom(u32),
+    State(Vec<u8>),
+}
+
+#[derive(Clone, Debug)]
+pub struct Movie {
+    pub checksum: u32,
+    pub model: Model,
+    pub start: Start,
+
+    // buttons for each frame
+    pub inputs: Vec<u8>,
+}
+
+impl Movie {
+
+    // An empty movie for the rom in `cpu`
+    pub fn init(cpu: &CPU, start: Start) -> Self {
+        Self {
+            checksum: cpu.bus.rom.get_checksum(),
+            model: cpu.get_model(),
+            start,
+
+            inputs: Vec::new(),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let data = fs::read(path).map_err(|e| format!("can't open movie {}: {}", path.display(), e))?;
+        Self::decode(&data).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, String> {
+        let mut pos = 0;
+        let mut take = |len: usize| -> Result<&[u8], String> {
+            let bytes = data.get(pos..(pos + len)).ok_or("movie ends early")?;
+            pos += len;
+            Ok(bytes)
+        };
+
+        if take(4)? != MAGIC {
+            return Err(String::from("not a movie"));
+        }
+
+        let version = take(1)?[0];
+        if version != VERSION {
+            return Err(format!("movie is version {}, expected {}", version, VERSION));
+        }
+
+        let checksum = read_u32(take(4)?);
+
+        let len = take(1)?[0] as usize;
+        let name = String::from_utf8_lossy(take(len)?).into_owned();
+        let model = Model::from_name(&name).ok_or_else(|| format!("unknown model {} in movie", name))?;
+
+        let start = match take(1)?[0] {
+            START_POWER_ON => Start::PowerOn,
+            START_BOOT_ROM => Start::BootRom(read_u32(take(4)?)),
+            START_STATE => {
+                let len = read_u32(take(4)?) as usize;
+                Start::State(take(len)?.to_vec())
+            },
+            start => return Err(format!("unknown movie start {}", start)),
+        };
+
+        let inputs = data[pos..].to_vec();
+
+        Ok(Self { checksum, model, start, inputs })
+    }
+
+    // Everything but the inputs
+    pub fn encode_header(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(MAGIC);
+        data.push(VERSION);
+
+        data.extend_from_slice(&self.checksum.to_le_bytes());
+
+        let name = self.model.get_name();
+        data.push(name.len() as u8);
+        data.extend_from_slice(name.as_bytes());
+
+        match &self.start {
+            Start::PowerOn => data.push(START_POWER_ON),
+
+            Start::BootRom(crc) => {
+                data.push(START_BOOT_ROM);
+                data.extend_from_slice(&crc.to_le_bytes());
+            },
+
+            Start::State(state) => {
+                data.push(START_STATE);
+                data.extend_from_slice(&(state.len() as u32).to_le_bytes());
+                data.extend_from_slice(state);
+            },
+        }
+
+        data
+    }
+
+    // Make sure the movie was recorded with this rom and boot rom
+    pub fn check(&self, cpu: &CPU, boot_rom: Option<&[u8]>) -> Result<(), String> {
+        if self.checksum != cpu.bus.rom.get_checksum() {
+            return Err(String::from("movie was recorded with a different rom"));
+        }
+
+        if self.model != cpu.get_model() {
+            return Err(format!("movie was recorded on {}, not {}", self.model.get_name(), cpu.get_model().get_name()));
+        }
+
+        match (&self.start, boot_rom) {
+            (Start::BootRom(crc), Some(boot_rom)) if *crc == crc32(boot_rom) => Ok(()),
+            (Start::BootRom(_), Some(_)) => Err(String::from("movie was recorded with a different boot rom")),
+            (Start::BootRom(_), None) => Err(String::from("movie starts from a boot rom, use --boot-rom")),
+            (_, Some(_)) => Err(String::from("movie doesn't start from a boot rom")),
+            _ => Ok(()),
+        }
+    }
+
+    // Put a freshly made cpu where the movie starts
+    pub fn start(&self, cpu: &mut CPU) -> Result<(), String> {
+        match &self.start {
+            Start::State(state) => cpu.load_state(state).map_err(|e| format!("movie's start state: {}", e)),
+            _ => Ok(()),
+        }
+    }
+
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+////////// RECORDING AND PLAYBACK //////////
+
+// Writes a movie out a frame at a time
+pub struct MovieWriter {
+    file: BufWriter<File>,
+    frames: usize,
+}
+
+impl MovieWriter {
+
+    pub fn create(path: &Path, movie: &Movie) -> io::Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(&movie.encode_header())?;
+        file.write_all(&movie.inputs)?;
+
+        Ok(Self {
+            file,
+            frames: movie.inputs.len(),
+        })
+    }
+
+    pub fn write_frame(&mut self, buttons: u8) -> io::Result<()> {
+        self.frames += 1;
+        self.file.write_all(&[buttons])?;
+
+        if self.frames.is_multiple_of(FLUSH_FRAMES) {
+            self.file.flush()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn get_frames(&self) -> usize {
+        self.frames
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+
+}
+
+// Hands out a movie's inputs a frame at a time
+pub struct MoviePlayer {
+    movie: Movie,
+    frame: usize,
+}
+
+impl MoviePlayer {
+
+    pub fn init(movie: Movie) -> Self {
+        Self {
+            movie,
+            frame: 0,
+        }
+    }
+
+    // Buttons for the next frame, None once the movie is over
+    pub fn next_frame(&mut self) -> Option<u8> {
+        let buttons = *self.movie.inputs.get(self.frame)?;
+        self.frame += 1;
+        Some(buttons)
+    }
+
+    pub fn get_length(&self) -> usize {
+        self.movie.inputs.len()
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gb::testing::make_cpu;
+
+    use std::env;
+
+    // keep storing the buttons to $c000
+    // ld a, $10; ldh ($00), a; ldh a, ($00); ld ($c000), a; jr -11
+    const PROGRAM: [u8; 11] = [0x3e, 0x10, 0xe0, 0x00, 0xf0, 0x00, 0xea, 0x00, 0xc0, 0x18, 0xf5];
+
+    #[test]
+    fn playback_matches_recording() {
+        let path = env::temp_dir().join(format!("samb_gb-test-movie-{}.smbm", std::process::id()));
+
+        let mut cpu = make_cpu("movie-record", &PROGRAM);
+        let start = Start::State(cpu.save_state());
+        let movie = Movie::init(&cpu, start);
+        let mut writer = MovieWriter::create(&path, &movie).unwrap();
+
+        for frame in 0..(2 * FLUSH_FRAMES) {
+            let buttons = (frame * 7 % 256) as u8;
+            writer.write_frame(buttons).unwrap();
+            cpu.bus.joypad.set_buttons(buttons);
+            cpu.run_until_vblank().unwrap();
+        }
+
+        // read back before the writer is closed, it's already been flushed
+        let movie = Movie::load(&path).unwrap();
+        assert_eq!(movie.inputs.len(), 2 * FLUSH_FRAMES);
+        drop(writer);
+
+        let mut other = make_cpu("movie-play", &PROGRAM);
+        movie.check(&other, None).unwrap();
+        movie.start(&mut other).unwrap();
+
+        let mut player = MoviePlayer::init(movie);
+        while let Some(buttons) = player.next_frame() {
+            other.bus.joypad.set_buttons(buttons);
+            other.run_until_vblank().unwrap();
+        }
+
+        assert!(other.save_state() == cpu.save_state());
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -6,6 +6,11 @@ pub fn nop(_: &mut CPU) -> usize {
 }
 
 pub fn stop(cpu: &mut CPU) -> usize {
+    // on the gameboy color stop also switches cpu speed
+    if cpu.bus.switch_speed() {
+        return 4;
+    }
+
     // halt CPU & LCD display until button pressed.
     if cpu.interrupts {
         cpu.stopped = true;
@@ -1,4 +1,5 @@
 use crate::gb::cpu::CPU;
+use crate::gb::log::Target;
 
 pub const UNKNOWN_RETURN_CODE : usize = usize::MAX;
 
@@ -8,6 +9,6 @@ pub fn unknown(_: &mut CPU) -> usize {
 }    
 
 pub fn unused(_: &mut CPU) -> usize {
-    println!("WARNING! Unused OPCode was executed!");
+    log_warn!(Target::Cpu, "Unused OPCode was executed!");
     0
 }
\ No newline at end of file
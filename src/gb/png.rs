@@ -0,0 +1,126 @@
+// Minimal PNG writer (8 bit rgb, stored deflate blocks)
+// https://www.w3.org/TR/PNG/
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+
+// largest block deflate can store without compression
+const STORED_BLOCK_SIZE: usize = 0xffff;
+
+// Write a 0x00RRGGBB image to a png file
+pub fn write_png(path: &Path, width: usize, height: usize, pixels: &[u32]) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    file.write_all(&encode_png(width, height, pixels))
+}
+
+// Encode a 0x00RRGGBB image as a png
+pub fn encode_png(width: usize, height: usize, pixels: &[u32]) -> Vec<u8> {
+    assert_eq!(pixels.len(), width * height, "png size doesn't match pixels");
+
+    let mut png = Vec::new();
+    png.extend_from_slice(&SIGNATURE);
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    header.push(8); // bit depth
+    header.push(2); // color type (rgb)
+    header.push(0); // compression
+    header.push(0); // filter
+    header.push(0); // interlace
+    write_chunk(&mut png, b"IHDR", &header);
+
+    // every scanline starts with its filter type (none)
+    let mut raw = Vec::with_capacity(height * (width * 3 + 1));
+    for y in 0..height {
+        raw.push(0);
+
+        for x in 0..width {
+            let pixel = pixels[y * width + x];
+            raw.push((pixel >> 16) as u8);
+            raw.push((pixel >> 8) as u8);
+            raw.push(pixel as u8);
+        }
+    }
+    write_chunk(&mut png, b"IDAT", &zlib_store(&raw));
+
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+// Scale an image up by an integer factor
+pub fn upscale(width: usize, height: usize, pixels: &[u32], scale: usize) -> Vec<u32> {
+    let mut out = Vec::with_capacity(width * height * scale * scale);
+
+    for y in 0..(height * scale) {
+        for x in 0..(width * scale) {
+            out.push(pixels[(y / scale) * width + (x / scale)]);
+        }
+    }
+
+    out
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+// zlib stream made of uncompressed deflate blocks
+fn zlib_store(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+
+    let mut blocks = data.chunks(STORED_BLOCK_SIZE).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[0x01, 0x00, 0x00, 0xff, 0xff]);
+    }
+
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+
+        out.push(if last { 0x01 } else { 0x00 });
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffffffff_u32;
+
+    for &byte in data {
+        crc ^= byte as u32;
+
+        for _ in 0..8 {
+            let mask = (!(crc & 1)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0xedb88320 & mask);
+        }
+    }
+
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let mut a = 1_u32;
+    let mut b = 0_u32;
+
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+
+    (b << 16) | a
+}
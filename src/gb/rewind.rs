@@ -0,0 +1,270 @@
+// Rewind buffer, a save state every few frames kept for a number of seconds.
+//
+// Only the newest state is kept whole. Every older one is stored as the bytes
+// that changed between it and the state after it (xor, then run length
+// encoded), which is mostly zeros from one frame to the next, so going back
+// a step is undoing the newest delta.
+//
+// The buttons for every frame are kept too, so going back a single frame
+// between states is loading the one before and running the frames up to it.
+
+use crate::gb::headless::CLOCK_SPEED;
+use crate::gb::hardware::io::gpu::FRAME_CYCLES;
+
+use std::collections::VecDeque;
+
+pub struct Rewind {
+    // frames between states
+    interval: usize,
+
+    // deltas kept, older ones are dropped
+    capacity: usize,
+
+    latest: Option<Vec<u8>>,
+
+    // buttons for each frame run since the latest state
+    inputs: Vec<u8>,
+
+    // oldest first, each turns the state after it back into the one before,
+    // with the buttons for the frames run in between
+    deltas: VecDeque<(Vec<u8>, Vec<u8>)>,
+}
+
+impl Rewind {
+
+    // Keep `seconds` of states taken every `interval` frames
+    pub fn init(seconds: usize, interval: usize) -> Self {
+        let interval = interval.max(1);
+        let frames_per_second = CLOCK_SPEED.div_ceil(FRAME_CYCLES);
+
+        Self {
+            interval,
+            capacity: seconds * frames_per_second / interval,
+
+            latest: None,
+            inputs: Vec::new(),
+            deltas: VecDeque::new(),
+        }
+    }
+
+    // Count a frame run with `buttons` held, true when it's time to push a state
+    pub fn tick(&mut self, buttons: u8) -> bool {
+        self.inputs.push(buttons);
+        self.inputs.len() >= self.interval
+    }
+
+    pub fn push(&mut self, state: Vec<u8>) {
+        let inputs = std::mem::take(&mut self.inputs);
+
+        if let Some(latest) = self.latest.take() {
+            self.deltas.push_back((encode_delta(&latest, &state), inputs));
+
+            while self.deltas.len() > self.capacity {
+                self.deltas.pop_front();
+            }
+        }
+
+        self.latest = Some(state);
+    }
+
+    // Go back a frame, giving the state to load and the buttons for the frames
+    // to run after it to get there. None once there's nothing older left
+    pub fn pop(&mut self) -> Option<(Vec<u8>, Vec<u8>)> {
+        if self.inputs.is_empty() {
+            let (delta, inputs) = self.deltas.pop_back()?;
+            let latest = self.latest.as_ref()?;
+
+            self.latest = Some(decode_delta(latest, &delta));
+            self.inputs = inputs;
+        }
+
+        self.inputs.pop();
+        Some((self.latest.clone()?, self.inputs.clone()))
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.inputs.clear();
+        self.deltas.clear();
+    }
+
+    // Bytes held, for keeping an eye on the compression
+    pub fn get_size(&self) -> usize {
+        let deltas: usize = self.deltas.iter().map(|(delta, inputs)| delta.len() + inputs.len()).sum();
+        self.latest.as_ref().map_or(0, Vec::len) + self.inputs.len() + deltas
+    }
+
+}
+
+////////// DELTAS //////////
+//
+// length of `old`, then pairs of (bytes unchanged, bytes changed) as
+// varints, each followed by the changed bytes xored with `new`
+
+fn encode_delta(old: &[u8], new: &[u8]) -> Vec<u8> {
+    let len = old.len().max(new.len());
+    let xor = |i: usize| old.get(i).copied().unwrap_or(0) ^ new.get(i).copied().unwrap_or(0);
+
+    let mut delta = Vec::new();
+    write_varint(&mut delta, old.len());
+
+    let mut i = 0;
+    while i < len {
+        let start = i;
+        while i < len && xor(i) == 0 {
+            i += 1;
+        }
+
+        let same = i - start;
+        if i == len {
+            break;
+        }
+
+        // runs of changed bytes end at a few unchanged ones,
+        // shorter gaps are cheaper to store as changes
+        let start = i;
+        while i < len && (xor(i) != 0 || (i + 3 < len && (1..4).any(|n| xor(i + n) != 0))) {
+            i += 1;
+        }
+
+        write_varint(&mut delta, same);
+        write_varint(&mut delta, i - start);
+        delta.extend((start..i).map(xor));
+    }
+
+    delta
+}
+
+fn decode_delta(new: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut pos = 0;
+    let len = read_varint(delta, &mut pos);
+
+    let mut old = new.to_vec();
+    old.resize(len.max(new.len()), 0);
+
+    let mut i = 0;
+    while pos < delta.len() {
+        i += read_varint(delta, &mut pos);
+        let changed = read_varint(delta, &mut pos);
+
+        for &byte in &delta[pos..(pos + changed)] {
+            old[i] ^= byte;
+            i += 1;
+        }
+
+        pos += changed;
+    }
+
+    old.truncate(len);
+    old
+}
+
+// 7 bits at a time, low first, top bit set when more follow
+fn write_varint(out: &mut Vec<u8>, mut n: usize) {
+    while n >= 0x80 {
+        out.push((n as u8 & 0x7f) | 0x80);
+        n >>= 7;
+    }
+
+    out.push(n as u8);
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> usize {
+    let mut n = 0;
+    let mut shift = 0;
+
+    loop {
+        let byte = data[*pos];
+        *pos += 1;
+
+        n |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+
+        if byte & 0x80 == 0 {
+            return n;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // xorshift, so the tests don't need a random number crate
+    fn random_bytes(seed: &mut u32, len: usize) -> Vec<u8> {
+        (0..len).map(|_| {
+            *seed ^= *seed << 13;
+            *seed ^= *seed >> 17;
+            *seed ^= *seed << 5;
+            *seed as u8
+        }).collect()
+    }
+
+    fn round_trip(old: &[u8], new: &[u8]) {
+        let delta = encode_delta(old, new);
+        assert!(decode_delta(new, &delta) == old);
+    }
+
+    #[test]
+    fn deltas_of_random_bytes() {
+        let mut seed = 0x1234_5678;
+
+        for len in [0, 1, 5, 127, 128, 300, 0x4000] {
+            let old = random_bytes(&mut seed, len);
+            let new = random_bytes(&mut seed, len);
+            round_trip(&old, &new);
+        }
+
+        // states can change length
+        let old = random_bytes(&mut seed, 200);
+        let new = random_bytes(&mut seed, 150);
+        round_trip(&old, &new);
+        round_trip(&new, &old);
+    }
+
+    #[test]
+    fn deltas_of_sparse_changes() {
+        let mut seed = 0x9abc_def0;
+        let old = random_bytes(&mut seed, 0x10000);
+
+        for step in [1, 2, 3, 4, 5, 200, 0x3fff] {
+            let mut new = old.clone();
+            for i in (0..new.len()).step_by(step) {
+                new[i] ^= 0x5a;
+            }
+            round_trip(&old, &new);
+        }
+
+        // nothing, the first and the last byte changed
+        round_trip(&old, &old);
+        let mut new = old.clone();
+        new[0] ^= 1;
+        new[0xffff] ^= 1;
+        round_trip(&old, &new);
+
+        // unchanged runs long enough to need multi byte varints
+        let delta = encode_delta(&old, &new);
+        assert!(delta.len() < 16);
+    }
+
+    #[test]
+    fn pops_a_frame_at_a_time() {
+        let mut rewind = Rewind::init(1, 3);
+        rewind.push(vec![0]);
+
+        for frame in 1..=6 {
+            if rewind.tick(frame) {
+                rewind.push(vec![frame]);
+            }
+        }
+
+        // back from frame 6 to 5 is frame 3 with 4 and 5 run after it
+        assert_eq!(rewind.pop(), Some((vec![3], vec![4, 5])));
+        assert_eq!(rewind.pop(), Some((vec![3], vec![4])));
+        assert_eq!(rewind.pop(), Some((vec![3], vec![])));
+        assert_eq!(rewind.pop(), Some((vec![0], vec![1, 2])));
+        assert_eq!(rewind.pop(), Some((vec![0], vec![1])));
+        assert_eq!(rewind.pop(), Some((vec![0], vec![])));
+        assert_eq!(rewind.pop(), None);
+    }
+}
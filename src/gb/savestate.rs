@@ -0,0 +1,268 @@
+// Save states, everything that changes as a game runs written out as bytes.
+//
+// Each part of the hardware has a sync_state method that goes through its
+// fields in order, and the same method both saves and loads so the two can't
+// drift apart. States only load into a CPU made from the same rom, which keeps
+// its rom, link cable, debugger hooks and trace.
+
+const MAGIC: &[u8; 4] = b"SMBS";
+
+// bump when the order or meaning of any field changes
+const VERSION: u8 = 1;
+
+enum Mode<'a> {
+    Save(Vec<u8>),
+    Load(&'a [u8], usize),
+}
+
+pub struct State<'a> {
+    mode: Mode<'a>,
+
+    // first thing that went wrong loading, the rest of the fields are skipped
+    error: Option<String>,
+}
+
+impl<'a> State<'a> {
+
+    pub fn save() -> Self {
+        Self {
+            mode: Mode::Save(Vec::new()),
+            error: None,
+        }
+    }
+
+    pub fn load(data: &'a [u8]) -> Self {
+        Self {
+            mode: Mode::Load(data, 0),
+            error: None,
+        }
+    }
+
+    pub fn is_loading(&self) -> bool {
+        match self.mode {
+            Mode::Load(..) => true,
+            Mode::Save(_) => false,
+        }
+    }
+
+    // Stop loading, with a reason
+    pub fn fail(&mut self, error: String) {
+        if self.error.is_none() {
+            self.error = Some(error);
+        }
+    }
+
+    // The saved bytes, or whether everything loaded
+    pub fn finish(self) -> Result<Vec<u8>, String> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+
+        match self.mode {
+            Mode::Save(data) => Ok(data),
+            Mode::Load(data, pos) if pos == data.len() => Ok(Vec::new()),
+            Mode::Load(..) => Err(String::from("state has data left over")),
+        }
+    }
+
+    // The first thing in every state, so the wrong file or
+    // a state from another version or rom isn't loaded
+    pub fn header(&mut self, checksum: u16, model: u8) {
+        let mut magic = *MAGIC;
+        self.bytes(&mut magic);
+        if &magic != MAGIC {
+            return self.fail(String::from("not a save state"));
+        }
+
+        let mut version = VERSION;
+        self.u8(&mut version);
+        if version != VERSION {
+            return self.fail(format!("state is version {}, expected {}", version, VERSION));
+        }
+
+        let mut saved = checksum;
+        self.u16(&mut saved);
+        if saved != checksum {
+            return self.fail(String::from("state is from a different rom"));
+        }
+
+        let mut saved = model;
+        self.u8(&mut saved);
+        if saved != model {
+            self.fail(String::from("state is from a different hardware model"));
+        }
+    }
+
+}
+
+impl<'a> State<'a> {
+
+    // Copy `val` out when saving, or fill it in when loading
+    pub fn bytes(&mut self, val: &mut [u8]) {
+        if self.error.is_some() {
+            return;
+        }
+
+        match &mut self.mode {
+            Mode::Save(data) => data.extend_from_slice(val),
+
+            Mode::Load(data, pos) => match data.get(*pos..(*pos + val.len())) {
+                Some(bytes) => {
+                    val.copy_from_slice(bytes);
+                    *pos += val.len();
+                },
+
+                None => self.error = Some(String::from("state ends early")),
+            },
+        }
+    }
+
+    pub fn u8(&mut self, val: &mut u8) {
+        let mut bytes = [*val];
+        self.bytes(&mut bytes);
+        *val = bytes[0];
+    }
+
+    pub fn u16(&mut self, val: &mut u16) {
+        let mut bytes = val.to_le_bytes();
+        self.bytes(&mut bytes);
+        *val = u16::from_le_bytes(bytes);
+    }
+
+    pub fn u32(&mut self, val: &mut u32) {
+        let mut bytes = val.to_le_bytes();
+        self.bytes(&mut bytes);
+        *val = u32::from_le_bytes(bytes);
+    }
+
+    pub fn u64(&mut self, val: &mut u64) {
+        let mut bytes = val.to_le_bytes();
+        self.bytes(&mut bytes);
+        *val = u64::from_le_bytes(bytes);
+    }
+
+    // saved as 64 bits so states move between machines
+    pub fn usize(&mut self, val: &mut usize) {
+        let mut n = *val as u64;
+        self.u64(&mut n);
+        *val = n as usize;
+    }
+
+    pub fn bool(&mut self, val: &mut bool) {
+        let mut n = *val as u8;
+        self.u8(&mut n);
+        *val = n != 0;
+    }
+
+    pub fn u16s(&mut self, vals: &mut [u16]) {
+        for val in vals.iter_mut() {
+            self.u16(val);
+        }
+    }
+
+    pub fn u32s(&mut self, vals: &mut [u32]) {
+        for val in vals.iter_mut() {
+            self.u32(val);
+        }
+    }
+
+    // A vector that can change length, with `sync` for each item
+    pub fn vec<T: Clone + Default>(&mut self, vals: &mut Vec<T>, mut sync: impl FnMut(&mut Self, &mut T)) {
+        let mut len = vals.len();
+        self.usize(&mut len);
+
+        if self.is_loading() {
+            // anything bigger than the state can't be real
+            if len > self.remaining() {
+                return self.fail(String::from("state has a bad length"));
+            }
+
+            vals.clear();
+            vals.resize(len, T::default());
+        }
+
+        for val in vals.iter_mut() {
+            sync(self, val);
+        }
+    }
+
+    pub fn option<T: Default>(&mut self, val: &mut Option<T>, mut sync: impl FnMut(&mut Self, &mut T)) {
+        let mut some = val.is_some();
+        self.bool(&mut some);
+
+        if self.is_loading() {
+            *val = if some { Some(T::default()) } else { None };
+        }
+
+        if let Some(val) = val {
+            sync(self, val);
+        }
+    }
+
+    fn remaining(&self) -> usize {
+        match &self.mode {
+            Mode::Save(_) => 0,
+            Mode::Load(data, pos) => data.len() - pos,
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gb::testing::make_cpu;
+    use crate::gb::hardware::link::wire::WireEnd;
+
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // inc a; ld ($c000), a; jr -6
+    const PROGRAM: [u8; 6] = [0x3c, 0xea, 0x00, 0xc0, 0x18, 0xfa];
+
+    #[test]
+    fn load_then_save_gives_the_same_state() {
+        let mut cpu = make_cpu("savestate", &PROGRAM);
+        for _ in 0..10 {
+            cpu.run_frame().unwrap();
+        }
+
+        let state = cpu.save_state();
+        let mut other = make_cpu("savestate-other", &PROGRAM);
+        other.load_state(&state).unwrap();
+        assert!(other.save_state() == state);
+
+        // and both carry on the same
+        for _ in 0..10 {
+            cpu.run_frame().unwrap();
+            other.run_frame().unwrap();
+        }
+        assert!(other.save_state() == cpu.save_state());
+    }
+
+    #[test]
+    fn loading_keeps_the_link_cable_plugged_in() {
+        let mut cpu = make_cpu("savestate-link", &PROGRAM);
+        let state = cpu.save_state();
+
+        let (end, _other) = WireEnd::pair();
+        cpu.bus.serial.connect(Rc::new(RefCell::new(end)));
+
+        cpu.load_state(&state).unwrap();
+        assert!(cpu.bus.serial.is_connected());
+    }
+
+    #[test]
+    fn oam_dma_keeps_the_bus_after_loading() {
+        let mut cpu = make_cpu("savestate-dma", &PROGRAM);
+        cpu.bus.write_byte(0xc000, 0x42);
+        cpu.bus.write_byte(0xff46, 0xc0);
+        cpu.bus.step(8);
+
+        let state = cpu.save_state();
+        let mut other = make_cpu("savestate-dma-other", &PROGRAM);
+        other.load_state(&state).unwrap();
+
+        assert_eq!(other.bus.read_byte(0xc000), 0xff);
+        assert_eq!(other.bus.peek_byte(0xc000), 0x42);
+    }
+}
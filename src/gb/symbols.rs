@@ -0,0 +1,160 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// Names of the io registers, as hardware.inc calls them
+const HARDWARE_REGISTERS: [(u16, &str); 55] = [
+    (0xff00, "rP1"), (0xff01, "rSB"), (0xff02, "rSC"),
+    (0xff04, "rDIV"), (0xff05, "rTIMA"), (0xff06, "rTMA"), (0xff07, "rTAC"),
+    (0xff0f, "rIF"),
+    (0xff10, "rNR10"), (0xff11, "rNR11"), (0xff12, "rNR12"), (0xff13, "rNR13"), (0xff14, "rNR14"),
+    (0xff16, "rNR21"), (0xff17, "rNR22"), (0xff18, "rNR23"), (0xff19, "rNR24"),
+    (0xff1a, "rNR30"), (0xff1b, "rNR31"), (0xff1c, "rNR32"), (0xff1d, "rNR33"), (0xff1e, "rNR34"),
+    (0xff20, "rNR41"), (0xff21, "rNR42"), (0xff22, "rNR43"), (0xff23, "rNR44"),
+    (0xff24, "rNR50"), (0xff25, "rNR51"), (0xff26, "rNR52"),
+    (0xff40, "rLCDC"), (0xff41, "rSTAT"), (0xff42, "rSCY"), (0xff43, "rSCX"),
+    (0xff44, "rLY"), (0xff45, "rLYC"), (0xff46, "rDMA"),
+    (0xff47, "rBGP"), (0xff48, "rOBP0"), (0xff49, "rOBP1"),
+    (0xff4a, "rWY"), (0xff4b, "rWX"),
+    (0xff4d, "rKEY1"), (0xff4f, "rVBK"),
+    (0xff51, "rHDMA1"), (0xff52, "rHDMA2"), (0xff53, "rHDMA3"), (0xff54, "rHDMA4"), (0xff55, "rHDMA5"),
+    (0xff56, "rRP"),
+    (0xff68, "rBCPS"), (0xff69, "rBCPD"), (0xff6a, "rOCPS"), (0xff6b, "rOCPD"),
+    (0xff70, "rSVBK"),
+    (0xffff, "rIE"),
+];
+
+// Names for addresses, keyed by bank and address
+#[derive(Clone)]
+pub struct Symbols {
+    names: BTreeMap<(u16, u16), String>,
+}
+
+impl Symbols {
+
+    pub fn init() -> Self {
+        Self {
+            names: BTreeMap::new(),
+        }
+    }
+
+    // Just the io registers
+    pub fn hardware() -> Self {
+        let mut symbols = Self::init();
+
+        for &(addr, name) in HARDWARE_REGISTERS.iter() {
+            symbols.insert(0, addr, name);
+        }
+
+        symbols
+    }
+
+    // Io registers plus everything in an rgbds .sym file
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut symbols = Self::hardware();
+        symbols.parse_sym(&fs::read_to_string(path)?);
+        Ok(symbols)
+    }
+
+    // RGBDS symbol files have a `bank:addr label` per line, and ; comments
+    pub fn parse_sym(&mut self, text: &str) {
+        for line in text.lines() {
+            let line = match line.find(';') {
+                Some(i) => &line[..i],
+                None => line,
+            };
+
+            let mut parts = line.split_whitespace();
+            let (location, name) = match (parts.next(), parts.next()) {
+                (Some(location), Some(name)) => (location, name),
+                _ => continue,
+            };
+
+            let i = match location.find(':') {
+                Some(i) => i,
+                None => continue,
+            };
+
+            let bank = u16::from_str_radix(&location[..i], 16);
+            let addr = u16::from_str_radix(&location[(i + 1)..], 16);
+
+            if let (Ok(bank), Ok(addr)) = (bank, addr) {
+                self.insert(bank, addr, name);
+            }
+        }
+    }
+
+}
+
+impl Symbols {
+
+    pub fn insert(&mut self, bank: u16, addr: u16, name: &str) {
+        self.names.insert(normalize(bank, addr), name.to_string());
+    }
+
+    // Name of exactly this address
+    pub fn get(&self, bank: u16, addr: u16) -> Option<&str> {
+        self.names.get(&normalize(bank, addr)).map(String::as_str)
+    }
+
+    // Nearest symbol at or before addr in the same part of memory, as Label+offset
+    pub fn describe(&self, bank: u16, addr: u16) -> Option<String> {
+        let (bank, addr) = normalize(bank, addr);
+
+        let (&(_, start), name) = self.names.range((bank, 0)..=(bank, addr)).next_back()?;
+        if region(start) != region(addr) {
+            return None;
+        }
+
+        Some(match addr - start {
+            0 => name.clone(),
+            offset => format!("{}+{}", name, offset),
+        })
+    }
+
+    // Address of a label, with its bank
+    pub fn find(&self, name: &str) -> Option<(u16, u16)> {
+        self.names.iter()
+            .find(|(_, n)| n.as_str() == name)
+            .map(|(&location, _)| location)
+    }
+
+    // Every labelled bank and address, in order
+    pub fn locations(&self) -> impl Iterator<Item = (u16, u16)> + '_ {
+        self.names.keys().copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+}
+
+// Only the switchable regions (romx, vram, sram, wramx) care about the bank
+fn normalize(bank: u16, addr: u16) -> (u16, u16) {
+    match addr {
+        0x4000..=0xbfff | 0xd000..=0xdfff => (bank, addr),
+        _ => (0, addr),
+    }
+}
+
+// Which part of memory addr is in, labels don't reach past their own part
+fn region(addr: u16) -> u8 {
+    match addr {
+        0x0000..=0x3fff => 0,
+        0x4000..=0x7fff => 1,
+        0x8000..=0x9fff => 2,
+        0xa000..=0xbfff => 3,
+        0xc000..=0xcfff => 4,
+        0xd000..=0xdfff => 5,
+        0xe000..=0xfeff => 6,
+        0xff00..=0xff7f => 7,
+        0xff80..=0xfffe => 8,
+        0xffff => 9,
+    }
+}
@@ -0,0 +1,32 @@
+// Helpers for the unit tests
+
+use crate::gb::cpu::CPU;
+use crate::gb::hardware::cartridge::Cartridge;
+
+use std::env;
+use std::fs::{self, File};
+
+// A 32kb rom only cartridge that runs `program` from 0x150, written to the
+// temp directory as `name` since cartridges are loaded from files
+pub fn make_rom(name: &str, program: &[u8]) -> File {
+    write_rom(name, &make_rom_bytes(program))
+}
+
+pub fn make_rom_bytes(program: &[u8]) -> Vec<u8> {
+    let mut rom = vec![0; 0x8000];
+
+    // nop, jp 0x0150
+    rom[0x100..0x104].copy_from_slice(&[0x00, 0xc3, 0x50, 0x01]);
+    rom[0x150..(0x150 + program.len())].copy_from_slice(program);
+    rom
+}
+
+pub fn write_rom(name: &str, rom: &[u8]) -> File {
+    let path = env::temp_dir().join(format!("samb_gb-test-{}-{}.gb", name, std::process::id()));
+    fs::write(&path, rom).expect("can't write test rom");
+    File::open(&path).expect("can't open test rom")
+}
+
+pub fn make_cpu(name: &str, program: &[u8]) -> CPU {
+    CPU::init(Cartridge::load(&mut make_rom(name, program)).expect("can't read test rom"))
+}
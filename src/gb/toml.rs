@@ -0,0 +1,319 @@
+// Reader for the parts of TOML the settings file uses: [tables], key = value
+// pairs, comments, and values that are strings, integers, floats, booleans
+// or single line arrays of them
+// https://toml.io/en/v1.0.0
+
+use std::collections::BTreeMap;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    Array(Vec<Value>),
+}
+
+impl Value {
+
+    pub fn get_type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "a string",
+            Value::Integer(_) => "an integer",
+            Value::Float(_) => "a number",
+            Value::Boolean(_) => "true or false",
+            Value::Array(_) => "an array",
+        }
+    }
+
+}
+
+// A value and the line it was on, for error messages
+#[derive(Clone, Debug)]
+pub struct Entry {
+    pub value: Value,
+    pub line: usize,
+}
+
+// Every value in the file by its full name, like "keys.start"
+pub type Document = BTreeMap<String, Entry>;
+
+pub fn parse(text: &str) -> Result<Document, String> {
+    let mut document = Document::new();
+    let mut table = String::new();
+
+    for (i, line) in text.lines().enumerate() {
+        let number = i + 1;
+        let error = |e: String| format!("line {}: {}", number, e);
+
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if line.starts_with('[') {
+            if !line.ends_with(']') || line.starts_with("[[") {
+                return Err(error(format!("expected a table like [keys], not {}", line)));
+            }
+
+            table = parse_key(&line[1..(line.len() - 1)]).map_err(error)?;
+            continue;
+        }
+
+        let eq = find_unquoted(line, '=').ok_or_else(|| error(String::from("expected key = value")))?;
+        let key = parse_key(&line[..eq]).map_err(error)?;
+
+        let mut rest = line[(eq + 1)..].trim();
+        let value = parse_value(&mut rest).map_err(error)?;
+        if !rest.trim().is_empty() {
+            return Err(error(format!("unexpected {} after the value", rest.trim())));
+        }
+
+        let name = if table.is_empty() { key } else { format!("{}.{}", table, key) };
+        if document.contains_key(&name) {
+            return Err(error(format!("{} is set twice", name)));
+        }
+
+        document.insert(name, Entry { value, line: number });
+    }
+
+    Ok(document)
+}
+
+// Everything before a # that isn't in a string
+fn strip_comment(line: &str) -> &str {
+    &line[..find_unquoted(line, '#').unwrap_or(line.len())]
+}
+
+// Where `target` first appears outside of a string
+fn find_unquoted(line: &str, target: char) -> Option<usize> {
+    let mut quote = None;
+    let mut escaped = false;
+
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (Some('"'), '\\') if !escaped => { escaped = true; continue; },
+            (Some(q), c) if c == q && !escaped => quote = None,
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (None, c) if c == target => return Some(i),
+            _ => {},
+        }
+
+        escaped = false;
+    }
+
+    None
+}
+
+// Bare or quoted keys, joined with dots, which quoted keys can also contain
+fn parse_key(key: &str) -> Result<String, String> {
+    let bad_key = || format!("bad key '{}'", key.trim());
+
+    let mut parts = Vec::new();
+    let mut rest = key.trim();
+
+    loop {
+        let quote = rest.chars().next().filter(|&c| c == '"' || c == '\'');
+
+        let end = match quote {
+            Some(q) => {
+                let end = rest[1..].find(q).ok_or_else(bad_key)? + 1;
+                parts.push(rest[1..end].to_string());
+                end + 1
+            },
+
+            None => {
+                let end = rest.find('.').unwrap_or(rest.len());
+                let part = rest[..end].trim();
+
+                if part.is_empty() || !part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+                    return Err(bad_key());
+                }
+
+                parts.push(part.to_string());
+                end
+            },
+        };
+
+        rest = rest[end..].trim_start();
+        if rest.is_empty() {
+            return Ok(parts.join("."));
+        }
+
+        rest = rest.strip_prefix('.').ok_or_else(bad_key)?.trim_start();
+    }
+}
+
+// Parse a value from the start of `text`, leaving whatever follows it
+fn parse_value(text: &mut &str) -> Result<Value, String> {
+    let s = *text;
+
+    if s.starts_with('"') {
+        return parse_basic_string(text).map(Value::String);
+    }
+
+    if let Some(rest) = s.strip_prefix('\'') {
+        let end = rest.find('\'').ok_or("unterminated string")?;
+        *text = &rest[(end + 1)..];
+        return Ok(Value::String(rest[..end].to_string()));
+    }
+
+    if let Some(rest) = s.strip_prefix('[') {
+        let mut items = Vec::new();
+        *text = rest.trim_start();
+
+        loop {
+            if text.starts_with(']') {
+                *text = &text[1..];
+                return Ok(Value::Array(items));
+            }
+
+            items.push(parse_value(text)?);
+            *text = text.trim_start();
+
+            if text.starts_with(',') {
+                *text = text[1..].trim_start();
+            } else if !text.starts_with(']') {
+                return Err(String::from("expected , or ] in the array"));
+            }
+        }
+    }
+
+    // numbers and booleans run until a separator
+    let end = s.find(|c: char| c == ',' || c == ']' || c.is_whitespace()).unwrap_or(s.len());
+    let word = &s[..end];
+    *text = &s[end..];
+
+    match word {
+        "true" => return Ok(Value::Boolean(true)),
+        "false" => return Ok(Value::Boolean(false)),
+        _ => {},
+    }
+
+    let digits = word.replace('_', "");
+    if let Some(hex) = digits.strip_prefix("0x") {
+        return i64::from_str_radix(hex, 16).map(Value::Integer).map_err(|_| format!("bad number {}", word));
+    }
+
+    if let Ok(n) = digits.parse::<i64>() {
+        return Ok(Value::Integer(n));
+    }
+
+    // inf and nan aren't numbers any setting could use
+    match digits.parse::<f64>() {
+        Ok(n) if n.is_finite() => Ok(Value::Float(n)),
+        _ => Err(format!("expected a value, not '{}'", word)),
+    }
+}
+
+fn parse_basic_string(text: &mut &str) -> Result<String, String> {
+    let mut out = String::new();
+    let mut chars = text.char_indices().skip(1);
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => {
+                *text = &text[(i + 1)..];
+                return Ok(out);
+            },
+
+            '\\' => match chars.next().map(|(_, c)| c) {
+                Some('n') => out.push('\n'),
+                Some('t') => out.push('\t'),
+                Some('"') => out.push('"'),
+                Some('\\') => out.push('\\'),
+                Some(c) => return Err(format!("unknown escape \\{}", c)),
+                None => break,
+            },
+
+            c => out.push(c),
+        }
+    }
+
+    Err(String::from("unterminated string"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(text: &str, name: &str) -> Value {
+        parse(text).unwrap()[name].value.clone()
+    }
+
+    #[test]
+    fn reads_tables_and_values() {
+        let document = parse("top = 1\n\n[video]\nscale = 0x10\nratio = 1.5\non = true\n").unwrap();
+
+        assert_eq!(document["top"].value, Value::Integer(1));
+        assert_eq!(document["video.scale"].value, Value::Integer(16));
+        assert_eq!(document["video.ratio"].value, Value::Float(1.5));
+        assert_eq!(document["video.on"].value, Value::Boolean(true));
+        assert_eq!(document["video.on"].line, 6);
+    }
+
+    #[test]
+    fn comments_stop_outside_strings() {
+        assert_eq!(value("a = \"#fff\" # white", "a"), Value::String(String::from("#fff")));
+        assert_eq!(value("a = '# not a comment'", "a"), Value::String(String::from("# not a comment")));
+        assert_eq!(value("a = \"say \\\"#\\\"\" # comment", "a"), Value::String(String::from("say \"#\"")));
+        assert_eq!(value("# all comment\na = 1 #", "a"), Value::Integer(1));
+    }
+
+    #[test]
+    fn quoted_keys() {
+        let document = parse("[\"keys\"]\n\"start button\" = 1\n'a.b' = 2\n\"x=y\" . z = 3\n").unwrap();
+
+        assert!(document.contains_key("keys.start button"));
+        assert!(document.contains_key("keys.a.b"));
+        assert!(document.contains_key("keys.x=y.z"));
+
+        assert!(parse("bad key = 1").is_err());
+        assert!(parse("a. = 1").is_err());
+        assert!(parse("\"open = 1").is_err());
+        assert!(parse("'a'b = 1").is_err());
+    }
+
+    #[test]
+    fn escapes_in_basic_strings_only() {
+        assert_eq!(value(r#"a = "tab\there\n""#, "a"), Value::String(String::from("tab\there\n")));
+        assert_eq!(value(r#"a = "back\\slash""#, "a"), Value::String(String::from("back\\slash")));
+        assert_eq!(value(r#"a = 'C:\no\escapes'"#, "a"), Value::String(String::from("C:\\no\\escapes")));
+
+        assert_eq!(parse(r#"a = "\q""#).unwrap_err(), "line 1: unknown escape \\q");
+        assert_eq!(parse("a = \"open").unwrap_err(), "line 1: unterminated string");
+    }
+
+    #[test]
+    fn arrays() {
+        assert_eq!(value("a = []", "a"), Value::Array(Vec::new()));
+        assert_eq!(value("a = [ \"Up\", \"W\" , ]", "a"), Value::Array(vec![
+            Value::String(String::from("Up")),
+            Value::String(String::from("W")),
+        ]));
+        assert_eq!(value("a = [1, [2, 3]]", "a"), Value::Array(vec![
+            Value::Integer(1),
+            Value::Array(vec![Value::Integer(2), Value::Integer(3)]),
+        ]));
+
+        assert!(parse("a = [1 2]").is_err());
+        assert!(parse("a = [1, 2").is_err());
+    }
+
+    #[test]
+    fn rejects_what_settings_cant_use() {
+        assert!(parse("a = inf").is_err());
+        assert!(parse("a = nan").is_err());
+        assert!(parse("a = -infinity").is_err());
+        assert!(parse("a = 1e999").is_err());
+        assert!(parse("a = 1 2").is_err());
+        assert!(parse("[[array]]").is_err());
+    }
+
+    #[test]
+    fn errors_give_the_line() {
+        assert_eq!(parse("a = 1\n\n[b]\nc = 2\nc = 3\n").unwrap_err(), "line 5: b.c is set twice");
+        assert_eq!(parse("a = 1\n[b]\na = 2\n[c\n").unwrap_err(), "line 4: expected a table like [keys], not [c");
+        assert_eq!(parse("# comment\njust words\n").unwrap_err(), "line 2: expected key = value");
+    }
+}
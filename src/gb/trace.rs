@@ -0,0 +1,89 @@
+// Instruction trace in the Gameboy Doctor format, one line of cpu state
+// before every instruction, for diffing against other emulators
+// https://github.com/robert/gameboy-doctor
+
+use crate::gb::hardware::memory_bus::MemoryBus;
+use crate::gb::hardware::registers::Registers;
+use crate::gb::symbols::Symbols;
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+pub struct TraceWriter {
+    out: Box<dyn Write>,
+
+    // when set, lines end with where pc is, like ` ; Main.loop+3`
+    symbols: Option<Symbols>,
+}
+
+impl TraceWriter {
+
+    pub fn init(out: Box<dyn Write>) -> Self {
+        Self { out, symbols: None }
+    }
+
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(Self::init(Box::new(BufWriter::new(File::create(path)?))))
+    }
+
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = Some(symbols);
+    }
+
+    // A:00 F:11 B:22 C:33 D:44 E:55 H:66 L:77 SP:8888 PC:9999 PCMEM:AA,BB,CC,DD
+    pub fn write_state(&mut self, reg: &Registers, bus: &MemoryBus) {
+        let pcmem: Vec<String> = (0..4)
+            .map(|i| format!("{:02X}", bus.peek_byte(reg.pc.wrapping_add(i))))
+            .collect();
+
+        let label = self.symbols.as_ref()
+            .and_then(|symbols| symbols.describe(bus.get_bank_of(reg.pc), reg.pc))
+            .map_or(String::new(), |name| format!(" ; {}", name));
+
+        let result = writeln!(
+            self.out,
+            "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{}{}",
+            reg.a, reg.f, reg.b, reg.c, reg.d, reg.e, reg.h, reg.l, reg.sp, reg.pc,
+            pcmem.join(","), label,
+        );
+
+        if let Err(e) = result {
+            log_error!(crate::gb::log::Target::Cpu, "Unable to write trace: {}", e);
+        }
+    }
+
+    pub fn flush(&mut self) {
+        self.out.flush().ok();
+    }
+
+}
+
+// the trace is buffered, so whatever is left goes out when the cpu is done with it
+impl Drop for TraceWriter {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gb::testing::make_cpu;
+
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn dropping_flushes_the_trace() {
+        let cpu = make_cpu("trace", &[0x18, 0xfe]);
+        let path = env::temp_dir().join(format!("samb_gb-test-trace-{}.txt", std::process::id()));
+
+        let mut trace = TraceWriter::create(&path).unwrap();
+        trace.write_state(&cpu.reg, &cpu.bus);
+        drop(trace);
+
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(text.ends_with("PC:0100 PCMEM:00,C3,50,01\n"), "{}", text);
+    }
+}
@@ -0,0 +1,91 @@
+// Pixel buffer the debug windows draw into before handing it to minifb
+
+use crate::gb::ui::font::{self, GLYPH_WIDTH, GLYPH_HEIGHT};
+
+// space taken by a character, with a gap after it
+pub const CHAR_WIDTH: usize = GLYPH_WIDTH + 1;
+pub const CHAR_HEIGHT: usize = GLYPH_HEIGHT + 2;
+
+pub struct Canvas {
+    pub width: usize,
+    pub height: usize,
+
+    pub buffer: Vec<u32>,
+}
+
+impl Canvas {
+
+    pub fn init(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+
+            buffer: vec![0; width * height],
+        }
+    }
+
+}
+
+impl Canvas {
+
+    pub fn clear(&mut self, color: u32) {
+        for pixel in self.buffer.iter_mut() {
+            *pixel = color;
+        }
+    }
+
+    // Anything off the edge is clipped
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: u32) {
+        if x < self.width && y < self.height {
+            self.buffer[y * self.width + x] = color;
+        }
+    }
+
+    pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: u32) {
+        for row in y..(y + height).min(self.height) {
+            for col in x..(x + width).min(self.width) {
+                self.buffer[row * self.width + col] = color;
+            }
+        }
+    }
+
+    // One pixel wide outline
+    pub fn draw_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: u32) {
+        if width == 0 || height == 0 {
+            return;
+        }
+
+        self.fill_rect(x, y, width, 1, color);
+        self.fill_rect(x, y + height - 1, width, 1, color);
+        self.fill_rect(x, y, 1, height, color);
+        self.fill_rect(x + width - 1, y, 1, height, color);
+    }
+
+    // Copy an image in, with each of its pixels drawn scale x scale
+    pub fn draw_image(&mut self, x: usize, y: usize, image: &[u32], width: usize, scale: usize) {
+        let height = image.len().checked_div(width).unwrap_or(0);
+
+        for row in 0..(height * scale) {
+            for col in 0..(width * scale) {
+                self.set_pixel(x + col, y + row, image[(row / scale) * width + col / scale]);
+            }
+        }
+    }
+
+    // Text starting with its top left at x, y
+    pub fn draw_text(&mut self, x: usize, y: usize, text: &str, color: u32) {
+        for (i, c) in text.chars().enumerate() {
+            let glyph = font::get_glyph(c);
+            let left = x + i * CHAR_WIDTH;
+
+            for (row, bits) in glyph.iter().enumerate() {
+                for col in 0..GLYPH_WIDTH {
+                    if bits & (0x10 >> col) != 0 {
+                        self.set_pixel(left + col, y + row, color);
+                    }
+                }
+            }
+        }
+    }
+
+}
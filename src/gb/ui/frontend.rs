@@ -0,0 +1,113 @@
+// The emulator window, showing the lcd (or the super gameboy picture) at the
+// largest integer scale that fits, centered with black bars around it
+
+use crate::gb::ui::settings::{Hotkey, KeyBindings};
+
+use minifb::{KeyRepeat, Scale, ScaleMode, Window, WindowOptions};
+
+const LETTERBOX_COLOR: u32 = 0x000000;
+
+pub struct Frontend {
+    window: Window,
+
+    // the whole window, screen and letterbox
+    buffer: Vec<u32>,
+
+    // size the window opened at, used if it can't tell us its size
+    size: (usize, usize),
+
+    bindings: KeyBindings,
+}
+
+impl Frontend {
+
+    // Open a window showing a width x height screen at `scale`
+    pub fn init(title: &str, width: usize, height: usize, scale: usize, bindings: KeyBindings) -> Result<Self, String> {
+        let size = (width * scale.max(1), height * scale.max(1));
+
+        let options = WindowOptions {
+            resize: true,
+            scale: Scale::X1,
+            scale_mode: ScaleMode::UpperLeft,
+            ..WindowOptions::default()
+        };
+
+        let window = Window::new(title, size.0, size.1, options)
+            .map_err(|e| format!("can't open a window: {}", e))?;
+
+        Ok(Self {
+            window,
+
+            buffer: vec![LETTERBOX_COLOR; size.0 * size.1],
+
+            size,
+
+            bindings,
+        })
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.window.is_open()
+    }
+
+    pub fn set_title(&mut self, title: &str) {
+        self.window.set_title(title);
+    }
+
+    // Buttons held down in the window, for the joypad
+    pub fn get_buttons(&self) -> u8 {
+        self.bindings.buttons.iter()
+            .filter(|&&(key, _)| self.window.is_key_down(key))
+            .fold(0, |buttons, &(_, button)| buttons | button)
+    }
+
+    // Hotkeys pressed since the last frame
+    pub fn get_hotkeys(&self) -> Vec<Hotkey> {
+        self.bindings.hotkeys.iter()
+            .filter(|&&(key, _)| self.window.is_key_pressed(key, KeyRepeat::No))
+            .map(|&(_, hotkey)| hotkey)
+            .collect()
+    }
+
+    pub fn is_hotkey_down(&self, hotkey: Hotkey) -> bool {
+        self.bindings.hotkeys.iter()
+            .any(|&(key, h)| h == hotkey && self.window.is_key_down(key))
+    }
+
+    // Draw a frame scaled up to fill as much of the window as it can
+    pub fn present(&mut self, screen: &[u32], width: usize, height: usize) {
+        let (window_width, window_height) = match self.window.get_size() {
+            (0, _) | (_, 0) => self.size,
+            size => size,
+        };
+
+        self.buffer.clear();
+        self.buffer.resize(window_width * window_height, LETTERBOX_COLOR);
+
+        let scale = (window_width / width).min(window_height / height).max(1);
+        let left = window_width.saturating_sub(width * scale) / 2;
+        let top = window_height.saturating_sub(height * scale) / 2;
+
+        // windows smaller than the screen only show its top left
+        let shown_width = (width * scale).min(window_width);
+        let shown_height = (height * scale).min(window_height);
+
+        let mut row = vec![0; shown_width];
+        for y in 0..shown_height {
+            // each screen line is scaled once then copied down
+            if y % scale == 0 {
+                let line = &screen[((y / scale) * width)..((y / scale + 1) * width)];
+
+                for (x, pixel) in row.iter_mut().enumerate() {
+                    *pixel = line[x / scale];
+                }
+            }
+
+            let start = (top + y) * window_width + left;
+            self.buffer[start..(start + shown_width)].copy_from_slice(&row);
+        }
+
+        self.window.update_with_buffer(&self.buffer, window_width, window_height).unwrap();
+    }
+
+}
@@ -0,0 +1,406 @@
+// Hex editor window for looking through and changing memory, opened with --memory
+//
+//   tab            next memory space (bus, rom, vram, sram, wram)
+//   [ ]            previous / next bank
+//   arrows, pgup, pgdn, home, end, mouse   move the cursor
+//   0-9 a-f        type a new value for the byte under the cursor
+//   g              go to an address, typed in hex then enter
+
+use crate::gb::hardware::memory_bus::{MemoryBus, MemorySpace, MEMORY_SPACES};
+use crate::gb::symbols::Symbols;
+use crate::gb::ui::canvas::{Canvas, CHAR_WIDTH, CHAR_HEIGHT};
+
+use minifb::{Key, KeyRepeat, MouseButton, MouseMode, Scale, Window, WindowOptions};
+
+const BYTES_PER_ROW: usize = 16;
+const ROWS: usize = 32;
+const PAGE_SIZE: usize = BYTES_PER_ROW * ROWS;
+
+// rows moved by a notch of the scroll wheel
+const SCROLL_ROWS: usize = 3;
+
+// Layout in characters, an extra space splits the hex columns in half
+const HEX_COLUMN: usize = 9;
+const ASCII_COLUMN: usize = HEX_COLUMN + BYTES_PER_ROW * 3 + 2;
+const COLUMNS: usize = ASCII_COLUMN + BYTES_PER_ROW;
+
+// a header line and a gap above the rows, a gap and a status line below
+const FIRST_ROW_LINE: usize = 2;
+const STATUS_LINE: usize = FIRST_ROW_LINE + ROWS + 1;
+
+const MARGIN: usize = 4;
+const WIDTH: usize = MARGIN * 2 + COLUMNS * CHAR_WIDTH;
+const HEIGHT: usize = MARGIN * 2 + (STATUS_LINE + 1) * CHAR_HEIGHT;
+
+// Colors
+const BACKGROUND: u32 = 0x1e1e1e;
+const TEXT: u32 = 0xd0d0d0;
+const DIM: u32 = 0x808080;
+const HEADER: u32 = 0xffd060;
+const CHANGED: u32 = 0xff6040;
+const CURSOR: u32 = 0x305080;
+
+pub struct MemoryViewer {
+    window: Window,
+    canvas: Canvas,
+
+    // names shown for the byte under the cursor
+    pub symbols: Symbols,
+
+    space: MemorySpace,
+    bank: u16,
+
+    // offsets into the bank of the first byte shown and of the cursor
+    top: usize,
+    cursor: usize,
+
+    // high nibble typed for the byte under the cursor
+    pending: Option<u8>,
+
+    // address being typed after g
+    goto: Option<String>,
+
+    // what was shown last frame, to highlight bytes that changed
+    previous: Vec<u8>,
+    previous_view: Option<(MemorySpace, u16, usize)>,
+
+    mouse_down: bool,
+}
+
+impl MemoryViewer {
+
+    pub fn init() -> Self {
+        let options = WindowOptions {
+            scale: Scale::X2,
+            ..WindowOptions::default()
+        };
+
+        Self {
+            window: Window::new("Memory", WIDTH, HEIGHT, options).unwrap_or_else(|e| {
+                panic!("{}", e);
+            }),
+            canvas: Canvas::init(WIDTH, HEIGHT),
+
+            symbols: Symbols::hardware(),
+
+            space: MemorySpace::Bus,
+            bank: 0,
+
+            top: 0,
+            cursor: 0,
+
+            pending: None,
+
+            goto: None,
+
+            previous: Vec::new(),
+            previous_view: None,
+
+            mouse_down: false,
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.window.is_open()
+    }
+
+    // Handle input and redraw, called once a frame
+    pub fn update(&mut self, bus: &mut MemoryBus) {
+        self.handle_input(bus);
+        self.draw(bus);
+
+        self.window.update_with_buffer(&self.canvas.buffer, WIDTH, HEIGHT).unwrap();
+    }
+
+}
+
+////////// INPUT //////////
+
+impl MemoryViewer {
+
+    fn handle_input(&mut self, bus: &mut MemoryBus) {
+        if let Some(keys) = self.window.get_keys_pressed(KeyRepeat::Yes) {
+            for key in keys {
+                self.press(key, bus);
+            }
+        }
+
+        if let Some((_, y)) = self.window.get_scroll_wheel() {
+            let rows = SCROLL_ROWS * BYTES_PER_ROW;
+
+            self.top = if y > 0.0 {
+                self.top.saturating_sub(rows)
+            } else {
+                (self.top + rows).min(self.space.get_size() - PAGE_SIZE)
+            };
+
+            // keep the cursor on screen rather than scrolling back to it
+            self.cursor = self.cursor.max(self.top).min(self.top + PAGE_SIZE - 1);
+        }
+
+        let down = self.window.get_mouse_down(MouseButton::Left);
+        if down && !self.mouse_down {
+            let clicked = self.window.get_mouse_pos(MouseMode::Discard)
+                .and_then(|(x, y)| self.offset_at(x as usize, y as usize));
+
+            if let Some(offset) = clicked {
+                self.cursor = offset;
+                self.pending = None;
+            }
+        }
+        self.mouse_down = down;
+    }
+
+    fn press(&mut self, key: Key, bus: &mut MemoryBus) {
+        if let Some(text) = &mut self.goto {
+            match key {
+                Key::Enter => self.go_to(),
+                Key::Escape => self.goto = None,
+                Key::Backspace => { text.pop(); },
+                _ => if let Some(digit) = hex_digit(key) {
+                    if text.len() < 4 {
+                        text.push_str(&format!("{:X}", digit));
+                    }
+                },
+            }
+            return;
+        }
+
+        let last = self.space.get_size() - 1;
+
+        match key {
+            Key::Tab => {
+                let i = MEMORY_SPACES.iter().position(|&s| s == self.space).unwrap_or(0);
+                self.space = MEMORY_SPACES[(i + 1) % MEMORY_SPACES.len()];
+                self.bank = 0;
+                self.move_cursor(0);
+            },
+
+            Key::LeftBracket | Key::RightBracket => {
+                let count = bus.get_bank_count(self.space);
+                self.bank = if key == Key::LeftBracket {
+                    (self.bank + count - 1) % count
+                } else {
+                    (self.bank + 1) % count
+                };
+                self.pending = None;
+            },
+
+            Key::Up => self.move_cursor(self.cursor.saturating_sub(BYTES_PER_ROW)),
+            Key::Down => self.move_cursor((self.cursor + BYTES_PER_ROW).min(last)),
+            Key::Left => self.move_cursor(self.cursor.saturating_sub(1)),
+            Key::Right => self.move_cursor((self.cursor + 1).min(last)),
+            Key::PageUp => self.move_cursor(self.cursor.saturating_sub(PAGE_SIZE)),
+            Key::PageDown => self.move_cursor((self.cursor + PAGE_SIZE).min(last)),
+            Key::Home => self.move_cursor(0),
+            Key::End => self.move_cursor(last),
+
+            Key::G => self.goto = Some(String::new()),
+            Key::Escape => self.pending = None,
+
+            _ => if let Some(digit) = hex_digit(key) {
+                self.edit(digit, bus);
+            },
+        }
+    }
+
+    // The second digit typed writes the byte and moves on to the next
+    fn edit(&mut self, digit: u8, bus: &mut MemoryBus) {
+        match self.pending.take() {
+            None => self.pending = Some(digit),
+
+            Some(high) => {
+                bus.write_space_byte(self.space, self.bank, self.cursor as u16, (high << 4) | digit);
+                self.move_cursor((self.cursor + 1).min(self.space.get_size() - 1));
+            },
+        }
+    }
+
+    // Addresses outside the bank being shown are looked at on the bus instead
+    fn go_to(&mut self) {
+        let addr = self.goto.take()
+            .and_then(|text| usize::from_str_radix(&text, 16).ok());
+
+        if let Some(addr) = addr {
+            let base = self.space.get_base(self.bank) as usize;
+
+            if addr >= base && addr < base + self.space.get_size() {
+                self.move_cursor(addr - base);
+            } else {
+                self.space = MemorySpace::Bus;
+                self.bank = 0;
+                self.move_cursor(addr);
+            }
+        }
+    }
+
+    // Scroll just enough to keep the cursor on screen
+    fn move_cursor(&mut self, offset: usize) {
+        self.cursor = offset;
+        self.pending = None;
+
+        let row = offset - offset % BYTES_PER_ROW;
+        if row < self.top {
+            self.top = row;
+        } else if row >= self.top + PAGE_SIZE {
+            self.top = row + BYTES_PER_ROW - PAGE_SIZE;
+        }
+    }
+
+    // Byte under a point in the window, in either the hex or ascii columns
+    fn offset_at(&self, x: usize, y: usize) -> Option<usize> {
+        let column = x.checked_sub(MARGIN)? / CHAR_WIDTH;
+        let row = (y.checked_sub(MARGIN)? / CHAR_HEIGHT).checked_sub(FIRST_ROW_LINE)?;
+
+        if row >= ROWS {
+            return None;
+        }
+
+        let i = if column >= ASCII_COLUMN {
+            column - ASCII_COLUMN
+        } else {
+            let column = column.checked_sub(HEX_COLUMN)?;
+            let half = BYTES_PER_ROW / 2 * 3;
+
+            if column < half {
+                column / 3
+            } else {
+                BYTES_PER_ROW / 2 + column.checked_sub(half + 1)? / 3
+            }
+        };
+
+        if i < BYTES_PER_ROW {
+            Some(self.top + row * BYTES_PER_ROW + i)
+        } else {
+            None
+        }
+    }
+
+}
+
+////////// DRAWING //////////
+
+impl MemoryViewer {
+
+    fn draw(&mut self, bus: &MemoryBus) {
+        let page: Vec<u8> = (0..PAGE_SIZE)
+            .map(|i| bus.read_space_byte(self.space, self.bank, (self.top + i) as u16))
+            .collect();
+
+        // changes only make sense against the same bytes last frame
+        let view = (self.space, self.bank, self.top);
+        let changed: Vec<bool> = (0..PAGE_SIZE)
+            .map(|i| self.previous_view == Some(view) && self.previous[i] != page[i])
+            .collect();
+
+        self.canvas.clear(BACKGROUND);
+
+        let count = bus.get_bank_count(self.space);
+        let header = match self.space {
+            MemorySpace::Bus => "BUS (as the cpu sees it)".to_string(),
+            space => format!("{} bank {:02X}/{:02X}", space.get_name(), self.bank, count - 1),
+        };
+        draw_line(&mut self.canvas, 0, 0, &header, HEADER);
+        draw_line(&mut self.canvas, ASCII_COLUMN - 8, 0, "tab [ ] g", DIM);
+
+        for row in 0..ROWS {
+            let line = FIRST_ROW_LINE + row;
+            let offset = self.top + row * BYTES_PER_ROW;
+            let addr = self.address_of(offset);
+
+            let bank = match self.space {
+                MemorySpace::Bus => bus.get_bank_of(addr),
+                _ => self.bank,
+            };
+            draw_line(&mut self.canvas, 0, line, &format!("{:02X}:{:04X}", bank, addr), DIM);
+
+            for col in 0..BYTES_PER_ROW {
+                let i = row * BYTES_PER_ROW + col;
+                let val = page[i];
+                let hex_column = HEX_COLUMN + col * 3 + if col >= BYTES_PER_ROW / 2 { 1 } else { 0 };
+                let ascii_column = ASCII_COLUMN + col;
+
+                if self.top + i == self.cursor {
+                    highlight(&mut self.canvas, hex_column, line, 2);
+                    highlight(&mut self.canvas, ascii_column, line, 1);
+                }
+
+                let color = if changed[i] { CHANGED } else { TEXT };
+                let text = match self.pending {
+                    Some(high) if self.top + i == self.cursor => format!("{:X}_", high),
+                    _ => format!("{:02X}", val),
+                };
+                draw_line(&mut self.canvas, hex_column, line, &text, color);
+
+                let c = if (0x20..0x7f).contains(&val) { val as char } else { '.' };
+                draw_line(&mut self.canvas, ascii_column, line, &c.to_string(), if changed[i] { CHANGED } else { DIM });
+            }
+        }
+
+        let status = match &self.goto {
+            Some(text) => format!("go to: {}_", text),
+            None => self.describe_cursor(bus),
+        };
+        draw_line(&mut self.canvas, 0, STATUS_LINE, &status, TEXT);
+
+        self.previous = page;
+        self.previous_view = Some(view);
+    }
+
+    // BB:AAAA = $VV <Label+offset>
+    fn describe_cursor(&self, bus: &MemoryBus) -> String {
+        let addr = self.address_of(self.cursor);
+        let bank = match self.space {
+            MemorySpace::Bus => bus.get_bank_of(addr),
+            _ => self.bank,
+        };
+
+        let val = bus.read_space_byte(self.space, self.bank, self.cursor as u16);
+        let name = match self.symbols.describe(bank, addr) {
+            Some(name) => format!(" <{}>", name),
+            None => String::new(),
+        };
+
+        format!("{:02X}:{:04X} = ${:02X}{}", bank, addr, val, name)
+    }
+
+    // Where a byte in the bank shows up for the cpu
+    fn address_of(&self, offset: usize) -> u16 {
+        self.space.get_base(self.bank).wrapping_add(offset as u16)
+    }
+
+}
+
+fn draw_line(canvas: &mut Canvas, column: usize, line: usize, text: &str, color: u32) {
+    canvas.draw_text(MARGIN + column * CHAR_WIDTH, MARGIN + line * CHAR_HEIGHT, text, color);
+}
+
+// Background behind `width` characters
+fn highlight(canvas: &mut Canvas, column: usize, line: usize, width: usize) {
+    let x = MARGIN + column * CHAR_WIDTH;
+    let y = MARGIN + line * CHAR_HEIGHT;
+
+    canvas.fill_rect(x - 1, y - 1, width * CHAR_WIDTH + 1, CHAR_HEIGHT, CURSOR);
+}
+
+fn hex_digit(key: Key) -> Option<u8> {
+    Some(match key {
+        Key::Key0 | Key::NumPad0 => 0x0,
+        Key::Key1 | Key::NumPad1 => 0x1,
+        Key::Key2 | Key::NumPad2 => 0x2,
+        Key::Key3 | Key::NumPad3 => 0x3,
+        Key::Key4 | Key::NumPad4 => 0x4,
+        Key::Key5 | Key::NumPad5 => 0x5,
+        Key::Key6 | Key::NumPad6 => 0x6,
+        Key::Key7 | Key::NumPad7 => 0x7,
+        Key::Key8 | Key::NumPad8 => 0x8,
+        Key::Key9 | Key::NumPad9 => 0x9,
+        Key::A => 0xa,
+        Key::B => 0xb,
+        Key::C => 0xc,
+        Key::D => 0xd,
+        Key::E => 0xe,
+        Key::F => 0xf,
+        _ => return None,
+    })
+}
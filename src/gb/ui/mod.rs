@@ -0,0 +1,7 @@
+pub mod canvas;
+pub mod font;
+pub mod frontend;
+pub mod memory_viewer;
+pub mod pacing;
+pub mod settings;
+pub mod vram_viewer;
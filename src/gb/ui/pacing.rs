@@ -0,0 +1,91 @@
+// Keeps emulation at the speed of real hardware, 4194304 cycles a second
+// (59.73 frames), by sleeping off whatever time a frame didn't need
+
+use crate::gb::headless::CLOCK_SPEED;
+use crate::gb::hardware::io::gpu::FRAME_CYCLES;
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+// falling further behind than this (a slow machine, or a breakpoint)
+// starts counting again from now instead of rushing to catch up
+const MAX_LAG: Duration = Duration::from_millis(100);
+
+// Speeds the speed up and down hotkeys step through, in percent
+pub const SPEEDS: [u32; 5] = [25, 50, 100, 200, 400];
+pub const NORMAL_SPEED: u32 = 100;
+
+pub struct FramePacer {
+    start: Instant,
+
+    // cycles emulated since start
+    cycles: u64,
+
+    // percent of hardware speed
+    speed: u32,
+}
+
+impl FramePacer {
+
+    pub fn init() -> Self {
+        Self {
+            start: Instant::now(),
+            cycles: 0,
+
+            speed: NORMAL_SPEED,
+        }
+    }
+
+    pub fn get_speed(&self) -> u32 {
+        self.speed
+    }
+
+    pub fn set_speed(&mut self, speed: u32) {
+        self.speed = speed.max(1);
+        self.reset();
+    }
+
+    // Start counting from now, after running unthrottled or not at all
+    pub fn reset(&mut self) {
+        self.start = Instant::now();
+        self.cycles = 0;
+    }
+
+    // Wait as long as a frame takes on hardware without emulating anything,
+    // for polling the window while paused
+    pub fn idle(&mut self) {
+        let nanos = (FRAME_CYCLES as u64) * 1_000_000_000 / (CLOCK_SPEED as u64);
+        thread::sleep(Duration::from_nanos(nanos));
+        self.reset();
+    }
+
+    // Count cycles that were just emulated, at the normal speed clock,
+    // and wait until they would have taken that long on hardware
+    pub fn wait(&mut self, cycles: usize) {
+        self.cycles += cycles as u64;
+
+        let nanos = (self.cycles as u128) * 1_000_000_000 * (NORMAL_SPEED as u128)
+            / (CLOCK_SPEED as u128 * self.speed as u128);
+        let target = self.start + Duration::from_nanos(nanos as u64);
+        let now = Instant::now();
+
+        if target > now {
+            thread::sleep(target - now);
+        } else if now - target > MAX_LAG {
+            self.reset();
+        }
+    }
+
+}
+
+// 0.25x, 1x, 4x
+pub fn get_speed_name(speed: u32) -> String {
+    let whole = speed / 100;
+    let fraction = format!("{:02}", speed % 100);
+
+    if speed.is_multiple_of(100) {
+        format!("{}x", whole)
+    } else {
+        format!("{}.{}x", whole, fraction.trim_end_matches('0'))
+    }
+}
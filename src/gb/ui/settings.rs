@@ -0,0 +1,437 @@
+// Settings file, config.toml in the samb_gb folder of the user config
+// directory (~/.config/samb_gb on linux), written out with the defaults
+// the first time it's looked for. Command line options win over it.
+
+use crate::gb::toml::{self, Entry, Value};
+use crate::gb::hardware::io::gpu::parse_dmg_palette;
+use crate::gb::hardware::io::joypad::{
+    BUTTON_RIGHT, BUTTON_LEFT, BUTTON_UP, BUTTON_DOWN,
+    BUTTON_A, BUTTON_B, BUTTON_SELECT, BUTTON_START,
+};
+
+use minifb::Key;
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_SETTINGS: &str = r#"# samb_gb settings, command line options take priority over these
+
+[video]
+# how many times bigger than the lcd the window opens
+scale = 3
+
+# dmg colors, grey, green, pocket or four hex colors lightest first
+palette = "grey"
+
+# how many times bigger than the lcd screenshots are saved
+screenshot_scale = 1
+
+# sound isn't emulated yet, these are kept for when it is
+[audio]
+enabled = true
+
+# 0 to 100
+volume = 100
+
+# key names are the ones minifb uses, like "A", "Key1", "F5", "Enter",
+# "Backspace", "LeftShift", "NumPad8" or "Up", use a list to bind several
+[keys]
+up = "Up"
+down = "Down"
+left = "Left"
+right = "Right"
+a = "A"
+b = "B"
+start = "X"
+select = "Z"
+
+[hotkeys]
+save_state = "F5"
+load_state = "F7"
+fast_forward = "Tab"
+speed_down = "Minus"
+speed_up = "Equal"
+pause = "P"
+frame_advance = "Space"
+rewind = "R"
+reset = "F2"
+screenshot = "F12"
+
+[speed]
+# frames run between the ones shown while fast forwarding
+fast_forward_frameskip = 0
+
+[rewind]
+# how far back holding rewind can go, 0 turns it off
+seconds = 10
+
+# frames between the states rewind steps back through
+interval = 1
+"#;
+
+// Joypad buttons by their name in [keys]
+const BUTTONS: [(&str, u8); 8] = [
+    ("up", BUTTON_UP), ("down", BUTTON_DOWN), ("left", BUTTON_LEFT), ("right", BUTTON_RIGHT),
+    ("a", BUTTON_A), ("b", BUTTON_B), ("start", BUTTON_START), ("select", BUTTON_SELECT),
+];
+
+// Emulator controls in the window
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Hotkey {
+    SaveState,
+    LoadState,
+    FastForward,
+    SpeedDown,
+    SpeedUp,
+    Pause,
+    FrameAdvance,
+    Rewind,
+    Reset,
+    Screenshot,
+}
+
+pub const HOTKEYS: [Hotkey; 10] = [
+    Hotkey::SaveState, Hotkey::LoadState, Hotkey::FastForward,
+    Hotkey::SpeedDown, Hotkey::SpeedUp, Hotkey::Pause, Hotkey::FrameAdvance,
+    Hotkey::Rewind, Hotkey::Reset, Hotkey::Screenshot,
+];
+
+impl Hotkey {
+
+    // Name in [hotkeys]
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Hotkey::SaveState => "save_state",
+            Hotkey::LoadState => "load_state",
+            Hotkey::FastForward => "fast_forward",
+            Hotkey::SpeedDown => "speed_down",
+            Hotkey::SpeedUp => "speed_up",
+            Hotkey::Pause => "pause",
+            Hotkey::FrameAdvance => "frame_advance",
+            Hotkey::Rewind => "rewind",
+            Hotkey::Reset => "reset",
+            Hotkey::Screenshot => "screenshot",
+        }
+    }
+
+}
+
+// Which keys press which joypad buttons and hotkeys
+#[derive(Clone, Debug)]
+pub struct KeyBindings {
+    pub buttons: Vec<(Key, u8)>,
+    pub hotkeys: Vec<(Key, Hotkey)>,
+}
+
+impl KeyBindings {
+
+    // Keys that press a joypad button and a hotkey at the same time
+    pub fn get_conflicts(&self) -> Vec<(Key, u8, Hotkey)> {
+        self.buttons.iter()
+            .flat_map(|&(key, button)| {
+                self.hotkeys.iter()
+                    .filter(move |&&(k, _)| k == key)
+                    .map(move |&(_, hotkey)| (key, button, hotkey))
+            })
+            .collect()
+    }
+
+}
+
+#[derive(Clone, Debug)]
+pub struct Settings {
+    pub scale: usize,
+    pub palette: [u32; 4],
+    pub screenshot_scale: usize,
+
+    pub audio: bool,
+    pub volume: u8,
+
+    pub frameskip: usize,
+
+    pub rewind_seconds: usize,
+    pub rewind_interval: usize,
+
+    pub bindings: KeyBindings,
+}
+
+impl Settings {
+
+    pub fn init() -> Self {
+        let mut settings = Self {
+            scale: 1,
+            palette: [0; 4],
+            screenshot_scale: 1,
+
+            audio: true,
+            volume: 0,
+
+            frameskip: 0,
+
+            rewind_seconds: 0,
+            rewind_interval: 1,
+
+            bindings: KeyBindings { buttons: Vec::new(), hotkeys: Vec::new() },
+        };
+
+        let document = toml::parse(DEFAULT_SETTINGS).expect("default settings don't parse");
+        settings.apply(&document).expect("default settings are invalid");
+        settings
+    }
+
+    // Read the settings file at `path`, or the one in the config directory,
+    // which is created if it isn't there yet
+    pub fn load(path: Option<&Path>) -> Result<Self, String> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+
+            None => match get_settings_path() {
+                Some(path) if !path.exists() => {
+                    // not being able to write it shouldn't stop the game
+                    let _ = path.parent().map(fs::create_dir_all);
+                    let _ = fs::write(&path, DEFAULT_SETTINGS);
+                    return Ok(Self::init());
+                },
+
+                Some(path) => path,
+                None => return Ok(Self::init()),
+            },
+        };
+
+        let text = fs::read_to_string(&path)
+            .map_err(|e| format!("can't read settings {}: {}", path.display(), e))?;
+
+        let document = toml::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        let mut settings = Self::init();
+        settings.apply(&document).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(settings)
+    }
+
+}
+
+impl Settings {
+
+    // Change whatever the document sets, leaving the rest
+    fn apply(&mut self, document: &toml::Document) -> Result<(), String> {
+        for (name, entry) in document {
+            let error = |e: String| format!("line {}: {}", entry.line, e);
+
+            match name.as_str() {
+                "video.scale" => {
+                    self.scale = get_integer(name, entry, 1, 16).map_err(error)? as usize;
+                },
+
+                "video.palette" => {
+                    self.palette = parse_dmg_palette(get_string(name, entry).map_err(error)?).map_err(error)?;
+                },
+
+                "video.screenshot_scale" => {
+                    self.screenshot_scale = get_integer(name, entry, 1, 16).map_err(error)? as usize;
+                },
+
+                "audio.enabled" => self.audio = match entry.value {
+                    Value::Boolean(enabled) => enabled,
+                    ref value => return Err(error(format!("{} should be true or false, not {}", name, value.get_type_name()))),
+                },
+
+                "audio.volume" => {
+                    self.volume = get_integer(name, entry, 0, 100).map_err(error)? as u8;
+                },
+
+                "speed.fast_forward_frameskip" => {
+                    self.frameskip = get_integer(name, entry, 0, 60).map_err(error)? as usize;
+                },
+
+                "rewind.seconds" => {
+                    self.rewind_seconds = get_integer(name, entry, 0, 600).map_err(error)? as usize;
+                },
+
+                "rewind.interval" => {
+                    self.rewind_interval = get_integer(name, entry, 1, 60).map_err(error)? as usize;
+                },
+
+                _ => {
+                    let binding = name.strip_prefix("keys.").map(|button| {
+                        BUTTONS.iter().find(|&&(n, _)| n == button).map(|&(_, b)| Binding::Button(b))
+                    }).or_else(|| name.strip_prefix("hotkeys.").map(|hotkey| {
+                        HOTKEYS.iter().find(|h| h.get_name() == hotkey).map(|&h| Binding::Hotkey(h))
+                    }));
+
+                    match binding.flatten() {
+                        Some(binding) => {
+                            let keys = get_keys(name, entry).map_err(error)?;
+                            self.bind(binding, &keys);
+                        },
+
+                        None => return Err(error(format!("unknown setting {}", name))),
+                    }
+                },
+            }
+        }
+
+        // both still work, but it's almost never what was meant
+        for (key, button, hotkey) in self.bindings.get_conflicts() {
+            let button = BUTTONS.iter().find(|&&(_, b)| b == button).map_or("?", |&(n, _)| n);
+            eprintln!("warning: {:?} is bound to both keys.{} and hotkeys.{}", key, button, hotkey.get_name());
+        }
+
+        Ok(())
+    }
+
+    // Replace the keys for a button or hotkey
+    fn bind(&mut self, binding: Binding, keys: &[Key]) {
+        let bindings = &mut self.bindings;
+
+        match binding {
+            Binding::Button(button) => {
+                bindings.buttons.retain(|&(_, b)| b != button);
+                bindings.buttons.extend(keys.iter().map(|&key| (key, button)));
+            },
+
+            Binding::Hotkey(hotkey) => {
+                bindings.hotkeys.retain(|&(_, h)| h != hotkey);
+                bindings.hotkeys.extend(keys.iter().map(|&key| (key, hotkey)));
+            },
+        }
+    }
+
+}
+
+#[derive(Clone, Copy)]
+enum Binding {
+    Button(u8),
+    Hotkey(Hotkey),
+}
+
+fn get_integer(name: &str, entry: &Entry, min: i64, max: i64) -> Result<i64, String> {
+    match entry.value {
+        Value::Integer(n) if n >= min && n <= max => Ok(n),
+        _ => Err(format!("{} should be a whole number from {} to {}", name, min, max)),
+    }
+}
+
+fn get_string<'a>(name: &str, entry: &'a Entry) -> Result<&'a str, String> {
+    match &entry.value {
+        Value::String(s) => Ok(s),
+        value => Err(format!("{} should be a string, not {}", name, value.get_type_name())),
+    }
+}
+
+// A key name or a list of them, an empty list unbinds
+fn get_keys(name: &str, entry: &Entry) -> Result<Vec<Key>, String> {
+    let names = match &entry.value {
+        Value::String(key) => vec![key.clone()],
+        Value::Array(keys) => keys.iter().map(|key| match key {
+            Value::String(key) => Ok(key.clone()),
+            value => Err(format!("{} should list key names, not {}", name, value.get_type_name())),
+        }).collect::<Result<_, _>>()?,
+        value => return Err(format!("{} should be a key name, not {}", name, value.get_type_name())),
+    };
+
+    names.iter()
+        .map(|key| get_key(key).ok_or_else(|| format!("unknown key '{}' for {}", key, name)))
+        .collect()
+}
+
+////////// KEY NAMES //////////
+
+const KEYS: [Key; 106] = [
+    Key::Key0, Key::Key1, Key::Key2, Key::Key3, Key::Key4,
+    Key::Key5, Key::Key6, Key::Key7, Key::Key8, Key::Key9,
+    Key::A, Key::B, Key::C, Key::D, Key::E, Key::F, Key::G, Key::H, Key::I,
+    Key::J, Key::K, Key::L, Key::M, Key::N, Key::O, Key::P, Key::Q, Key::R,
+    Key::S, Key::T, Key::U, Key::V, Key::W, Key::X, Key::Y, Key::Z,
+    Key::F1, Key::F2, Key::F3, Key::F4, Key::F5, Key::F6, Key::F7, Key::F8,
+    Key::F9, Key::F10, Key::F11, Key::F12, Key::F13, Key::F14, Key::F15,
+    Key::Down, Key::Left, Key::Right, Key::Up,
+    Key::Apostrophe, Key::Backquote, Key::Backslash, Key::Comma, Key::Equal,
+    Key::LeftBracket, Key::Minus, Key::Period, Key::RightBracket, Key::Semicolon, Key::Slash,
+    Key::Backspace, Key::Delete, Key::End, Key::Enter, Key::Escape, Key::Home,
+    Key::Insert, Key::Menu, Key::PageDown, Key::PageUp, Key::Pause, Key::Space, Key::Tab,
+    Key::NumLock, Key::CapsLock, Key::ScrollLock,
+    Key::LeftShift, Key::RightShift, Key::LeftCtrl, Key::RightCtrl,
+    Key::NumPad0, Key::NumPad1, Key::NumPad2, Key::NumPad3, Key::NumPad4,
+    Key::NumPad5, Key::NumPad6, Key::NumPad7, Key::NumPad8, Key::NumPad9,
+    Key::NumPadDot, Key::NumPadSlash, Key::NumPadAsterisk,
+    Key::NumPadMinus, Key::NumPadPlus, Key::NumPadEnter,
+    Key::LeftAlt, Key::RightAlt, Key::LeftSuper, Key::RightSuper,
+];
+
+// Key by its minifb name, ignoring case, with 0-9 for the number keys
+pub fn get_key(name: &str) -> Option<Key> {
+    let name = if name.len() == 1 && name.chars().all(|c| c.is_ascii_digit()) {
+        format!("Key{}", name)
+    } else {
+        name.to_string()
+    };
+
+    KEYS.iter().copied().find(|key| format!("{:?}", key).eq_ignore_ascii_case(&name))
+}
+
+////////// CONFIG DIRECTORY //////////
+
+// $XDG_CONFIG_HOME or ~/.config, %APPDATA% on windows
+// and ~/Library/Application Support on macos
+pub fn get_config_dir() -> Option<PathBuf> {
+    let var = |name: &str| env::var_os(name).filter(|v| !v.is_empty()).map(PathBuf::from);
+
+    if cfg!(windows) {
+        var("APPDATA")
+    } else if cfg!(target_os = "macos") {
+        var("HOME").map(|home| home.join("Library").join("Application Support"))
+    } else {
+        var("XDG_CONFIG_HOME").or_else(|| var("HOME").map(|home| home.join(".config")))
+    }
+}
+
+pub fn get_settings_path() -> Option<PathBuf> {
+    get_config_dir().map(|dir| dir.join("samb_gb").join("config.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(text: &str) -> Result<Settings, String> {
+        let mut settings = Settings::init();
+        settings.apply(&toml::parse(text)?)?;
+        Ok(settings)
+    }
+
+    #[test]
+    fn defaults_have_no_conflicts() {
+        assert!(Settings::init().bindings.get_conflicts().is_empty());
+    }
+
+    #[test]
+    fn finds_keys_bound_to_a_button_and_a_hotkey() {
+        let settings = apply("[keys]\na = [\"A\", \"P\"]\n[hotkeys]\nreset = \"A\"\n").unwrap();
+
+        assert_eq!(settings.bindings.get_conflicts(), vec![
+            (Key::A, BUTTON_A, Hotkey::Reset),
+            (Key::P, BUTTON_A, Hotkey::Pause),
+        ]);
+    }
+
+    #[test]
+    fn rebinding_replaces_the_old_keys() {
+        let settings = apply("[keys]\nstart = [\"Enter\", \"Space\"]\n[hotkeys]\nframe_advance = []\n").unwrap();
+
+        let start: Vec<Key> = settings.bindings.buttons.iter()
+            .filter(|&&(_, b)| b == BUTTON_START)
+            .map(|&(key, _)| key)
+            .collect();
+        assert_eq!(start, vec![Key::Enter, Key::Space]);
+
+        assert!(!settings.bindings.hotkeys.iter().any(|&(_, h)| h == Hotkey::FrameAdvance));
+        assert!(settings.bindings.get_conflicts().is_empty());
+    }
+
+    #[test]
+    fn errors_give_the_line() {
+        assert_eq!(apply("[video]\n\nscale = 99\n").unwrap_err(), "line 3: video.scale should be a whole number from 1 to 16");
+        assert_eq!(apply("[keys]\njump = \"A\"\n").unwrap_err(), "line 2: unknown setting keys.jump");
+    }
+}
@@ -0,0 +1,224 @@
+// VRAM inspector window, opened with --vram
+//
+//   1, 2, 3 or tab   tile sheet, tile maps, oam table
+//   p                next palette for the tile sheet
+//   mouse            hover over a tile to see where it is
+
+use crate::gb::hardware::io::gpu::{GPU, Image, ViewPalette, OamEntry, TILES_PER_BANK, TILE_MAP_SIZE};
+use crate::gb::ui::canvas::{Canvas, CHAR_WIDTH, CHAR_HEIGHT};
+
+use minifb::{Key, KeyRepeat, MouseMode, Scale, Window, WindowOptions};
+
+const MARGIN: usize = 8;
+
+// tabs on the first line, then a line for whatever is under the mouse
+const TOP: usize = MARGIN + CHAR_HEIGHT * 2;
+
+// the tile sheet is 384 tiles (24 rows of 16) tall
+const TILE_SCALE: usize = 2;
+const TILE_SHEET_HEIGHT: usize = TILES_PER_BANK / 16 * 8 * TILE_SCALE;
+
+// oam table has two columns of 20 sprites
+const OAM_ROWS: usize = 20;
+const OAM_ROW_HEIGHT: usize = 18;
+const OAM_COLUMN_WIDTH: usize = TILE_MAP_SIZE + MARGIN;
+
+const WIDTH: usize = MARGIN * 3 + TILE_MAP_SIZE * 2;
+const HEIGHT: usize = TOP + TILE_SHEET_HEIGHT + MARGIN;
+
+// Colors
+const BACKGROUND: u32 = 0x1e1e1e;
+const TEXT: u32 = 0xd0d0d0;
+const DIM: u32 = 0x808080;
+const HEADER: u32 = 0xffd060;
+const OUTLINE: u32 = 0x404040;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Tab {
+    Tiles,
+    Maps,
+    Oam,
+}
+
+pub struct VramViewer {
+    window: Window,
+    canvas: Canvas,
+
+    tab: Tab,
+
+    // palette the tile sheet is drawn with
+    palette: ViewPalette,
+}
+
+impl VramViewer {
+
+    pub fn init() -> Self {
+        let options = WindowOptions {
+            scale: Scale::X2,
+            ..WindowOptions::default()
+        };
+
+        Self {
+            window: Window::new("VRAM", WIDTH, HEIGHT, options).unwrap_or_else(|e| {
+                panic!("{}", e);
+            }),
+            canvas: Canvas::init(WIDTH, HEIGHT),
+
+            tab: Tab::Tiles,
+
+            palette: ViewPalette::Grey,
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.window.is_open()
+    }
+
+    // Handle input and redraw, called once a frame
+    pub fn update(&mut self, gpu: &GPU) {
+        self.handle_input(gpu);
+
+        self.canvas.clear(BACKGROUND);
+        self.draw_tabs();
+
+        match self.tab {
+            Tab::Tiles => self.draw_tiles(gpu),
+            Tab::Maps => self.draw_maps(gpu),
+            Tab::Oam => self.draw_oam(gpu),
+        }
+
+        self.window.update_with_buffer(&self.canvas.buffer, WIDTH, HEIGHT).unwrap();
+    }
+
+}
+
+impl VramViewer {
+
+    fn handle_input(&mut self, gpu: &GPU) {
+        let keys = match self.window.get_keys_pressed(KeyRepeat::No) {
+            Some(keys) => keys,
+            None => return,
+        };
+
+        for key in keys {
+            match key {
+                Key::Key1 => self.tab = Tab::Tiles,
+                Key::Key2 => self.tab = Tab::Maps,
+                Key::Key3 => self.tab = Tab::Oam,
+
+                Key::Tab => self.tab = match self.tab {
+                    Tab::Tiles => Tab::Maps,
+                    Tab::Maps => Tab::Oam,
+                    Tab::Oam => Tab::Tiles,
+                },
+
+                Key::P => {
+                    let palettes = ViewPalette::get_all(gpu.is_cgb());
+                    let i = palettes.iter().position(|&p| p == self.palette).unwrap_or(0);
+                    self.palette = palettes[(i + 1) % palettes.len()];
+                },
+
+                _ => {},
+            }
+        }
+    }
+
+    fn draw_tabs(&mut self) {
+        let tabs = [(Tab::Tiles, "1 tiles"), (Tab::Maps, "2 maps"), (Tab::Oam, "3 oam")];
+        let mut x = MARGIN;
+
+        for &(tab, name) in tabs.iter() {
+            let color = if tab == self.tab { HEADER } else { DIM };
+            self.canvas.draw_text(x, MARGIN, name, color);
+            x += (name.len() + 2) * CHAR_WIDTH;
+        }
+    }
+
+    fn draw_tiles(&mut self, gpu: &GPU) {
+        let sheet = gpu.render_tiles(self.palette);
+        draw_image(&mut self.canvas, MARGIN, TOP, &sheet, TILE_SCALE);
+
+        let info = format!("palette {} (p)", self.palette.get_name());
+        self.canvas.draw_text(WIDTH - MARGIN - info.len() * CHAR_WIDTH, MARGIN, &info, TEXT);
+
+        // tile under the mouse
+        let hovered = self.window.get_mouse_pos(MouseMode::Discard).and_then(|(x, y)| {
+            let x = (x as usize).checked_sub(MARGIN)? / TILE_SCALE / 8;
+            let y = (y as usize).checked_sub(TOP)? / TILE_SCALE / 8;
+
+            let (bank, column) = (x / 16, x % 16);
+            let tile = y * 16 + column;
+
+            if x < sheet.width / 8 && tile < TILES_PER_BANK { Some((bank, tile)) } else { None }
+        });
+
+        if let Some((bank, tile)) = hovered {
+            let text = format!("tile {:03X} at {}:{:04X}", tile, bank, 0x8000 + tile * 16);
+            self.canvas.draw_text(MARGIN, MARGIN + CHAR_HEIGHT, &text, TEXT);
+        }
+    }
+
+    fn draw_maps(&mut self, gpu: &GPU) {
+        let (bg_map, win_map) = gpu.get_map_selection();
+        let (scx, scy, wx, wy) = gpu.get_scroll();
+
+        for map in 0..2 {
+            let x = MARGIN + map * (TILE_MAP_SIZE + MARGIN);
+            let image = gpu.render_tile_map(map);
+            draw_image(&mut self.canvas, x, TOP, &image, 1);
+
+            let mut label = format!("{:04X}", if map == 0 { 0x9800 } else { 0x9c00 });
+            if map == bg_map { label.push_str(" bg"); }
+            if map == win_map { label.push_str(" window"); }
+
+            self.canvas.draw_text(x, MARGIN + CHAR_HEIGHT, &label, TEXT);
+        }
+
+        let text = format!("SCX {:02X} SCY {:02X} WX {:02X} WY {:02X}", scx, scy, wx, wy);
+        self.canvas.draw_text(WIDTH - MARGIN - text.len() * CHAR_WIDTH, MARGIN, &text, TEXT);
+    }
+
+    fn draw_oam(&mut self, gpu: &GPU) {
+        let height = gpu.get_sprite_height();
+
+        for sprite in gpu.get_oam_entries() {
+            let x = MARGIN + (sprite.index / OAM_ROWS) * OAM_COLUMN_WIDTH;
+            let y = TOP + (sprite.index % OAM_ROWS) * OAM_ROW_HEIGHT;
+
+            self.canvas.draw_rect(x, y, 10, height as usize + 2, OUTLINE);
+            draw_image(&mut self.canvas, x + 1, y + 1, &gpu.render_sprite(&sprite), 1);
+
+            let color = if sprite.is_visible(height) { TEXT } else { DIM };
+            let text = describe_sprite(&sprite, gpu.is_cgb());
+            self.canvas.draw_text(x + 10 + CHAR_WIDTH, y + (OAM_ROW_HEIGHT - CHAR_HEIGHT) / 2, &text, color);
+        }
+
+        let text = format!("8x{} sprites", height);
+        self.canvas.draw_text(WIDTH - MARGIN - text.len() * CHAR_WIDTH, MARGIN, &text, TEXT);
+    }
+
+}
+
+fn draw_image(canvas: &mut Canvas, x: usize, y: usize, image: &Image, scale: usize) {
+    canvas.draw_image(x, y, &image.pixels, image.width, scale);
+}
+
+// ## x,y tile palette flags, with the position on screen
+fn describe_sprite(sprite: &OamEntry, cgb: bool) -> String {
+    let palette = if cgb {
+        format!("C{} V{}", sprite.get_cgb_palette(), sprite.get_bank())
+    } else {
+        format!("P{}   ", sprite.get_dmg_palette())
+    };
+
+    format!(
+        "{:02} {:4},{:4} T{:02X} {} {}{}{}",
+        sprite.index,
+        sprite.x as i16 - 8, sprite.y as i16 - 16,
+        sprite.tile,
+        palette,
+        if sprite.is_flip_x() { 'X' } else { '-' },
+        if sprite.is_flip_y() { 'Y' } else { '-' },
+        if sprite.is_behind_bg() { 'B' } else { '-' },
+    )
+}
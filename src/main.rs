@@ -1,76 +1,653 @@
 extern crate minifb;
 
-use minifb::*;
-
 pub mod gb;
+mod cli;
+
 pub use crate::gb::cpu::CPU;
-use std::{thread, time};
-use std::fs::File;
+use crate::gb::headless;
+use crate::gb::debugger::Debugger;
+use crate::gb::debugger::gdb::GdbStub;
+use crate::gb::disasm;
+use crate::gb::symbols::Symbols;
+use crate::gb::log;
+use crate::gb::model::Model;
+use crate::gb::movie::{Movie, MoviePlayer, MovieWriter, Start};
+use crate::gb::png;
+use crate::gb::rewind::Rewind;
+use crate::gb::trace::TraceWriter;
+use crate::gb::ui::frontend::Frontend;
+use crate::gb::ui::memory_viewer::MemoryViewer;
+use crate::gb::ui::pacing::{self, FramePacer, SPEEDS, NORMAL_SPEED};
+use crate::gb::ui::settings::{Hotkey, Settings};
+use crate::gb::ui::vram_viewer::VramViewer;
+use crate::gb::hardware::cartridge::Cartridge;
+use crate::gb::hardware::header::{self, Header};
+use crate::gb::hardware::io::gpu::FRAME_CYCLES;
+use crate::gb::hardware::link::printer::Printer;
+use crate::gb::hardware::link::tcp::TcpLink;
+use crate::cli::{Command, LinkOption, Options};
+use std::fs;
+use std::process;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::path::{Path, PathBuf};
 
-const WIDTH: usize = 1024;
-const HEIGHT: usize = 0x80000 / WIDTH;
+// instructions shown by disasm when given a start address
+const DISASM_COUNT: usize = 32;
 
 
-fn main() {
-    let mut file = File::open("./tetris.gb").expect("can't open file");
-    let mut cpu = CPU::init(&mut file);
-
-    let mut buffer: Vec<u32> = vec![0; WIDTH * HEIGHT];
-
-    let mut window = Window::new(
-        "tetris",
-        WIDTH,
-        HEIGHT,
-        WindowOptions::default(),
-    )
-    .unwrap_or_else(|e| {
-        panic!("{}", e);
-    });
+// Read a rom, making sure it at least has a header
+fn read_rom(path: &Path) -> Result<(Vec<u8>, Header), String> {
+    let rom = fs::read(path).map_err(|e| format!("can't open rom {}: {}", path.display(), e))?;
+    let header = Header::parse(&rom).map_err(|e| format!("{}: {}", path.display(), e))?;
 
-    while window.is_open() {
-        if(window.is_key_down(Key::Space)) {
-            cpu.step();
-        } else {
-            for i in 0..6666 {
-                cpu.step();
+    Ok((rom, header))
+}
+
+// samb_gb info <rom>
+fn run_info(options: &Options) -> Result<(), String> {
+    let (rom, header) = read_rom(&options.rom)?;
+
+    let size = |bytes: Option<usize>| match bytes {
+        Some(0) => String::from("none"),
+        Some(bytes) => format!("{} KiB", bytes / 1024),
+        None => String::from("unknown"),
+    };
+
+    let check = |ok: bool| if ok { "ok" } else { "bad" };
+
+    // the new licensee code is used when the old one is 0x33
+    let licensee = if header.old_licensee == 0x33 {
+        format!("{:?}", header.new_licensee)
+    } else {
+        format!("{:02x}", header.old_licensee)
+    };
+
+    let header_sum = header::compute_header_checksum(&rom);
+    let global_sum = header::compute_global_checksum(&rom);
+
+    println!("title          {}", header.title);
+    println!("type           {} ({:02x})", header.get_type_name(), header.cartridge_type);
+    println!("rom size       {} ({:02x}), file is {} KiB", size(header.get_rom_bytes()), header.rom_size, rom.len() / 1024);
+    println!("ram size       {} ({:02x})", size(header.get_ram_bytes()), header.ram_size);
+    println!("gameboy color  {} ({:02x})", header.get_cgb_support(), header.cgb_flag);
+    println!("super gameboy  {} ({:02x})", if header.sgb_flag == 0x03 { "yes" } else { "no" }, header.sgb_flag);
+    println!("destination    {}", if header.destination == 0 { "japan" } else { "overseas" });
+    println!("licensee       {}", licensee);
+    println!("version        {}", header.version);
+    println!("header sum     {:02x} ({})", header.header_checksum, check(header.header_checksum == header_sum));
+    println!("global sum     {:04x} ({})", header.global_checksum, check(header.global_checksum == global_sum));
+
+    Ok(())
+}
+
+// samb_gb test <rom>
+fn run_test(options: &Options) -> Result<(), String> {
+    let mut cpu = load_cpu(options, options.model, load_boot_rom(options)?)?;
+
+    let result = headless::run_test(&mut cpu, headless::TEST_TIMEOUT_CYCLES);
+    println!("{}", result.get_output());
+
+    match result {
+        headless::TestResult::Passed(_) => process::exit(0),
+        headless::TestResult::Failed(_) => process::exit(1),
+        headless::TestResult::Timeout(_) => {
+            println!("Timed out waiting for test result!");
+            process::exit(2)
+        },
+        headless::TestResult::Stopped(hit, _) => {
+            println!("Stopped by a {}!", hit);
+            process::exit(2)
+        },
+    }
+}
+
+// --sym <path> loads an rgbds symbol file, otherwise <rom>.sym is used if there is one
+fn load_symbols(options: &Options) -> Result<Option<Symbols>, String> {
+    let path = match &options.symbols {
+        Some(path) => path.clone(),
+        None => options.rom.with_extension("sym"),
+    };
+
+    if !path.exists() && options.symbols.is_none() {
+        return Ok(None);
+    }
+
+    Symbols::load(&path)
+        .map(Some)
+        .map_err(|e| format!("can't open symbol file {}: {}", path.display(), e))
+}
+
+// samb_gb disasm <rom> [bank:addr [count]]
+fn run_disasm(options: &Options) -> Result<(), String> {
+    let (rom, _) = read_rom(&options.rom)?;
+    let symbols = load_symbols(options)?.unwrap_or_else(Symbols::hardware);
+
+    match &options.start {
+        Some(start) => {
+            let (bank, addr) = disasm::parse_bank_address(start)
+                .ok_or_else(|| format!("expected an address like 02:4a3f, not '{}'", start))?;
+            let count = options.count.unwrap_or(DISASM_COUNT);
+
+            print!("{}", disasm::disassemble_range(&rom, bank, addr, count, &symbols));
+        },
+
+        None => print!("{}", disasm::disassemble_rom(&rom, &symbols)),
+    }
+
+    Ok(())
+}
+
+// --link-listen <port> or --link-connect <port> plugs in a link cable
+// to another emulator running on this machine, --printer <dir> plugs
+// in a gameboy printer that saves its printouts to dir
+fn connect_link(cpu: &mut CPU, options: &Options) -> Result<(), String> {
+    match &options.link {
+        Some(LinkOption::Printer(dir)) => {
+            cpu.bus.serial.connect(Rc::new(RefCell::new(Printer::init(dir.clone()))));
+        },
+
+        Some(LinkOption::Listen(port)) => {
+            println!("Waiting for link cable on port {}...", port);
+            let link = TcpLink::listen(*port).map_err(|e| format!("can't open link cable: {}", e))?;
+            cpu.bus.serial.connect(Rc::new(RefCell::new(link)));
+        },
+
+        Some(LinkOption::Connect(port)) => {
+            let link = TcpLink::connect(*port).map_err(|e| format!("can't open link cable: {}", e))?;
+            cpu.bus.serial.connect(Rc::new(RefCell::new(link)));
+        },
+
+        None => {},
+    }
+
+    Ok(())
+}
+
+// --boot-rom <path> runs a dmg (256 byte) or cgb (2304 byte) boot rom first
+fn load_boot_rom(options: &Options) -> Result<Option<Vec<u8>>, String> {
+    let path = match &options.boot_rom {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+
+    let boot_rom = fs::read(path).map_err(|e| format!("can't open boot rom {}: {}", path.display(), e))?;
+    if boot_rom.len() != 0x100 && boot_rom.len() != 0x900 {
+        return Err(format!("boot rom should be 256 or 2304 bytes, not {}", boot_rom.len()));
+    }
+
+    Ok(Some(boot_rom))
+}
+
+// Power on with the rom from the options
+fn load_cpu(options: &Options, model: Option<Model>, boot_rom: Option<Vec<u8>>) -> Result<CPU, String> {
+    let (rom, _) = read_rom(&options.rom)?;
+
+    Ok(CPU::init_with(Cartridge::from_bytes(&rom), model, boot_rom))
+}
+
+// Battery saves are <rom name>.sav and save states <rom name>.state,
+// next to the rom or in --save-dir
+fn get_save_path(options: &Options, extension: &str) -> PathBuf {
+    let name = options.rom.with_extension(extension);
+
+    match (&options.save_dir, name.file_name()) {
+        (Some(dir), Some(file)) => dir.join(file),
+        _ => name,
+    }
+}
+
+fn load_battery(cpu: &mut CPU, path: &Path) -> Result<(), String> {
+    if !cpu.bus.rom.has_battery() || !path.exists() {
+        return Ok(());
+    }
+
+    let ram = fs::read(path).map_err(|e| format!("can't load save {}: {}", path.display(), e))?;
+    cpu.bus.rom.load_ram(&ram);
+    Ok(())
+}
+
+// No path leaves the battery save alone
+// Save whatever the cartridge and the link port haven't yet on the way out
+fn shut_down(cpu: &mut CPU, save_path: Option<&Path>) -> Result<(), String> {
+    let finished = cpu.bus.serial.finish_device();
+    save_battery(cpu, save_path)?;
+    finished
+}
+
+fn save_battery(cpu: &CPU, path: Option<&Path>) -> Result<(), String> {
+    let path = match path {
+        Some(path) if cpu.bus.rom.has_battery() => path,
+        _ => return Ok(()),
+    };
+
+    create_save_dir(path)?;
+    fs::write(path, cpu.bus.rom.get_ram()).map_err(|e| format!("can't write save {}: {}", path.display(), e))
+}
+
+fn create_save_dir(path: &Path) -> Result<(), String> {
+    match path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        Some(dir) => fs::create_dir_all(dir).map_err(|e| format!("can't create save directory {}: {}", dir.display(), e)),
+        None => Ok(()),
+    }
+}
+
+fn load_state(cpu: &mut CPU, path: &Path) -> Result<(), String> {
+    let state = fs::read(path).map_err(|e| format!("can't open state {}: {}", path.display(), e))?;
+    cpu.load_state(&state).map_err(|e| format!("can't load state {}: {}", path.display(), e))
+}
+
+fn save_state(cpu: &mut CPU, path: &Path) -> Result<(), String> {
+    create_save_dir(path)?;
+    fs::write(path, cpu.save_state()).map_err(|e| format!("can't write state {}: {}", path.display(), e))
+}
+
+// A movie being recorded or played, which is where the buttons come from
+enum MovieMode {
+    Record(MovieWriter),
+    Play(MoviePlayer),
+}
+
+// --play <path> puts the cpu where the movie starts, --record <path> starts
+// a movie from wherever the cpu is
+fn start_movie(cpu: &mut CPU, options: &Options, movie: Option<Movie>, boot_rom: Option<&[u8]>) -> Result<Option<MovieMode>, String> {
+    if let Some(movie) = movie {
+        movie.check(cpu, boot_rom)?;
+        movie.start(cpu)?;
+        return Ok(Some(MovieMode::Play(MoviePlayer::init(movie))));
+    }
+
+    let path = match &options.record {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+
+    let start = match (&options.load_state, boot_rom) {
+        (Some(_), _) => Start::State(cpu.save_state()),
+        (None, Some(boot_rom)) => Start::BootRom(png::crc32(boot_rom)),
+        (None, None) => Start::PowerOn,
+    };
+
+    let writer = MovieWriter::create(path, &Movie::init(cpu, start))
+        .map_err(|e| format!("can't create movie {}: {}", path.display(), e))?;
+
+    println!("Recording movie to {}", path.display());
+    Ok(Some(MovieMode::Record(writer)))
+}
+
+// Buttons for the next frame, `live` unless a movie is playing
+fn next_buttons(movie: &mut Option<MovieMode>, live: u8) -> Result<u8, String> {
+    match movie {
+        Some(MovieMode::Play(player)) => {
+            if let Some(buttons) = player.next_frame() {
+                return Ok(buttons);
             }
+
+            println!("Movie finished after {} frames", player.get_length());
+            *movie = None;
+            Ok(live)
+        },
+
+        Some(MovieMode::Record(writer)) => {
+            writer.write_frame(live).map_err(|e| format!("can't write movie: {}", e))?;
+            Ok(live)
+        },
+
+        None => Ok(live),
+    }
+}
+
+fn finish_movie(movie: Option<MovieMode>) -> Result<(), String> {
+    if let Some(MovieMode::Record(mut writer)) = movie {
+        writer.flush().map_err(|e| format!("can't write movie: {}", e))?;
+        println!("Recorded {} frames", writer.get_frames());
+    }
+
+    Ok(())
+}
+
+// --trace <path> writes the cpu state before every instruction
+// in the gameboy doctor format, labelled when there are symbols.
+// --doctor stubs ly like gameboy doctor's reference traces
+fn setup_trace(cpu: &mut CPU, options: &Options, symbols: Option<Symbols>) -> Result<(), String> {
+    cpu.bus.stub_ly = options.doctor;
+
+    if let Some(path) = &options.trace {
+        let mut trace = TraceWriter::create(path)
+            .map_err(|e| format!("can't create trace file {}: {}", path.display(), e))?;
+
+        if let Some(symbols) = symbols {
+            trace.set_symbols(symbols);
         }
 
-        for i in 0x0000..=0xffff {
-            let byte = cpu.bus.read_byte(i);
-            let index = i as usize * 8;
+        cpu.trace = Some(Rc::new(RefCell::new(trace)));
+    }
 
-            let mut black : u32 = 0x00000000;
-            let mut white : u32 = 0xffffffff;
+    Ok(())
+}
 
-            if i == cpu.reg.pc {
-                black = 0x00ff0000;
-                white = 0xff00ff00;
-            }
+// Run up to the next vblank. Only the debuggers set breakpoints,
+// so one being hit here is an error
+fn run_frame(cpu: &mut CPU) -> Result<usize, String> {
+    cpu.run_until_vblank().map_err(|hit| format!("stopped by a {}", hit))
+}
+
+// samb_gb [run] <rom>
+fn run(options: &Options) -> Result<(), String> {
+    let settings = Settings::load(options.config.as_deref())?;
+    let symbols = load_symbols(options)?;
+
+    // a movie picks the model, and starts without the battery save and never writes it
+    let movie = match &options.play {
+        Some(path) => Some(Movie::load(path)?),
+        None => None,
+    };
+
+    let save_path = if movie.is_some() || options.record.is_some() {
+        None
+    } else {
+        Some(get_save_path(options, "sav"))
+    };
+
+    let model = options.model.or_else(|| movie.as_ref().map(|movie| movie.model));
+    let boot_rom = load_boot_rom(options)?;
+
+    // reset goes back to the cpu as it was before any saves or states were loaded
+    let mut cpu = load_cpu(options, model, boot_rom.clone())?;
+    let power_on = cpu.save_state();
+
+    cpu.bus.gpu.set_dmg_colors(options.palette.unwrap_or(settings.palette));
+    cpu.bus.sound.set_enabled(options.audio.unwrap_or(settings.audio));
+    cpu.bus.sound.set_volume(settings.volume);
+
+    if let Some(path) = &save_path {
+        load_battery(&mut cpu, path)?;
+    }
+
+    if let Some(path) = &options.load_state {
+        load_state(&mut cpu, path)?;
+    }
+
+    let mut movie = start_movie(&mut cpu, options, movie, boot_rom.as_deref())?;
+
+    connect_link(&mut cpu, options)?;
+    setup_trace(&mut cpu, options, symbols.clone())?;
+
+    // --debug drops into the command line debugger instead
+    if options.debug {
+        let mut debugger = Debugger::init(cpu);
+        debugger.symbols = symbols.unwrap_or_else(Symbols::hardware);
+        debugger.run();
+        return shut_down(&mut debugger.cpu, save_path.as_deref());
+    }
+
+    // --gdb <port> waits for a gdb remote protocol client instead
+    if let Some(port) = options.gdb {
+        println!("Waiting for gdb on port {}...", port);
+        let mut stub = GdbStub::listen(cpu, port).map_err(|e| format!("can't listen for gdb: {}", e))?;
+        if let Err(e) = stub.run() {
+            println!("gdb connection lost: {}", e);
+        }
+        return shut_down(&mut stub.cpu, save_path.as_deref());
+    }
+
+    let screenshot_scale = options.screenshot_scale.unwrap_or(settings.screenshot_scale);
 
-            if i == cpu.reg.sp {
-                black = 0x0000ff00;
-                white = 0xffff00ff;
+    // --frames <n> runs without a window, and so does --screenshot-at-frame <n> <path>,
+    // stopping at the screenshot if there's no --frames. Frames end at vblank like
+    // in the window, so frame n is the nth picture the lcd finished
+    let frames = options.frames.or_else(|| options.screenshot.as_ref().map(|(frame, _)| *frame));
+    if let Some(frames) = frames {
+        for frame in 1..=frames {
+            let buttons = next_buttons(&mut movie, 0)?;
+            cpu.bus.joypad.set_buttons(buttons);
+            run_frame(&mut cpu)?;
+
+            if let Some((_, path)) = options.screenshot.as_ref().filter(|(at, _)| *at == frame) {
+                cpu.save_screenshot(path, screenshot_scale)
+                    .map_err(|e| format!("can't write screenshot {}: {}", path.display(), e))?;
             }
+        }
+
+        finish_movie(movie)?;
+        return shut_down(&mut cpu, save_path.as_deref());
+    }
+
+    run_window(cpu, power_on, options, settings, symbols, movie, save_path.as_deref())
+}
+
+// Play in a window until it's closed
+fn run_window(mut cpu: CPU, power_on: Vec<u8>, options: &Options, settings: Settings, symbols: Option<Symbols>, mut movie: Option<MovieMode>, save_path: Option<&Path>) -> Result<(), String> {
+    let title = match cpu.bus.rom.get_header().title {
+        title if title.is_empty() => String::from("samb_gb"),
+        title => format!("{} - samb_gb", title),
+    };
+
+    let (_, width, height) = cpu.bus.get_screen();
+    let scale = options.scale.unwrap_or(settings.scale);
+    let mut frontend = Frontend::init(&title, width, height, scale, settings.bindings)?;
+    let mut pacer = FramePacer::init();
+    let screenshot_scale = options.screenshot_scale.unwrap_or(settings.screenshot_scale);
+
+    // --memory and --vram open the memory viewer and vram inspector alongside
+    let mut memory = if options.memory { Some(MemoryViewer::init()) } else { None };
+    let mut vram = if options.vram { Some(VramViewer::init()) } else { None };
+
+    if let Some(viewer) = &mut memory {
+        viewer.symbols = symbols.unwrap_or_else(Symbols::hardware);
+    }
+
+    // save states are kept in memory and also written to <rom name>.state,
+    // which is loaded if nothing has been saved since starting
+    let mut state: Option<Vec<u8>> = None;
+    let state_path = get_save_path(options, "state");
+
+    let mut paused = false;
+    let mut advance = false;
+    let mut shown_title = title.clone();
+
+    let mut rewind = if settings.rewind_seconds > 0 {
+        let mut rewind = Rewind::init(settings.rewind_seconds, settings.rewind_interval);
+        rewind.push(cpu.save_state());
+        Some(rewind)
+    } else {
+        None
+    };
+
+    while frontend.is_open() {
+        // going back in time would leave the movie out of step with the game
+        let locked = movie.is_some();
+
+        for hotkey in frontend.get_hotkeys() {
+            match hotkey {
+                Hotkey::SaveState => {
+                    state = Some(cpu.save_state());
+                    match save_state(&mut cpu, &state_path) {
+                        Ok(()) => println!("Saved state to {}", state_path.display()),
+                        Err(e) => eprintln!("error: {}", e),
+                    }
+                },
+
+                Hotkey::LoadState if locked => println!("Can't load a state while a movie is running"),
+                Hotkey::LoadState => {
+                    let loaded = match &state {
+                        Some(state) => cpu.load_state(state).map_err(|e| format!("can't load state: {}", e)),
+                        None => load_state(&mut cpu, &state_path),
+                    };
+
+                    match loaded {
+                        // the frames before were played from somewhere else
+                        Ok(()) => if let Some(rewind) = &mut rewind {
+                            rewind.push(cpu.save_state());
+                        },
+                        Err(e) => eprintln!("error: {}", e),
+                    }
+                },
+
+                Hotkey::SpeedDown | Hotkey::SpeedUp => {
+                    let i = SPEEDS.iter().position(|&speed| speed == pacer.get_speed()).unwrap_or(0);
+                    let i = if hotkey == Hotkey::SpeedUp { (i + 1).min(SPEEDS.len() - 1) } else { i.saturating_sub(1) };
+                    pacer.set_speed(SPEEDS[i]);
+                },
+
+                Hotkey::Pause => paused = !paused,
+
+                // pauses first, then steps a frame at a time
+                Hotkey::FrameAdvance => if paused { advance = true } else { paused = true },
+
+                Hotkey::Reset if locked => println!("Can't reset while a movie is running"),
 
-            if i == cpu.reg.get_hl() {
-                black = 0x000000ff;
-                white = 0xffffff00;
+                // the cartridge keeps its ram through a reset
+                Hotkey::Reset => {
+                    let ram = cpu.bus.rom.get_ram().to_vec();
+                    if let Err(e) = cpu.load_state(&power_on) {
+                        eprintln!("error: can't reset: {}", e);
+                    }
+                    cpu.bus.rom.load_ram(&ram);
+
+                    if let Some(rewind) = &mut rewind {
+                        rewind.push(cpu.save_state());
+                    }
+                },
+
+                Hotkey::Screenshot => match save_screenshot(&cpu, options, screenshot_scale) {
+                    Ok(path) => println!("Saved screenshot to {}", path.display()),
+                    Err(e) => eprintln!("error: {}", e),
+                },
+
+                Hotkey::FastForward | Hotkey::Rewind => {},
             }
+        }
+
+        let status = get_title(&title, paused, pacer.get_speed());
+        if status != shown_title {
+            frontend.set_title(&status);
+            shown_title = status;
+        }
+
+        // fast forward runs as fast as it can while held, skipping frames
+        let fast_forward = !paused && frontend.is_hotkey_down(Hotkey::FastForward);
+        let frames = match (paused, advance, fast_forward) {
+            (true, false, _) => 0,
+            (true, true, _) => 1,
+            (false, _, true) => 1 + settings.frameskip,
+            (false, _, false) => 1,
+        };
+        advance = false;
+
+        // holding rewind steps back through the states, stopping at the oldest
+        let rewinding = rewind.is_some() && !locked && frontend.is_hotkey_down(Hotkey::Rewind);
+
+        let mut cycles = 0;
+        if rewinding {
+            if let Some((state, inputs)) = rewind.as_mut().and_then(Rewind::pop) {
+                if let Err(e) = cpu.load_state(&state) {
+                    eprintln!("error: can't rewind: {}", e);
+                }
+
+                // catch up to the frame before from the last state
+                for buttons in inputs {
+                    cpu.bus.joypad.set_buttons(buttons);
+                    run_frame(&mut cpu)?;
+                }
 
-            for b in 0..8 {
-                let bit_mask = (1 << b) as u8;
+                cycles = FRAME_CYCLES;
+            }
+        } else {
+            // each frame runs up to the lcd's vblank so a whole picture is presented
+            for _ in 0..frames {
+                let buttons = next_buttons(&mut movie, frontend.get_buttons())?;
+                cpu.bus.joypad.set_buttons(buttons);
+                cycles += run_frame(&mut cpu)?;
 
-                if (byte & bit_mask) != 0 {
-                    buffer[(index + b) as usize] = white;
-                } else {
-                    buffer[(index + b) as usize] = black;
+                if let Some(rewind) = &mut rewind {
+                    if rewind.tick(buttons) {
+                        rewind.push(cpu.save_state());
+                    }
                 }
             }
         }
-        
-        window
-            .update_with_buffer(&buffer, WIDTH, HEIGHT)
-            .unwrap();
+
+        let (screen, width, height) = cpu.bus.get_screen();
+        frontend.present(screen, width, height);
+
+        if let Some(viewer) = memory.as_mut().filter(|viewer| viewer.is_open()) {
+            viewer.update(&mut cpu.bus);
+        }
+
+        if let Some(viewer) = vram.as_mut().filter(|viewer| viewer.is_open()) {
+            viewer.update(&cpu.bus.gpu);
+        }
+
+        if fast_forward && !rewinding {
+            pacer.reset();
+        } else if cycles == 0 {
+            pacer.idle();
+        } else {
+            pacer.wait(cycles);
+        }
+    }
+
+    finish_movie(movie)?;
+    shut_down(&mut cpu, save_path)
+}
+
+// Window title, with the speed when it isn't 1x
+fn get_title(title: &str, paused: bool, speed: u32) -> String {
+    match (paused, speed) {
+        (true, _) => format!("{} (paused)", title),
+        (false, NORMAL_SPEED) => title.to_string(),
+        (false, speed) => format!("{} ({})", title, pacing::get_speed_name(speed)),
+    }
+}
+
+// Screenshots are <rom name>-<n>.png, next to the rom or in --save-dir
+fn save_screenshot(cpu: &CPU, options: &Options, scale: usize) -> Result<PathBuf, String> {
+    let stem = options.rom.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+    let dir = match &options.save_dir {
+        Some(dir) => dir.clone(),
+        None => options.rom.parent().map(Path::to_path_buf).unwrap_or_default(),
+    };
+
+    let path = (1..).map(|n| dir.join(format!("{}-{}.png", stem, n)))
+        .find(|path| !path.exists())
+        .unwrap();
+
+    cpu.save_screenshot(&path, scale)
+        .map_err(|e| format!("can't write screenshot {}: {}", path.display(), e))?;
+
+    Ok(path)
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    let result = cli::parse(&args).and_then(|options| {
+        // --log <filter> turns on diagnostics, like --log cpu=debug,bus=warn
+        if let Some(filter) = &options.log {
+            log::set_filter(filter)?;
+        }
+
+        match options.command {
+            Command::Run => run(&options),
+            Command::Disasm => run_disasm(&options),
+            Command::Info => run_info(&options),
+            Command::Test => run_test(&options),
+
+            Command::Help => {
+                print!("{}", cli::USAGE);
+                Ok(())
+            },
+
+            Command::Version => {
+                println!("samb_gb {}", env!("CARGO_PKG_VERSION"));
+                Ok(())
+            },
+        }
+    });
+
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        process::exit(1);
     }
 }